@@ -1,162 +1,352 @@
 use crate::commands::*;
-use crate::parser::parse_expr;
+use crate::parser::SourceLine;
+use crate::lexer::words;
+use crate::ast::{Line, operation, is_instruction, directive_value, directive_values, directive_symbol};
 use crate::objwriter::*;
-use std::fs::File;
+use crate::objreader::{ObjSection, ObjModification};
+use crate::symbols::{Section, section_scope};
+use crate::expressions::Scope;
+use crate::diagnostics::{Diagnostic, at_token, line_error, locate, line_has_error};
 use std::collections::HashMap;
 
-pub fn generate_obj(program: &Vec<String>, symtab: &HashMap<String, i32>, len: usize) -> Result<String, String> {
+
+// control section with its defined and referred symbols, text and modification records are added while it's assembled
+fn section_start(section: &Section) -> ObjSection {
+    // exported labels are defined too, absolute constants aren't addresses that loader could relocate
+    let exported = section.exports.iter().filter(|name| !section.extdef.contains(name) && !section.absolute.contains(*name));
+    // undefined symbols were reported in first pass
+    let defs: Vec<(String, usize)> = section.extdef.iter().chain(exported)
+                                                    .filter_map(|def| section.symtab.get(def).map(|addr| (def.to_owned(), *addr as usize)))
+                                                    .collect();
+    ObjSection{name: section.name.to_owned(), start: section.start, length: section.length, defs: defs, refs: section.extref.clone(),
+               texts: Vec::new(), mods: Vec::new(), entry: section.entry}
+}
+
+
+// text record of machine code at start, code is written in hex and object keeps its bytes
+fn write_text(obj_section: &mut ObjSection, start: usize, code: &str) {
+    let bytes = (0..code.len()).step_by(2).map(|i| u8::from_str_radix(&code[i..i+2], 16).unwrap()).collect();
+    obj_section.texts.push((start, bytes));
+}
+
+
+// remaining text record and modification records of control section
+fn write_section_end(obj_section: &mut ObjSection, text_record: &String, text_loc: usize, mod_records: &Vec<ObjModification>) {
+    if text_record.len() > 0 {
+        write_text(obj_section, text_loc, text_record);
+    }
+
+    // modification records come after all text records
+    obj_section.mods.extend(mod_records.iter().cloned());
+}
+
+
+// add machine code to text record, while text record is larger than the limit, write it to obj file
+// this is used to split long char arrays into multiple lines
+fn push_text_record(obj_section: &mut ObjSection, text_record: &mut String, text_loc: &mut usize, code: &str) {
+    text_record.push_str(code);
+    if text_record.len() < 60 {
+        return;
+    }
+    // long code like FILL is split into full records, only the rest is kept for next record
+    let mut start = 0;
+    while text_record.len() - start >= 60 {
+        write_text(obj_section, *text_loc, &text_record[start..start+60]);
+        start += 60;
+        *text_loc += 30;
+    }
+    *text_record = text_record[start..].to_owned();
+}
+
+
+// symbols of section together with literals of current pool, so literals can be looked up like labels
+fn pool_symtab(section: &Section, pool: usize) -> HashMap<String, i32> {
+    let mut symtab = section.symtab.clone();
+    for lit in section.literals.iter().filter(|lit| lit.pool == pool) {
+        symtab.insert(lit.name.to_owned(), lit.address as i32);
+    }
+    symtab
+}
+
+
+// write literals of pool to text record and listing, returns size of pool
+fn write_literals(obj_section: &mut ObjSection, lst_file: &mut String, text_record: &mut String, text_loc: &mut usize, section: &Section, pool: usize) -> usize {
+    let mut size = 0;
+    for lit in section.literals.iter().filter(|lit| lit.pool == pool) {
+        write_lst_instr(lst_file, lit.address, lit.code.clone(), &vec!["*", &lit.name], false);
+        push_text_record(obj_section, text_record, text_loc, &lit.code);
+        size += lit.code.len() / 2;
+    }
+    size
+}
+
+
+/*
+    second pass, returns object code and listing
+    like in first pass, lines with errors are reported to diagnostics and skipped
+    lines that already have an error from first pass aren't checked again
+*/
+pub fn generate_obj(program: &Vec<SourceLine>, lines: &Vec<Option<Line>>, sections: &Vec<Section>, diagnostics: &mut Vec<Diagnostic>) -> Result<(Vec<ObjSection>, String), Diagnostic> {
 
     // find start line by skipping leading comments and empty lines
     let mut st_index = 0;
-    while program[st_index].starts_with(".") || program[st_index] == "" { st_index += 1;}
+    while program[st_index].text.starts_with(".") || program[st_index].text == "" { st_index += 1;}
 
-    let start: Vec<&str> = program[st_index].split(" ").collect();
-    let name;
-    if start[0].len() <= 6 {
-        name = format!("{:<6}", start[0]);
-    }
-    else {
-        return Err(format!("Name must be at most 6 characters wide, {} is {}", start[0], start[0].len()));
+    // start line was already checked in first pass
+    let start = lines[st_index].as_ref().unwrap();
+    let name = start.label.as_ref().unwrap();
+    if name.text.len() > 6 {
+        return Err(locate(at_token(name, format!("Name must be at most 6 characters wide, {} is {}", name.text, name.text.len())), &program[st_index]));
     }
-    // output file used to create listing file
-    let mut lst_file = File::create(format!("./{}.lst", name.trim())).unwrap();
-    write_lst_instr(&mut lst_file, 0, "".to_owned(), &start);
+    // listing, written to file by caller
+    let mut lst_file = String::new();
+    let start_words = words(&start.tokens);
+    write_lst_instr(&mut lst_file, sections[0].start, "".to_owned(), &start_words.iter().map(|word| word.as_str()).collect(), false);
 
     // modification records are added here by get_machine_code function
-    let mut mod_records: Vec<String> = Vec::new();
+    let mut mod_records: Vec<ObjModification> = Vec::new();
 
     // current text record string
     let mut current_text_record = String::new();
+
+    // index of current control section
+    let mut sect = 0;
+
+    let mut loc_counter: usize = sections[sect].start;
     // txt_loc is used to store correct memory location in text record
-    let mut text_loc = 0;
-    
-    let mut loc_counter: usize = start[2].parse::<usize>().unwrap();
+    let mut text_loc = loc_counter;
     let mut prev_loc = loc_counter;
-    let mut line_count = 1;
-
-    // output file for object code
-    let mut obj_file = File::create(format!("./{}.obj", name.trim())).unwrap();
-    write_obj_header(&mut obj_file, name, loc_counter, len);
 
-    // for end entry in obj, default is same as start
-    let mut end_addr: i32 = loc_counter as i32;
+    // object code, written to file by caller
+    let mut object: Vec<ObjSection> = Vec::new();
+    let mut obj_section = section_start(&sections[sect]);
 
     // -1 base means we don't have BASE in use
     let mut base: isize = -1;
 
-    for line in program[st_index+1..].iter() {
-        line_count += 1;
+    // literal pool that is currently being filled, instructions see only its literals
+    let mut pool = 0;
+    let mut lit_symtab = pool_symtab(&sections[sect], pool);
+
+    // current program block and where each block of section continues
+    let mut block = 0;
+    let mut block_loc: Vec<usize> = sections[sect].blocks.iter().map(|blk| blk.start).collect();
+
+    // listing notes every change of source file, so included lines can be told apart
+    let mut current_file = program[st_index].file.to_owned();
+
+    for (src, line_ast) in program.iter().zip(lines.iter()).skip(st_index + 1) {
+        // locations are from source, so they stay correct after INCLUDE and macro expansion
+        let line = &src.text;
+        if src.file != current_file {
+            // lines of included file, or lines after it
+            write_lst_file(&mut lst_file, &src.file);
+            current_file = src.file.to_owned();
+        }
+        if src.skipped {
+            // line in IF branch that isn't assembled
+            write_lst_skipped(&mut lst_file, line);
+            continue;
+        }
         if line == "" || line.starts_with(".") {
             // empty line or comment
-            write_lst_comment(&mut lst_file, &line);
+            write_lst_comment(&mut lst_file, line);
             continue;
         }
+        // line with error from parsing or first pass, or line after END
+        let line_ast = match line_ast {
+                            Some(line_ast) if !line_has_error(diagnostics, src) => line_ast,
+                            _ => continue
+                       };
+        let op = operation(line_ast);
+        let operands = &line_ast.operands;
+
+        // words of line as they were written, for lst file
+        let line_words = words(&line_ast.tokens);
+        let mut split: Vec<&str> = line_words.iter().map(|word| word.as_str()).collect();
 
-        let mut split: Vec<&str> = line.split(" ").collect();
+        let symtab = &sections[sect].symtab;
+        // directives see symbols of section, instructions also see literals of current pool
+        let scope = section_scope(&sections[sect], loc_counter);
+        let instr_scope = Scope{symtab: &lit_symtab, absolute: &sections[sect].absolute, extref: &sections[sect].extref, loc: loc_counter};
 
-        let mut machine_code: Result<String, String> = Ok("--42".to_owned());
+        let mut machine_code: Result<String, Diagnostic> = Ok("--42".to_owned());
         let mut org_flag = false;
 
-        let mut instr_size: Result<usize, String> = Ok(0);
-        if !is_instr(split[0]) && !is_directive(split[0]) {
+        let mut instr_size: Result<usize, Diagnostic> = Ok(0);
+        // warnings of instruction get location of line once it's assembled
+        let mut warnings: Vec<Diagnostic> = Vec::new();
+        if let Some(label) = &line_ast.label {
             // 1st word is label
 
-            if !symtab.contains_key(split[0]) {
-                return Err(format!("Line {}, label {} doesn't appear as left label.", line_count, split[0]));
+            if op == "CSECT" {
+                // close current section with its remaining literals and continue with the next one
+                write_literals(&mut obj_section, &mut lst_file, &mut current_text_record, &mut text_loc, &sections[sect], pool);
+                write_section_end(&mut obj_section, &current_text_record, text_loc, &mod_records);
+                object.push(obj_section);
+                current_text_record = String::new();
+                mod_records = Vec::new();
+
+                sect += 1;
+                loc_counter = 0;
+                text_loc = 0;
+                prev_loc = 0;
+                base = -1;
+                pool = 0;
+                lit_symtab = pool_symtab(&sections[sect], pool);
+                block = 0;
+                block_loc = sections[sect].blocks.iter().map(|blk| blk.start).collect();
+                obj_section = section_start(&sections[sect]);
+
+                write_lst_instr(&mut lst_file, loc_counter, "".to_owned(), &split, src.expanded);
+                continue;
+            }
+
+            if op == "SET" {
+                // SET symbols were already filled in by conditional assembly
+                write_lst_instr(&mut lst_file, loc_counter, "".to_owned(), &split, src.expanded);
+                continue;
+            }
+
+            if !symtab.contains_key(&label.text) {
+                diagnostics.push(locate(at_token(label, format!("label {} doesn't appear as left label.", label.text)), src));
+                continue;
             }
 
-            if is_instr(split[1]) {
+            if is_instruction(line_ast) {
                 // label for instr
-                instr_size = determine_command_size(split[1]);
-                machine_code = get_machine_code(&split[1..].to_vec(), &symtab, &mut mod_records, loc_counter, base);
+                instr_size = determine_command_size(op);
+                machine_code = get_machine_code(op, operands, &instr_scope, &mut mod_records, base, &mut warnings);
             }
-            else if is_directive(split[1]) {
-                // label for directive
-                if split[1] == "EQU" {
-                    write_lst_instr(&mut lst_file, loc_counter, "".to_owned(), &split);
-                    continue;
-                }
-                instr_size = determine_res_size(split[1], &split[2..].to_vec());
-                machine_code =  handle_res(split[1], &split[2..].to_vec());
+            else if op == "EQU" {
+                write_lst_instr(&mut lst_file, loc_counter, "".to_owned(), &split, src.expanded);
+                continue;
             }
-            else if split[1].starts_with(".") {
+            else if op == "" {
                 // label + comment is still valid... kind of
-                write_lst_comment(&mut lst_file, &line);
+                write_lst_comment(&mut lst_file, line);
                 continue;
             }
             else {
-                return Err(format!("Line {}, not a valid format.", line_count));
+                // label for directive
+                match directive_values(line_ast) {
+                    Ok(values) => { instr_size = determine_res_size(op, &values, &scope);
+                                   machine_code =  handle_res(op, &values, &scope, &mut mod_records, &mut warnings); },
+                    Err(diag) => { diagnostics.push(locate(diag, src));
+                                   continue; }
+                }
             }
         }
-        else if is_instr(split[0]) {
+        else if is_instruction(line_ast) {
             // 1st word is an instruction
-            instr_size = determine_command_size(split[0]);
-            machine_code = get_machine_code(&split[0..].to_vec(), &symtab, &mut mod_records, loc_counter, base);
+            instr_size = determine_command_size(op);
+            machine_code = get_machine_code(op, operands, &instr_scope, &mut mod_records, base, &mut warnings);
 
             // for lst file
             split.insert(0," ")
         }
-        else if is_directive(split[0]){
+        else if is_directive(op) {
             // 1st word is a directive
-            match split[0]{
-                "EQU" => return Err(format!("Line {}, can't use EQU without label", line_count)),
-                "END" => if symtab.contains_key(&*split[1]) {
-                            end_addr = symtab[&*split[1]];
-                        },
-                "ORG" => instr_size = match parse_expr(split[1]) {
-                                        Ok(value) => if value >= 0 {
-                                                        org_flag = true;
-                                                        machine_code = Ok("".to_owned());
-
-                                                        Ok(value as usize)
-                                                     } 
-                                                     else { 
-                                                        return Err(format!("Line {}, ORG needs non-negative number", line_count))
-                                                     },
-                                        Err(msg) => Err(msg.to_owned())
-                                      },
-                "BASE"   => base = match parse_expr(split[1]) {
-                                        Ok(value) => if value >= 0 && value < i32::pow(2, 24) {
-                                                        value as isize
-                                                     } 
-                                                     else { 
-                                                        return Err(format!("Line {}, BASE needs number in interval [0, 16777216]", line_count))
+            match op {
+                "EQU" => { diagnostics.push(line_error(src, op, "can't use EQU without label".to_owned()));
+                           continue; },
+                // address of first instruction was already resolved in first pass, lines after END aren't parsed
+                "END" => (),
+                // symbols were collected in first pass, here they are just listed
+                "EXTDEF" | "EXTREF" | "EXPORTS" => machine_code = Ok("".to_owned()),
+                // new location is found the same way as in first pass, but here locations are addresses
+                "ORG" => match directive_value(line_ast).and_then(|value| determine_org(value, &scope, 0)) {
+                            Ok(new_loc) => { org_flag = true;
+                                             machine_code = Ok("".to_owned());
+                                             loc_counter = new_loc; },
+                            Err(diag) => { diagnostics.push(locate(diag, src));
+                                           continue; }
+                         },
+                // padding is left empty, the same as RESB
+                "ALIGN" => match directive_value(line_ast).and_then(|value| determine_align(value, &scope, loc_counter - sections[sect].blocks[block].start)) {
+                            Ok(padding) => { instr_size = Ok(padding);
+                                             machine_code = Ok(if padding > 0 { "RESB" } else { "" }.to_owned()); },
+                            Err(diag) => { diagnostics.push(locate(diag, src));
+                                           continue; }
+                           },
+                "BASE"   => base = match directive_value(line_ast) {
+                                        Ok(value) => match determine_base(value, &scope) {
+                                                        Ok(value) => value,
+                                                        Err(diag) => { diagnostics.push(locate(diag, src));
+                                                                       continue; }
                                                      },
-                                        Err(msg) => return Err(msg.to_owned())
+                                        Err(diag) => { diagnostics.push(locate(diag, src));
+                                                       continue; }
                                     },
                 "NOBASE" => base = -1,
-                _     => {instr_size = determine_res_size(split[0], &split[1..].to_vec());
-                          machine_code = handle_res(split[0], &split[1..].to_vec());}
+                "IF" | "ELSE" | "ENDIF" => machine_code = Ok("".to_owned()),
+                "LTORG" => { // literals are listed right after LTORG
+                             split.insert(0, " ");
+                             write_lst_instr(&mut lst_file, loc_counter, "".to_owned(), &split, src.expanded);
+
+                             loc_counter += write_literals(&mut obj_section, &mut lst_file, &mut current_text_record, &mut text_loc, &sections[sect], pool);
+                             prev_loc = loc_counter;
+
+                             pool += 1;
+                             lit_symtab = pool_symtab(&sections[sect], pool);
+                             continue; },
+                "USE" => { // code of other block is placed elsewhere, so text record must be written before switching
+                           if current_text_record.len() > 0 {
+                               write_text(&mut obj_section, text_loc, &current_text_record);
+                               current_text_record = String::new();
+                           }
+                           block_loc[block] = loc_counter;
+
+                           // name of block was checked in first pass
+                           let name = match directive_symbol(line_ast) {
+                                            Ok(Some(name)) => name.text.as_str(),
+                                            _ => ""
+                                      };
+                           block = sections[sect].blocks.iter().position(|blk| blk.name == name).unwrap();
+                           loc_counter = block_loc[block];
+                           text_loc = loc_counter;
+                           prev_loc = loc_counter;
+
+                           split.insert(0, " ");
+                           write_lst_instr(&mut lst_file, loc_counter, "".to_owned(), &split, src.expanded);
+                           continue; },
+                _     => match directive_values(line_ast) {
+                            Ok(values) => { instr_size = determine_res_size(op, &values, &scope);
+                                           machine_code = handle_res(op, &values, &scope, &mut mod_records, &mut warnings); },
+                            Err(diag) => { diagnostics.push(locate(diag, src));
+                                           continue; }
+                         }
             }
             // for lst file
             split.insert(0," ")
         }
         else {
             // invalid
-            return Err(format!("Line {}, Not a valid instruction", line_count));
+            diagnostics.push(line_error(src, op, "Not a valid instruction".to_owned()));
+            continue;
         }
 
         match instr_size {
             Ok(size) => loc_counter += size,
-            Err(msg) => return Err(format!("{}, line {}", line_count, msg))
+            Err(diag) => { diagnostics.push(locate(diag, src));
+                           continue; }
         }
 
         match machine_code {
             Ok(value) => if value != "--42" {
-                            if value == "RESW" || value == "RESB" || org_flag {
-                                write_lst_instr(&mut lst_file, prev_loc, "".to_owned(), &split);
+                            if value == "RESW" || value == "RESB" || value == "RESF" || org_flag {
+                                write_lst_instr(&mut lst_file, prev_loc, "".to_owned(), &split, src.expanded);
 
                                 // if there is anything in text record to be written we must write it before we make empty space for reservation
                                 if current_text_record.len() > 0 {
-                                    // size / 2 because txt record is in nibbles
-                                    write_obj_text(&mut obj_file, text_loc, current_text_record.len() / 2, current_text_record.clone());
+                                    write_text(&mut obj_section, text_loc, &current_text_record);
                                     // adjust text record memory location
                                     text_loc += current_text_record.len() / 2;
 
                                     current_text_record = String::new();
                                 }
                                 // if directive was for reservation we adjust memory location to make that "reservation space"
-                                if value == "RESW" || value == "RESB" {
+                                if value == "RESW" || value == "RESB" || value == "RESF" {
                                     text_loc += loc_counter - prev_loc;
                                 }
                                 // org, we need to set text loc to loc counter which contains new org address
@@ -165,34 +355,31 @@ pub fn generate_obj(program: &Vec<String>, symtab: &HashMap<String, i32>, len: u
                                 }
                             }
                             else {
-                                write_lst_instr(&mut lst_file, prev_loc, value.clone(), &split);
+                                write_lst_instr(&mut lst_file, prev_loc, value.clone(), &split, src.expanded);
 
                                 // machine code is added to text record
-                                current_text_record.push_str(&value);
-                                // while text record is larger than the limit, write it to obj file
-                                // this is used to split long char arrays into multiple lines
-                                while current_text_record.len() >= 60 {
-                                    write_obj_text(&mut obj_file, text_loc, 30, (&current_text_record[..60]).to_owned());
-                                    current_text_record = (&current_text_record[60..]).to_owned();
-                                    text_loc += 30;
-                                }
+                                push_text_record(&mut obj_section, &mut current_text_record, &mut text_loc, &value);
                             }
                          },
-            Err(msg) => return Err(format!("{}, line {}", line_count, msg))
+            Err(diag) => diagnostics.push(locate(diag, src))
+        }
+        for warn in warnings {
+            diagnostics.push(locate(warn, src));
         }
 
         prev_loc = loc_counter;
     }
-    if current_text_record.len() > 0 {
-        // at the end we still need to write obj file, since it might have not been written if it's too short
-        write_obj_text(&mut obj_file, text_loc, current_text_record.len() / 2, current_text_record);
-    }
+    // literals that weren't placed by LTORG are placed at the end of program
+    write_literals(&mut obj_section, &mut lst_file, &mut current_text_record, &mut text_loc, &sections[sect], pool);
+
+    // at the end we still need to write obj file, since text record might have not been written if it's too short
+    write_section_end(&mut obj_section, &current_text_record, text_loc, &mod_records);
+    object.push(obj_section);
 
-    // write modification record after all text records
-    for mod_rec in mod_records {
-        write_obj_mod(&mut obj_file, mod_rec)
+    // block table is listed for sections that use program blocks
+    for section in sections.iter().filter(|sect| sect.blocks.len() > 1) {
+        write_lst_blocks(&mut lst_file, &section.name, &section.blocks);
     }
-    write_obj_end(&mut obj_file, end_addr as usize);
 
-    Ok("OK".to_owned())
+    Ok((object, lst_file))
 }
\ No newline at end of file
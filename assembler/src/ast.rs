@@ -0,0 +1,252 @@
+use crate::lexer::{Token, TokenKind, tokenize, tokens_text, line_tokens};
+use crate::commands::{determine_format, mnemonic_names};
+use crate::parser::SourceLine;
+use crate::diagnostics::{Diagnostic, token_error, at_token, with_hint, with_suggestion, closest_word, locate};
+
+
+// operand of instruction or directive, tokens are without prefix and index register
+#[derive(Clone, Debug)]
+pub enum Operand {
+    // #value
+    Immediate(Vec<Token>),
+    // @address
+    Indirect(Vec<Token>),
+    // address,X
+    Indexed(Vec<Token>),
+    // address or value without prefix, operands of directives are always simple
+    Simple(Vec<Token>),
+    // =C'EOF', =X'05' or =42
+    Literal(Vec<Token>),
+    // register of format 2 instruction, ie. A in CLEAR A
+    Register(Token)
+}
+
+
+/*
+    line of program parsed once, both passes and warnings work on the same lines
+
+    LOOP +LDA BUF,X . comment
+    label is LOOP, operation +LDA, operands are indexed BUF and comment is . comment
+*/
+#[derive(Clone, Debug)]
+pub struct Line {
+    pub label: Option<Token>,
+    // instruction or directive, None for empty line, comment or label followed only by comment
+    pub operation: Option<Token>,
+    pub operands: Vec<Operand>,
+    pub comment: Option<Token>,
+    // columns of line without comment, first column is 1 and end isn't included
+    pub span: (usize, usize),
+    // every token in order, used for listing
+    pub tokens: Vec<Token>
+}
+
+
+// mnemonic or directive of line, empty when there is none
+pub fn operation(line: &Line) -> &str {
+    match &line.operation {
+        Some(op) => &op.text,
+        None => ""
+    }
+}
+
+
+// line has instruction, FLOAT with value is directive even though it has the same name as instruction
+pub fn is_instruction(line: &Line) -> bool {
+    matches!(&line.operation, Some(op) if op.kind == TokenKind::Mnemonic)
+}
+
+
+// tokens of operand without prefix
+pub fn operand_tokens(operand: &Operand) -> &[Token] {
+    match operand {
+        Operand::Immediate(tokens) | Operand::Indirect(tokens) | Operand::Indexed(tokens) | Operand::Simple(tokens) | Operand::Literal(tokens) => tokens,
+        Operand::Register(reg) => std::slice::from_ref(reg)
+    }
+}
+
+
+// first token of operand, used to point at it in messages
+pub fn operand_token(operand: &Operand) -> &Token {
+    &operand_tokens(operand)[0]
+}
+
+
+// name of literal, ie. =C'EOF', literal is looked up by it in both passes
+pub fn literal_name(tokens: &[Token]) -> String {
+    format!("={}", tokens_text(tokens))
+}
+
+
+// single value of directive, ie. 42 in WORD 42
+pub fn directive_value(line: &Line) -> Result<&[Token], Diagnostic> {
+    let op = operation(line);
+    match line.operands.as_slice() {
+        [] => Err(token_error(op, format!("{} needs a value", op))),
+        [Operand::Simple(tokens)] => Ok(tokens),
+        [Operand::Simple(_), extra, ..] => Err(at_token(operand_token(extra), format!("{} takes only one value", op))),
+        [operand, ..] => Err(at_token(operand_token(operand), format!("{} value can't have addressing prefix", op)))
+    }
+}
+
+
+// comma separated values of directive, ie. 1, 2 and BUF+6 in WORD 1, 2, BUF+6
+pub fn directive_values(line: &Line) -> Result<Vec<&[Token]>, Diagnostic> {
+    let op = operation(line);
+    if line.operands.len() == 0 {
+        return Err(token_error(op, format!("{} needs a value", op)));
+    }
+    let mut values: Vec<&[Token]> = Vec::new();
+    for operand in line.operands.iter() {
+        match operand {
+            Operand::Simple(tokens) => values.push(tokens),
+            _ => return Err(at_token(operand_token(operand), format!("{} value can't have addressing prefix", op)))
+        }
+    }
+    Ok(values)
+}
+
+
+// symbol of directive that takes name, ie. first instruction in END FIRST
+pub fn directive_symbol(line: &Line) -> Result<Option<&Token>, Diagnostic> {
+    match directive_value(line) {
+        Ok([symbol]) if symbol.kind == TokenKind::Symbol || symbol.kind == TokenKind::Register => Ok(Some(symbol)),
+        Ok(tokens) => Err(at_token(&tokens[0], format!("{} needs name of symbol", operation(line)))),
+        Err(_) if line.operands.len() == 0 => Ok(None),
+        Err(diag) => Err(diag)
+    }
+}
+
+
+// operand from tokens between commas
+fn parse_operand(tokens: &[Token], format: usize) -> Result<Operand, Diagnostic> {
+    if format == 2 && tokens.len() == 1 && tokens[0].kind == TokenKind::Register {
+        return Ok(Operand::Register(tokens[0].clone()));
+    }
+
+    let value = tokens[1..].to_vec();
+    let operand = match tokens[0].kind {
+                        TokenKind::Prefix('#') => Operand::Immediate(value),
+                        TokenKind::Prefix('@') => Operand::Indirect(value),
+                        TokenKind::Prefix('=') => Operand::Literal(value),
+                        _ => return Ok(Operand::Simple(tokens.to_vec()))
+                  };
+    if tokens.len() == 1 {
+        return Err(at_token(&tokens[0], format!("{} needs value after it", tokens[0].text)));
+    }
+    Ok(operand)
+}
+
+
+// parse single line of program
+pub fn parse_line(text: &str) -> Result<Line, Diagnostic> {
+    let tokens = match tokenize(text) {
+                    Ok(tokens) => tokens,
+                    Err(diag) => return Err(diag)
+                 };
+
+    let comment = tokens.iter().find(|tok| tok.kind == TokenKind::Comment).cloned();
+    let code: Vec<Token> = tokens.iter().filter(|tok| tok.kind != TokenKind::Comment).cloned().collect();
+    let span = match (code.first(), code.last()) {
+                    (Some(first), Some(last)) => (first.column, last.column + last.text.chars().count()),
+                    _ => (1, 1)
+               };
+
+    let mut rest = code.iter().peekable();
+    let label = rest.next_if(|tok| tok.kind == TokenKind::Label).cloned();
+    let operation = match rest.next() {
+                        Some(op) if op.kind == TokenKind::Mnemonic || op.kind == TokenKind::Directive => Some(op.clone()),
+                        // first word close to instruction is misspelled instruction rather than label, ie. LDAA BUF
+                        Some(_) if label.as_ref().is_some_and(|label| closest_word(&label.text, &mnemonic_names()).is_some()) => {
+                                      let label = label.unwrap();
+                                      return Err(with_suggestion(at_token(&label, format!("{} is not an instruction or directive", label.text)), &mnemonic_names())); },
+                        Some(op) => { let diag = at_token(op, format!("{} is not an instruction or directive", op.text));
+                                      let diag = if label.is_some() { with_hint(diag, "line is <label> <instruction> <operands>") } else { diag };
+                                      return Err(with_suggestion(diag, &mnemonic_names())); },
+                        // label alone is most likely misspelled instruction, label followed by comment is still valid... kind of
+                        None => match &label {
+                                    Some(label) if comment.is_none() =>
+                                        return Err(with_suggestion(at_token(label, format!("{} is not an instruction or directive", label.text)), &mnemonic_names())),
+                                    _ => None
+                                }
+                    };
+
+    // format decides how operands are read, registers are only operands of format 2
+    let format = match &operation {
+                    Some(op) if op.kind == TokenKind::Mnemonic => determine_format(&op.text).unwrap_or(3),
+                    _ => 0
+                 };
+
+    // operands are separated by commas
+    let mut operands: Vec<Operand> = Vec::new();
+    let rest: Vec<Token> = rest.cloned().collect();
+    if rest.len() > 0 {
+        let mut start = 0;
+        for i in 0..=rest.len() {
+            if i < rest.len() && rest[i].kind != TokenKind::Comma {
+                continue;
+            }
+            if i == start {
+                // comma at start, two commas in a row or comma at the end
+                let comma = if i < rest.len() { &rest[i] } else { &rest[i-1] };
+                return Err(at_token(comma, "missing operand next to comma".to_owned()));
+            }
+            match parse_operand(&rest[start..i], format) {
+                Ok(operand) => operands.push(operand),
+                Err(diag) => return Err(diag)
+            }
+            start = i + 1;
+        }
+    }
+
+    // X after last comma of format 3 and 4 is index register, ie. BUF,X
+    let indexed = (format == 3 || format == 4) && operands.len() > 1 &&
+                  matches!(operands.last(), Some(Operand::Simple(tokens)) if tokens.len() == 1 && tokens[0].text == "X");
+    if indexed {
+        operands.pop();
+        let operand = operands.pop().unwrap();
+        match operand {
+            Operand::Simple(tokens) => operands.push(Operand::Indexed(tokens)),
+            _ => return Err(at_token(operand_token(&operand), "Indexed not allowed here".to_owned()))
+        }
+    }
+
+    Ok(Line{label: label, operation: operation, operands: operands, comment: comment, span: span, tokens: tokens})
+}
+
+
+// line that can't be parsed keeps only its label, so label is still defined and lines that use it aren't reported too
+fn label_only(text: &str) -> Option<Line> {
+    // lexer may stop at problem later in line, label in front of it is still read
+    match line_tokens(text).0.first() {
+        Some(label) if label.kind == TokenKind::Label =>
+            Some(Line{label: Some(label.clone()), operation: None, operands: Vec::new(), comment: None,
+                      span: (label.column, label.column + label.text.chars().count()), tokens: vec![label.clone()]}),
+        _ => None
+    }
+}
+
+
+/*
+    parse every line of program, None is given for lines that aren't assembled and lines with errors that have no label
+    lines after END aren't part of program, so they aren't parsed
+*/
+pub fn parse_program(program: &Vec<SourceLine>, diagnostics: &mut Vec<Diagnostic>) -> Vec<Option<Line>> {
+    let mut lines: Vec<Option<Line>> = Vec::new();
+    let mut ended = false;
+    for src in program.iter() {
+        if src.skipped || ended {
+            lines.push(None);
+            continue;
+        }
+        match parse_line(&src.text) {
+            Ok(line) => { ended = operation(&line) == "END";
+                          lines.push(Some(line)); },
+            // word that error is about isn't label, ie. misspelled instruction in LDAA BUF
+            Err(diag) => { let line = label_only(&src.text).filter(|line| !matches!((&line.label, diag.span), (Some(label), Some((start, _))) if label.column == start));
+                           diagnostics.push(locate(diag, src));
+                           lines.push(line); }
+        }
+    }
+    lines
+}
@@ -1,5 +1,10 @@
 use crate::parser::*;
-use std::collections::HashMap;
+use crate::lexer::{Token, TokenKind, tokens_text};
+use crate::ast::{Operand, operand_tokens, operand_token, literal_name};
+use crate::simulator::{float_to_bytes, float_from_bytes};
+use crate::expressions::{Value, ValueKind, Scope, eval_expr, undefined_symbol};
+use crate::objreader::ObjModification;
+use crate::diagnostics::{Diagnostic, WarningKind, error, token_error, at_token, with_hint, with_suggestion, warning};
 
 
 const OPCODES: [(&str, u8); 59] = [("ADD", 0x18),
@@ -62,7 +67,9 @@ const OPCODES: [(&str, u8); 59] = [("ADD", 0x18),
                                     ("TIXR", 0xB8),
                                     ("WD", 0xDC)];
 
-const DIRECTIVES: [&str; 11] = ["START", "END", "BYTE", "WORD", "RESB", "RESW", "EXPORTS", "BASE", "NOBASE", "ORG", "EQU"];
+// FLOAT is also instruction, it's directive when it has value
+const DIRECTIVES: [&str; 26] = ["START", "END", "BYTE", "WORD", "RESB", "RESW", "FLOAT", "RESF", "FILL", "ALIGN", "ASCIZ", "ASCIC", "EXPORTS", "BASE", "NOBASE", "ORG", "EQU", "CSECT", "EXTDEF", "EXTREF", "LTORG", "USE",
+                                "IF", "ELSE", "ENDIF", "SET"];
 
 const FORMAT1: [&str; 6] = ["FIX", "FLOAT", "HIO", "NORM", "SIO", "TIO"];
 const FORMAT2: [&str; 11] = ["ADDR", "CLEAR", "COMPR", "DIVR", "MULR", "RMO", "SHIFTL", "SHIFTR", "SUBR", "TIXR", "SVC"];
@@ -80,13 +87,25 @@ pub fn is_instr(mnem: &str) -> bool {
 }
 
 
+// find mnemonic of instruction from its opcode, last 2 bits of opcode byte are n and i bits so they are ignored
+pub fn find_mnemonic(opcode: u8) -> Option<&'static str> {
+    OPCODES.iter().find(|(_, code)| *code == opcode & 0xFC).map(|(m, _)| *m)
+}
+
+
+// every instruction and directive, used to suggest what misspelled word was meant to be
+pub fn mnemonic_names() -> Vec<&'static str> {
+    OPCODES.iter().map(|(m, _)| *m).chain(DIRECTIVES.iter().copied().filter(|dir| !is_instr(dir))).collect()
+}
+
+
 // check if given word is a directive
 pub fn is_directive(dir: &str) -> bool {
     DIRECTIVES.contains(&dir)
 }
 
 
-pub fn determine_format(mnem: &str) -> Result<usize, String> {
+pub fn determine_format(mnem: &str) -> Result<usize, Diagnostic> {
     if FORMAT1.contains(&mnem) {
         return Ok(1);
     }
@@ -96,74 +115,267 @@ pub fn determine_format(mnem: &str) -> Result<usize, String> {
     else if mnem.starts_with("+") {
         return Ok(4);
     }
-    else if is_instr(&mnem) {
+    else if is_instr(mnem) {
         return Ok(3);
     }
-    Err("Not a valid format".to_owned())
+    Err(with_suggestion(token_error(mnem, format!("{} is not an instruction", mnem)), &mnemonic_names()))
 }
 
 
-pub fn determine_command_size(mnem: &str) -> Result<usize, String> {
+pub fn determine_command_size(mnem: &str) -> Result<usize, Diagnostic> {
     // instruction size is equal to format
-    determine_format(&mnem)
+    determine_format(mnem)
 }
 
 
 // determine size of reservation
-pub fn determine_res_size(dir: &str, value: &Vec<&str>) -> Result<usize, String> {
+pub fn determine_res_size(dir: &str, values: &Vec<&[Token]>, scope: &Scope) -> Result<usize, Diagnostic> {
     /*
         BYTE and WORD can be either 1 number or HEX/CHAR array
-        BYTE is occupies 1byte and WORD occupies 3 bytes
+        BYTE is occupies 1byte, WORD occupies 3 bytes and FLOAT 6 bytes
         when using hex/char arrays, length is max{<type_size>, <len_of_init>}
+        BYTE and WORD can also have list of values, ie. WORD 1, 2, BUF+6, where each value is sized on its own
+        FILL count, value takes count bytes
     */
-    // join value in case char array contains spaces
-    let init_str = value.join(" ");
+    if values.len() == 0 {
+        return Err(token_error(dir, format!("{} needs a value", dir)));
+    }
+    if dir == "BYTE" || dir == "WORD" {
+        let type_size = if dir == "BYTE" { 1 } else { 3 };
+        let mut size = 0;
+        for value in values.iter() {
+            match init_size(value, type_size, scope) {
+                Ok(value_size) => size += value_size,
+                Err(diag) => return Err(diag)
+            }
+        }
+        return Ok(size);
+    }
+    if dir == "FILL" {
+        if values.len() > 2 {
+            return Err(with_hint(at_token(&values[2][0], "FILL takes only count and value".to_owned()), "Use: FILL <count>, <value>. Example: FILL 100, 0xFF"));
+        }
+        return absolute_count(values[0], scope, "Fill count");
+    }
+    if values.len() > 1 {
+        return Err(at_token(&values[1][0], format!("{} takes only one value", dir)));
+    }
+
+    let value = values[0];
     match dir {
-        "BYTE" => parse_init_size(&init_str.clone(), 1),
-        "RESB" => parse_res_size(&value[0], 1),
-        "WORD" => parse_init_size(&init_str.clone(), 3),
-        "RESW" => parse_res_size(&value[0], 3),
-        _ => Err("Invalid reservation".to_owned())
+        "RESB" => reservation_size(value, 1, scope),
+        "RESW" => reservation_size(value, 3, scope),
+        "FLOAT" => match parse_float(value) {
+                        Ok(_) => Ok(6),
+                        Err(diag) => Err(diag)
+                   },
+        "RESF" => reservation_size(value, 6, scope),
+        "ASCIZ" | "ASCIC" => match string_bytes(dir, value) {
+                                Ok(bytes) => Ok(bytes.len()),
+                                Err(diag) => Err(diag)
+                             },
+        _ => Err(token_error(dir, "Invalid reservation".to_owned()))
+    }
+}
+
+
+// size of RESB or RESW, count can be expression of absolute symbols, ie. RESB LEN+1
+fn reservation_size(value: &[Token], type_size: usize, scope: &Scope) -> Result<usize, Diagnostic> {
+    let count_str = tokens_text(value);
+    match eval_expr(value, scope) {
+        Ok(count) if count.kind == ValueKind::Relative || count.refs.len() > 0 =>
+            Err(with_hint(token_error(&count_str, format!("Reservation size {} must be absolute", count_str)),
+                          "address changes when program is loaded elsewhere, so it can't be size of reservation")),
+        Ok(count) if count.value < 1 => Err(token_error(&count_str, "Reservations need to be greater than 0".to_owned())),
+        Ok(count) => Ok(type_size * count.value as usize),
+        Err(diag) => Err(diag)
+    }
+}
+
+
+/*
+    bytes of string directive, ASCIZ is followed by zero byte and ASCIC starts with its length, ie. ASCIZ C'HELLO\n'
+    string is only char constant, so escapes are the same as in C'...'
+*/
+fn string_bytes(dir: &str, value: &[Token]) -> Result<Vec<u8>, Diagnostic> {
+    let mut bytes = match value {
+                        [string] if string.kind == TokenKind::Char => match char_bytes(&string.text) {
+                                                                        Ok(bytes) => bytes,
+                                                                        Err(diag) => return Err(diag)
+                                                                      },
+                        _ => return Err(with_hint(token_error(&tokens_text(value), format!("{} needs char constant", dir)),
+                                                  &format!("Use: {} C'<char val>'. Example: {} C'HELLO'", dir, dir)))
+                    };
+    if dir == "ASCIZ" {
+        bytes.push(0);
+    }
+    else {
+        // length has only one byte
+        if bytes.len() > 255 {
+            return Err(at_token(&value[0], format!("ASCIC string has {} characters, but its length byte can hold at most 255", bytes.len())));
+        }
+        bytes.insert(0, bytes.len() as u8);
+    }
+    Ok(bytes)
+}
+
+
+// count of FILL, DUP or ALIGN, it must be absolute and known in first pass, ie. FILL LEN, 0
+fn absolute_count(value: &[Token], scope: &Scope, name: &str) -> Result<usize, Diagnostic> {
+    let value_str = tokens_text(value);
+    match eval_expr(value, scope) {
+        Ok(count) if count.kind == ValueKind::Relative || count.refs.len() > 0 =>
+            Err(with_hint(token_error(&value_str, format!("{} {} must be absolute", name, value_str)),
+                          "address changes when program is loaded elsewhere, so it can't be used as count")),
+        Ok(count) if count.value < 1 => Err(token_error(&value_str, format!("{} must be greater than 0", name))),
+        Ok(count) => Ok(count.value as usize),
+        Err(diag) => Err(diag)
+    }
+}
+
+
+/*
+    padding added by ALIGN, location is moved to next multiple of given number, ie. ALIGN 4
+    location is counted from start of block, since that's the only location both passes know
+*/
+pub fn determine_align(value: &[Token], scope: &Scope, loc: usize) -> Result<usize, Diagnostic> {
+    match absolute_count(value, scope, "Alignment") {
+        Ok(align) => Ok((align - loc % align) % align),
+        Err(diag) => Err(diag)
+    }
+}
+
+
+// value in parentheses, ie. (BUF) of 4 DUP (BUF), parentheses that don't enclose whole value are kept
+fn strip_parens(value: &[Token]) -> &[Token] {
+    if value.len() < 2 || value[0].kind != TokenKind::Operator('(') || value[value.len()-1].kind != TokenKind::Operator(')') {
+        return value;
+    }
+    let mut depth = 0;
+    for (i, tok) in value.iter().enumerate() {
+        match tok.kind {
+            TokenKind::Operator('(') => depth += 1,
+            TokenKind::Operator(')') => depth -= 1,
+            _ => ()
+        }
+        // parenthesis closed before end, ie. (A)+(B)
+        if depth == 0 && i < value.len() - 1 {
+            return value;
+        }
+    }
+    &value[1..value.len()-1]
+}
+
+
+// BYTE or WORD value that is repeated, ie. 10 DUP 0, gives count, DUP and repeated value
+fn split_dup(value: &[Token]) -> Option<(&[Token], &Token, &[Token])> {
+    match value.iter().position(|tok| tok.kind == TokenKind::Symbol && tok.text == "DUP") {
+        Some(pos) if pos > 0 => Some((&value[..pos], &value[pos], strip_parens(&value[pos+1..]))),
+        _ => None
+    }
+}
+
+
+// size of single BYTE or WORD value, repeated value takes its size count times
+fn init_size(value: &[Token], type_size: usize, scope: &Scope) -> Result<usize, Diagnostic> {
+    match split_dup(value) {
+        Some((_, dup, repeated)) if repeated.len() == 0 => Err(with_hint(at_token(dup, "DUP needs value to repeat".to_owned()), "Use: <count> DUP <value>. Example: WORD 10 DUP 0")),
+        Some((count, _, repeated)) => match (absolute_count(count, scope, "Repeat count"), parse_init_size(repeated, type_size)) {
+                                            (Ok(count), Ok(size)) => Ok(count * size),
+                                            (Err(diag), _) | (_, Err(diag)) => Err(diag)
+                                      },
+        None => parse_init_size(value, type_size)
+    }
+}
+
+
+/*
+    new location counter set by ORG, both passes must agree on it
+    relative value is location inside program, absolute value is address, origin is address of location 0 in pass that asks
+*/
+pub fn determine_org(value: &[Token], scope: &Scope, origin: usize) -> Result<usize, Diagnostic> {
+    let value_str = tokens_text(value);
+    match eval_expr(value, scope) {
+        Ok(new_loc) if new_loc.refs.len() > 0 => Err(token_error(&value_str, "ORG can't use external reference".to_owned())),
+        Ok(new_loc) if new_loc.kind == ValueKind::Relative && new_loc.value >= 0 => Ok(new_loc.value as usize),
+        Ok(new_loc) if new_loc.kind == ValueKind::Absolute && new_loc.value >= origin as i32 => Ok(new_loc.value as usize - origin),
+        Ok(_) => Err(token_error(&value_str, "ORG can't move location before start of section".to_owned())),
+        Err(diag) => Err(diag)
+    }
+}
+
+
+// value assembler can assume for base register
+pub fn determine_base(value: &[Token], scope: &Scope) -> Result<isize, Diagnostic> {
+    let value_str = tokens_text(value);
+    match eval_expr(value, scope) {
+        Ok(base) if base.refs.len() > 0 => Err(token_error(&value_str, "BASE can't use external reference".to_owned())),
+        Ok(base) if base.value >= 0 && base.value < i32::pow(2, 24) => Ok(base.value as isize),
+        Ok(_) => Err(token_error(&value_str, "BASE needs number in interval [0, 16777216]".to_owned())),
+        Err(diag) => Err(diag)
+    }
+}
+
+
+// literal is placed in literal pool, its address is looked up like a label
+fn literal_address(name: &str, scope: &Scope) -> Result<Value, Diagnostic> {
+    match scope.symtab.get(name) {
+        Some(address) => Ok(Value{value: *address, kind: ValueKind::Relative, refs: Vec::new()}),
+        None => Err(undefined_symbol(name, scope.symtab, scope.extref))
+    }
+}
+
+
+// register operand of format 2, anything that isn't register is reported by parse_reg
+fn operand_register(operand: &Operand) -> Result<u8, Diagnostic> {
+    parse_reg(&tokens_text(operand_tokens(operand)))
+}
+
+
+// number operand of format 2, ie. count of SHIFTL
+fn operand_number(operand: &Operand) -> Result<i32, Diagnostic> {
+    match operand {
+        Operand::Simple(tokens) if tokens.len() == 1 => match tokens[0].kind {
+                                                            TokenKind::Number(value) => Ok(value),
+                                                            _ => Err(at_token(&tokens[0], format!("Failed parsing number {}", tokens[0].text)))
+                                                        },
+        _ => Err(at_token(operand_token(operand), format!("Failed parsing number {}", tokens_text(operand_tokens(operand)))))
     }
 }
 
 
 // handle instructions with format 2
-fn handle_format_2(operands: &Vec<&str>, mnem: &str, opcode: u8) -> Result<String, String> {
+fn handle_format_2(operands: &Vec<Operand>, mnem: &str, opcode: u8) -> Result<String, Diagnostic> {
     // instruction takes 2 operands
     if TWO_OP.contains(&mnem) {
-        // since there can be space after comma: either r1,r2 or r1, r2, we join them to r1,r2
-        let val_join;
-        match operands[0].ends_with(",") {
-            false => val_join = operands[0].to_owned(),
-            true => val_join = format!("{}{}", operands[0], operands[1]),
+        if operands.len() < 2 {
+            return Err(with_hint(token_error(mnem, format!("{} needs two operands", mnem)), &format!("{} r1,r2", mnem)));
         }
-        let val_split: Vec<&str> = val_join.split(",").map(|val| val.trim()).collect();
 
         let mut code_build = format!("{:02X}", opcode);
         // first operand is always register
-        match parse_reg(val_split[0]) {
+        match operand_register(&operands[0]) {
             Ok(value) => code_build.push_str(&format!("{:01X}", value)),
-            Err(msg)  => return Err(msg.to_owned())
+            Err(diag) => return Err(diag)
         }
         // second operand in shift is number
         if mnem == "SHIFTL" || mnem == "SHIFTR" {
-            match parse_num(val_split[1]) {
+            match operand_number(&operands[1]) {
                 // interval of shift is in [0, 255]
                 Ok(value) => if value > 0 && value < 16 {
                                 code_build.push_str(&format!("{:01X}", value))
                              }
                              else {
-                                 return Err("Shift value needs to be in interval [0, 16]".to_owned())
+                                return Err(at_token(operand_token(&operands[1]), "Shift value needs to be in interval [0, 16]".to_owned()))
                              },
-                Err(msg)  => return Err(msg.to_owned())
+                Err(diag) => return Err(diag)
             }
         } 
         // if it's not shift operation, second operand is reg
         else {
-            match parse_reg(val_split[1]) {
+            match operand_register(&operands[1]) {
                 Ok(value) => code_build.push_str(&format!("{:01X}", value)),
-                Err(msg)  => return Err(msg.to_owned())
+                Err(diag) => return Err(diag)
             }
         }
 
@@ -173,18 +385,18 @@ fn handle_format_2(operands: &Vec<&str>, mnem: &str, opcode: u8) -> Result<Strin
     else {
         if mnem == "TIXR" || mnem == "CLEAR" {
             // both take register operand
-            let reg = match parse_reg(operands[0]) {
+            let reg = match operand_register(&operands[0]) {
                         Ok(value) => value << 4,
-                        Err(msg) => return Err(msg.to_owned())
+                        Err(diag) => return Err(diag)
                       };
             return Ok(format!("{:02X}{:02X}", opcode, reg));
         }
         // SVC
         else {
             // takes 8bit number
-            let reg = match parse_num(operands[0]) {
+            let reg = match operand_number(&operands[0]) {
                         Ok(value) => value,
-                        Err(msg) => return Err(msg.to_owned())
+                        Err(diag) => return Err(diag)
                       };
             return Ok(format!("{:02X}{:02X}", opcode, reg));
         }
@@ -192,7 +404,7 @@ fn handle_format_2(operands: &Vec<&str>, mnem: &str, opcode: u8) -> Result<Strin
 }
 
 
-fn handle_pc_relative(opcode: u8, pc: usize, address: i32, mut xbpe_offset: i32) -> Result<String, String> {
+fn handle_pc_relative(opcode: u8, pc: usize, address: i32, mut xbpe_offset: i32) -> Result<String, Diagnostic> {
     let offset = address - (pc + 3) as i32;     // pc + 3 since PC actually points to next instr
     // we are limited to 12 bits for offset
     if (offset >= - i32::pow(2, 11)) && (offset <= i32::pow(2, 11) - 1) {
@@ -204,11 +416,11 @@ fn handle_pc_relative(opcode: u8, pc: usize, address: i32, mut xbpe_offset: i32)
         return Ok(format!("{:02X}{:04X}", opcode, xbpe_offset));
     }
     
-    Err("Can't use PC relative".to_owned())
+    Err(error("Can't use PC relative".to_owned()))
 }
 
 
-fn handle_base_relative(opcode: u8, address: i32, mut xbpe_offset: i32, base: isize) -> Result<String, String> {
+fn handle_base_relative(opcode: u8, address: i32, mut xbpe_offset: i32, base: isize) -> Result<String, Diagnostic> {
     if base != -1 {
         let offset = address - base as i32;
         // we are limited to 12 bits for offset, but it needs to be positive
@@ -221,111 +433,132 @@ fn handle_base_relative(opcode: u8, address: i32, mut xbpe_offset: i32, base: is
             return Ok(format!("{:02X}{:04X}", opcode, xbpe_offset));
         }
     }
-    Err("Can't use Base relative".to_owned())
+    Err(error("Can't use Base relative".to_owned()))
+}
+
+
+// field of length half bytes at address, which loader relocates or adds external reference to, ie. +BUFEND
+fn modification(address: usize, length: usize, reference: Option<&str>) -> ObjModification {
+    let symbol = reference.map(|ext| (ext.chars().next().unwrap(), ext[1..].to_owned()));
+    ObjModification{address: address, length: length, symbol: symbol, line: 0}
 }
 
 
-fn handle_direct(opcode: u8, pc: usize, address: i32, mut xbpe_offset: i32, mod_record: &mut Vec<String>) -> Result<String, String> {
+// address of relative value is moved by loader, absolute one stays as it is
+fn handle_direct(opcode: u8, pc: usize, address: i32, mut xbpe_offset: i32, kind: ValueKind, mod_record: &mut Vec<ObjModification>) -> Result<String, Diagnostic> {
     if (address >= 0) && (address <= i32::pow(2, 12) - 1) {
         xbpe_offset |= address;
         // b and p stay 0
 
-        // needs mod record
-        mod_record.push(format!("{:06X}{:02X}", pc + 1, 3));
+        if kind == ValueKind::Relative {
+            // needs mod record
+            mod_record.push(modification(pc + 1, 3, None));
+        }
 
         return Ok(format!("{:02X}{:04X}", opcode, xbpe_offset));
     }
-    Err("Can't use Direct".to_owned())
+    Err(error("Can't use Direct".to_owned()))
 }
 
 
-fn handle_old_sic(opcode: u8, address: i32) -> Result<String, String> {
+fn handle_old_sic(opcode: u8, pc: usize, address: i32, kind: ValueKind, mod_record: &mut Vec<ObjModification>) -> Result<String, Diagnostic> {
     if (address >= 0) && (address <= i32::pow(2, 15) - 1) {
         // odl sic is in interval [0, 32767]
+        if kind == ValueKind::Relative {
+            // 15 bit address with x bit above it is 4 half bytes
+            mod_record.push(modification(pc + 1, 4, None));
+        }
 
         return Ok(format!("{:02X}{:04X}", opcode, address));
     }
-    Err("Can't use SIC".to_owned())
+    Err(error("Can't use SIC".to_owned()))
 }
 
 
-fn handle_format_3(operands: &Vec<&str>, symtab: &HashMap<String, i32>, mod_record: &mut Vec<String>, opcode: u8, pc: usize, base: isize) -> Result<String, String> {
-    let mut xbpe_offset = 0;
-
-    let operand: String;
-    let bits_ni;
-    let mut immediate_value = false;    // when immediate operand is actual number
-    
-    if operands[0].starts_with("#") {
-        // ***** IMMEDIATE *****
+fn too_far(operand: &str) -> Diagnostic {
+    with_hint(token_error(operand, "Offset is too great for any instruction".to_owned()),
+              "set base register with LDB and BASE, or use extended format (+)")
+}
 
-        // indexed not allowed with immediate
-        match parse_indexed(&operands) {
-            Some(_) => return Err("Indexed not allowed here".to_owned()),
-            None => ()
-        };
 
-        operand = operands[0][1..].to_owned();
-        bits_ni = 1;
+fn handle_format_3(operand: &Operand, scope: &Scope, mod_record: &mut Vec<ObjModification>, opcode: u8, base: isize, warnings: &mut Vec<Diagnostic>) -> Result<String, Diagnostic> {
+    let mut xbpe_offset = 0;
+    let pc = scope.loc;
 
-        // operand is immediate number
-        if !symtab.contains_key(&*operand) {
-            immediate_value = true;
+    let operand_str: String;
+    let bits_ni;
+    let value;
+
+    match operand {
+        Operand::Immediate(tokens) => {
+            // ***** IMMEDIATE *****
+            operand_str = tokens_text(tokens);
+            bits_ni = 1;
+            value = eval_expr(tokens, scope);
+        },
+        Operand::Indirect(tokens) => {
+            // ***** INDIRECT *****
+            operand_str = tokens_text(tokens);
+            bits_ni = 2;
+            value = eval_expr(tokens, scope);
+        },
+        Operand::Literal(tokens) => {
+            // ***** LITERAL *****
+            operand_str = literal_name(tokens);
+            bits_ni = 3;
+            value = literal_address(&operand_str, scope);
+        },
+        Operand::Indexed(tokens) => {
+            // label with index -> label, X
+            xbpe_offset |= 1 << 15;   // turn on X bit
+            operand_str = tokens_text(tokens);
+            bits_ni = 3;
+            value = eval_expr(tokens, scope);
+        },
+        _ => {
+            // simple or sic
+            operand_str = tokens_text(operand_tokens(operand));
+            bits_ni = 3;
+            value = eval_expr(operand_tokens(operand), scope);
         }
     }
-    else if operands[0].starts_with("@") {
-        // ***** INDIRECT *****
-
-        // indexed not allowed with indirect
-        match parse_indexed(&operands) {
-            Some(_) => return Err("Indexed not allowed here".to_owned()),
-            None => ()
-        };
 
-        operand = operands[0][1..].to_owned();
-        bits_ni = 2;
+    let value = match value {
+                    Ok(value) => value,
+                    Err(diag) => return Err(diag)
+                };
+    // address of external reference is only known to loader, so it needs all 20 bits of format 4
+    if let Some(ext) = value.refs.first() {
+        return Err(with_hint(token_error(&operand_str, format!("External reference {} needs extended format (+)", &ext[1..])),
+                             "write + in front of mnemonic, ie. +JSUB"));
     }
-    else {
-        // simple or sic
-        
-        // label is either alone or also with index -> label, X
-        let mut label = operands[0].to_string();
-        match parse_indexed(&operands) {
-            Some(x_label) => {label = x_label;
-                              xbpe_offset |= 1 << 15},   // turn on X bit
-            None => ()
-        };
-
-        operand = label;
-        bits_ni = 3;
-    }
-    
-    let operand_val;
-    if immediate_value {
-        match parse_num(&operand) {
-            Ok(value) => operand_val = value,
-            Err(msg) => return Err(msg.to_owned())
-        }
+    let operand_val = value.value;
 
-        // we are limited to 20 bits
+    if bits_ni == 1 && value.kind == ValueKind::Absolute {
+        // immediate constant, we are limited to 12 bits
         if (operand_val >= - i32::pow(2, 11)) && (operand_val <= i32::pow(2, 11) - 1) {
             // if negative clear upper 20 bits
             return Ok(format!("{:02X}{:04X}", opcode | bits_ni, xbpe_offset | operand_val & !(0xFFFFF << 12)));
         }
         else {
-            return Err("Immediate value must be on interval [-2048, 2047]".to_owned());
+            return Err(with_hint(token_error(&operand_str, "Immediate value must be on interval [-2048, 2047]".to_owned()), "use extended format (+) for bigger values"));
         }
     }
-    else {
-        if symtab.contains_key(&*operand) {
-            operand_val = symtab[&*operand];
-        
+    else if value.kind == ValueKind::Absolute {
+        // absolute address is the same wherever program is loaded, so it's used directly
+        match handle_direct(opcode | bits_ni, pc, operand_val, xbpe_offset, value.kind, mod_record) {
+            Ok(code) => return Ok(code),
+            _ => ()
         }
-        // label must exist on left side
-        else {
-            return Err(format!("Symbol {} does not appear as a left label", operand))
+        if bits_ni == 3 {
+            match handle_old_sic(opcode, pc, operand_val | xbpe_offset, value.kind, mod_record) {
+                Ok(code) => return Ok(code),
+                _ => ()
+            }
         }
-
+        return Err(too_far(&operand_str));
+    }
+    else {
         // first try PC-relative
         match handle_pc_relative(opcode | bits_ni, pc, operand_val, xbpe_offset) {
             Ok(code) => return Ok(code),
@@ -336,137 +569,137 @@ fn handle_format_3(operands: &Vec<&str>, symtab: &HashMap<String, i32>, mod_reco
             Ok(code) => return Ok(code),
             _ => ()
         }
-        // then try direct
-        match handle_direct(opcode | bits_ni, pc, operand_val, xbpe_offset, mod_record) {
-            Ok(code) => return Ok(code),
+        // then try direct, it works but program can't be relocated without modification record, so it's worth a warning
+        match handle_direct(opcode | bits_ni, pc, operand_val, xbpe_offset, value.kind, mod_record) {
+            Ok(code) => { warnings.push(with_hint(warning(WarningKind::AddressingFallback, &operand_str,
+                                                          format!("{} is out of PC and base relative range, direct addressing is used", operand_str)),
+                                                  "set base register with LDB and BASE, or use extended format (+)"));
+                          return Ok(code); },
             _ => ()
         }
         if bits_ni == 3 {
             // finally try old SIC
-            match handle_old_sic(opcode, operand_val | xbpe_offset) {
-                Ok(code) => return Ok(code),
-                _ => return Err("Offset is too great for any instruction".to_owned())
+            match handle_old_sic(opcode, pc, operand_val | xbpe_offset, value.kind, mod_record) {
+                Ok(code) => { warnings.push(with_hint(warning(WarningKind::AddressingFallback, &operand_str,
+                                                              format!("{} is out of PC and base relative range, SIC addressing is used", operand_str)),
+                                                      "use extended format (+)"));
+                              return Ok(code); },
+                _ => return Err(too_far(&operand_str))
             }
         }
         else {
-            return Err("Offset is too great for any instruction".to_owned())
+            return Err(too_far(&operand_str))
         }
     }
 }
 
 
-fn handle_format_4(operands: &Vec<&str>, symtab: &HashMap<String, i32>, mod_record: &mut Vec<String>, opcode: u8,  pc: usize) -> Result<String, String> {
+fn handle_format_4(operand: &Operand, scope: &Scope, mod_record: &mut Vec<ObjModification>, opcode: u8) -> Result<String, Diagnostic> {
     // set e bit
     let mut xbpe_offset = 1 << 20;
+    let pc = scope.loc;
 
-    let operand: String;
+    let operand_str: String;
     let bits_ni;
-    let mut immediate_value = false;    // only when immediate operand is actual number
-
-    if operands[0].starts_with("#") {
-        // ***** IMMEDIATE *****
-
-        // indexed not allowed with immediate
-        match parse_indexed(&operands) {
-            Some(_) => return Err("Indexed not allowed here".to_owned()),
-            None => ()
-        };
-
-        operand = operands[0][1..].to_owned();
-        bits_ni = 1;
-
-        // operand is immediate number
-        if !symtab.contains_key(&*operand) {
-            immediate_value = true;
+    let value;
+
+    match operand {
+        Operand::Immediate(tokens) => {
+            // ***** IMMEDIATE *****
+            operand_str = tokens_text(tokens);
+            bits_ni = 1;
+            value = eval_expr(tokens, scope);
+        },
+        Operand::Indirect(tokens) => {
+            // ***** INDIRECT *****
+            operand_str = tokens_text(tokens);
+            bits_ni = 2;
+            value = eval_expr(tokens, scope);
+        },
+        Operand::Literal(tokens) => {
+            // ***** LITERAL *****
+            operand_str = literal_name(tokens);
+            bits_ni = 3;
+            value = literal_address(&operand_str, scope);
+        },
+        Operand::Indexed(tokens) => {
+            // label with index -> label, X
+            xbpe_offset |= 1 << 23;   // turn on X bit
+            operand_str = tokens_text(tokens);
+            bits_ni = 3;
+            value = eval_expr(tokens, scope);
+        },
+        _ => {
+            // ***** SIMPLE *****
+            operand_str = tokens_text(operand_tokens(operand));
+            bits_ni = 3;
+            value = eval_expr(operand_tokens(operand), scope);
         }
     }
-    else if operands[0].starts_with("@") {
-        // ***** INDIRECT *****
 
-        // indexed not allowed with indirect
-        match parse_indexed(&operands) {
-            Some(_) => return Err("Indexed not allowed here".to_owned()),
-            None => ()
-        };
+    let value = match value {
+                    Ok(value) => value,
+                    Err(diag) => return Err(diag)
+                };
+    let operand_val = value.value;
 
-        operand = operands[0][1..].to_owned();
-        bits_ni = 2;
-    }
-    else {
-        // ***** SIMPLE *****
-        
-        // label is either alone or also with index -> label, X
-        let mut label = operands[0].to_string();
-        match parse_indexed(&operands) {
-            Some(x_label) => {label = x_label;
-                              xbpe_offset |= 1 << 23},   // turn on X bit
-            None => ()
-        };
-        
-        operand = label;
-        bits_ni = 0;
-    }
-
-    let operand_val;
-    if immediate_value {
-        // operand is immediate number
-        match parse_num(&operand) {
-            Ok(value) => operand_val = value,
-            Err(msg) => return Err(msg.to_owned())
-        }
-
-        // we are limited to 20 bits
+    if bits_ni == 1 && value.kind == ValueKind::Absolute && value.refs.len() == 0 {
+        // immediate constant, we are limited to 20 bits
         if (operand_val >= - i32::pow(2, 19)) && (operand_val <= i32::pow(2, 19) - 1) {
             // if negative clear upper 12 bits
             return Ok(format!("{:02X}{:06X}", opcode | bits_ni, xbpe_offset | operand_val & !(0xFFF << 20)));
         }
         else {
-            return Err("Immediate value must be on interval [-524288, 524287]".to_owned());
+            return Err(token_error(&operand_str, "Immediate value must be on interval [-524288, 524287]".to_owned()));
         }
     }
     else {
-        if symtab.contains_key(&*operand) {
-            operand_val = symtab[&*operand];
-        
-        }
-        // label must exist on left side
-        else {
-            return Err(format!("Symbol {} does not appear as a left label", operand))
-        }
-
+        // operand is address of label, external reference or expression containing them
         if (operand_val >= 0) && (operand_val <= i32::pow(2, 20) - 1) {
             xbpe_offset |= operand_val;
             // b and p stay 0
 
-            // needs mod record
-            mod_record.push(format!("M{:06X}{:02X}", pc + 1, 5));
+            if value.kind == ValueKind::Relative {
+                // needs mod record
+                mod_record.push(modification(pc + 1, 5, None));
+            }
+            // loader adds address of each external reference to address field
+            for ext in value.refs {
+                mod_record.push(modification(pc + 1, 5, Some(&ext)));
+            }
 
             return Ok(format!("{:02X}{:06X}", opcode | bits_ni, xbpe_offset));
         }
         else {
-            return Err("Invalid extended, must be in range [0, 1048576]".to_owned())
+            return Err(token_error(&operand_str, "Invalid extended, must be in range [0, 1048576]".to_owned()))
         }
     }
 }
 
 
 // get machine code from assembly code
-pub fn get_machine_code(instr: &Vec<&str>, symtab: &HashMap<String, i32>, mod_record: &mut Vec<String>, pc: usize, base: isize) -> Result<String, String> {
-    let mnem = instr[0];
-    let mut clean_mnem = mnem;
-    if mnem.starts_with("+") {
-        clean_mnem = &mnem[1..];
-    }
+// warnings are added for instructions that assemble, but probably not the way they were meant to
+pub fn get_machine_code(mnem: &str, operands: &Vec<Operand>, scope: &Scope, mod_record: &mut Vec<ObjModification>, base: isize, warnings: &mut Vec<Diagnostic>) -> Result<String, Diagnostic> {
+    let clean_mnem = mnem.strip_prefix("+").unwrap_or(mnem);
 
-    let opcode = OPCODES.iter().filter(|(m, _)| *m == clean_mnem).next().unwrap().1;
+    let opcode = OPCODES.iter().find(|(m, _)| *m == clean_mnem).unwrap().1;
     let format = determine_format(mnem).unwrap();
 
+    // everything except format 1 and RSUB needs operand
+    if operands.len() == 0 && format != 1 && clean_mnem != "RSUB" {
+        return Err(token_error(mnem, format!("{} needs operand", mnem)));
+    }
+
     if format == 1 {
         return Ok(format!("{:02X}", opcode));
     }
+    // format 3 and 4 take one operand, index register is part of it
+    if format != 2 && operands.len() > 1 {
+        return Err(at_token(operand_token(&operands[1]), format!("{} takes one operand", mnem)));
+    }
 
     else if format == 2 {
-        return handle_format_2(&instr[1..].to_vec(), &mnem, opcode);
+        return handle_format_2(operands, mnem, opcode);
     }
     else if format == 3 {
         // rsub takes no arguments
@@ -474,63 +707,215 @@ pub fn get_machine_code(instr: &Vec<&str>, symtab: &HashMap<String, i32>, mod_re
             return Ok(format!("{:0<6X}", opcode | 3));
         }
 
-        return handle_format_3(&instr[1..].to_vec(), &symtab, mod_record, opcode, pc, base);
+        return handle_format_3(&operands[0], scope, mod_record, opcode, base, warnings);
     }
     else if format == 4 {
-        return handle_format_4(&instr[1..].to_vec(), &symtab, mod_record, opcode, pc);
+        // +RSUB has no address either, only e bit is set
+        if clean_mnem == "RSUB" {
+            return Ok(format!("{:02X}100000", opcode | 3));
+        }
+
+        return handle_format_4(&operands[0], scope, mod_record, opcode);
     }
     else {
-        return Err("Not a valid format".to_owned());
+        return Err(token_error(mnem, "Not a valid format".to_owned()));
     }
 }
 
 
-pub fn handle_res(dir: &str, value: &Vec<&str>) -> Result<String, String> {
+// machine code of literal, number takes a word, while hex and char literals take as many bytes as they have
+// literal is given without = in front of it, ie. C'EOF' of =C'EOF'
+pub fn handle_literal(literal: &[Token]) -> Result<String, Diagnostic> {
+    match parse_init(literal) {
+        Ok(value) => match value {
+                        ResType::Num(val) => Ok(format!("{:06X}", val & 0xFFFFFF)),
+                        ResType::Char(vec) | ResType::Hex(vec) | ResType::Float(vec) => Ok(vec.iter().map(|hex| format!("{:02X}", hex)).collect::<Vec<String>>().join("")),
+                        ResType::Expr(expr) => Err(with_hint(token_error(&tokens_text(&expr), format!("Invalid literal ={}", tokens_text(&expr))), "Use: =<number>, =<float>, =X'<hex val>' or =C'<char val>'"))
+                     },
+        Err(diag) => Err(diag)
+    }
+}
+
+
+// value fits when it's either unsigned or two's complement number of given bytes
+fn fits_in_bytes(value: i64, size: usize) -> bool {
+    value >= -(1i64 << (size * 8 - 1)) && value < (1i64 << (size * 8))
+}
+
+
+fn truncated(token: &str, value: i64, size: usize) -> Diagnostic {
+    let bits = size * 8;
+    warning(WarningKind::Truncation, token, format!("value {} doesn't fit in {} bits, only lower {} bits are kept", value, bits, bits))
+}
+
+
+/*
+    float that has more digits than SIC/XE float can keep, its 36 bit fraction holds about 10 decimal digits
+    value was already checked by parse_float, so only written digits are compared to stored value
+*/
+pub fn float_precision(value: &[Token]) -> Option<Diagnostic> {
+    let number = match value.iter().find(|tok| tok.kind == TokenKind::Float) {
+                    Some(number) => number,
+                    None => return None
+                 };
+    let mantissa = number.text.split(['E', 'e']).next().unwrap();
+    let digits = mantissa.replace('.', "").trim_matches('0').len().max(1);
+
+    let written = number.text.parse::<f64>().unwrap();
+    let stored = float_from_bytes(&float_to_bytes(written));
+    if format!("{:.*e}", digits - 1, written) == format!("{:.*e}", digits - 1, stored) {
+        return None;
+    }
+    Some(warning(WarningKind::Precision, &tokens_text(value),
+                 format!("float {} has {} significant digits, but only about 10 are kept", tokens_text(value), digits)))
+}
+
+
+// evaluate BYTE or WORD expression, each external reference in it gets its own modification record
+fn handle_res_expr(expr: &[Token], scope: &Scope, mod_record: &mut Vec<ObjModification>, size: usize, warnings: &mut Vec<Diagnostic>) -> Result<String, Diagnostic> {
+    let value = match eval_expr(expr, scope) {
+                    Ok(result) => result,
+                    Err(diag) => return Err(diag)
+                };
+
+    // loader relocates whole addresses, which don't fit in a byte
+    if size == 1 && (value.kind == ValueKind::Relative || value.refs.len() > 0) {
+        let (first, last) = (&expr[0], &expr[expr.len()-1]);
+        let diag = token_error(&tokens_text(expr), format!("BYTE value {} must be absolute", tokens_text(expr)));
+        return Err(with_hint(Diagnostic{span: Some((first.column, last.column + last.text.chars().count())), ..diag},
+                             "addresses change when program is loaded and need 24 bits, use WORD for them"));
+    }
+
+    // length of modification is in half bytes
+    if value.kind == ValueKind::Relative {
+        mod_record.push(modification(scope.loc, size * 2, None));
+    }
+    for ext in value.refs {
+        mod_record.push(modification(scope.loc, size * 2, Some(&ext)));
+    }
+    Ok(encode_value(&tokens_text(expr), value.value as i64, size, warnings))
+}
+
+
+// value in given number of bytes, negative values are in two's complement and values that don't fit keep only lower bytes
+fn encode_value(token: &str, value: i64, size: usize, warnings: &mut Vec<Diagnostic>) -> String {
+    let mask = (1i64 << (size * 8)) - 1;
+    if !fits_in_bytes(value, size) {
+        warnings.push(truncated(token, value, size));
+    }
+    format!("{:0width$X}", value & mask, width = size * 2)
+}
+
+
+// code of single BYTE or WORD value, repeated value is written count times
+fn handle_init(value: &[Token], type_size: usize, scope: &Scope, mod_record: &mut Vec<ObjModification>, warnings: &mut Vec<Diagnostic>) -> Result<String, Diagnostic> {
+    let (count, value) = match split_dup(value) {
+                            Some((count, _, repeated)) => match absolute_count(count, scope, "Repeat count") {
+                                                            Ok(count) => (count, repeated),
+                                                            Err(diag) => return Err(diag)
+                                                          },
+                            None => return init_code(value, type_size, scope, mod_record, warnings)
+                         };
+
+    let code = match init_code(value, type_size, scope, mod_record, warnings) {
+                    Ok(code) => code,
+                    Err(diag) => return Err(diag)
+               };
+    // constants are the same in every copy, so large fills are just repeated code
+    if !matches!(parse_init(value), Ok(ResType::Expr(_))) {
+        return Ok(code.repeat(count));
+    }
+    // expression is evaluated at every copy, so each one gets its own modification record, warnings are reported once
+    let mut copies = code.clone();
+    for i in 1..count {
+        let copy_scope = Scope{loc: scope.loc + i * code.len() / 2, ..*scope};
+        match init_code(value, type_size, &copy_scope, mod_record, &mut Vec::new()) {
+            Ok(copy) => copies.push_str(&copy),
+            Err(diag) => return Err(diag)
+        }
+    }
+    Ok(copies)
+}
+
+
+// code of BYTE or WORD value without repetition, hex and char arrays are padded with leading zeros to size of type
+fn init_code(value: &[Token], type_size: usize, scope: &Scope, mod_record: &mut Vec<ObjModification>, warnings: &mut Vec<Diagnostic>) -> Result<String, Diagnostic> {
+    match parse_init(value) {
+        Ok(init) => match init {
+                        ResType::Num(num) => Ok(encode_value(&tokens_text(value), num as i64, type_size, warnings)),
+                        ResType::Char(vec) | ResType::Hex(vec) => { let mut bytes = vec.iter().map(|hex| format!("{:02X}", hex)).collect::<Vec<String>>();
+                                                                    while bytes.len() < type_size { bytes.insert(0, "00".to_owned()) };
+                                                                    Ok(bytes.join("")) },
+                        ResType::Float(_) => Err(float_not_allowed(value)),
+                        ResType::Expr(expr) => handle_res_expr(&expr, scope, mod_record, type_size, warnings)
+                    },
+        Err(diag) => Err(diag)
+    }
+}
+
+
+pub fn handle_res(dir: &str, values: &Vec<&[Token]>, scope: &Scope, mod_record: &mut Vec<ObjModification>, warnings: &mut Vec<Diagnostic>) -> Result<String, Diagnostic> {
     /*
-        BYTE and WORD can be either 1 number or HEX/CHAR array
+        BYTE and WORD can be either 1 number, HEX/CHAR array or expression with labels, or list of those
     */
-    // join value in case char array contains spaces
-    let init_str = value.join(" ");
-    if dir == "BYTE" {
-        let init_value = parse_init(&init_str);
-        match init_value {
-            Ok(value) => {  match value {
-                                // save number value as hex byte iee 2 nibbles
-                                ResType::Num(val) => return Ok(format!("{:02X}", val)),
-                                // concat all bytes together where each occupies 1byte ie. 2 nibbles
-                                ResType::Char(vec) => return Ok(vec.iter().map(|hex| format!("{:02X}", hex)).collect::<Vec<String>>().join("")),
-                                ResType::Hex(vec) => return Ok(vec.iter().map(|hex| format!("{:02X}", hex)).collect::<Vec<String>>().join(""))
-                            }
-                         },
-            Err(msg) => return Err(msg.to_owned())
-        }
-    }
-    else if dir == "WORD" {
-        let init_value = parse_init(&init_str);
-        match init_value {
-            Ok(value) => {  match value {
-                                // save number value as hex word iee 6 nibbles
-                                ResType::Num(val) => return Ok(format!("{:06X}", val)),
-                                // concat all bytes together where each occupies 1byte ie. 2 nibbles, but pad to get minimal 3 bytes
-                                ResType::Char(vec) => { let mut word_vec = vec.iter().map(|hex| format!("{:02X}", hex)).collect::<Vec<String>>();
-                                                        // pad with zeros to get at least width of 3
-                                                        while word_vec.len() < 3 { word_vec.insert(0, "00".to_owned()) };
-                                                        return Ok(word_vec.join(""))},
-                                ResType::Hex(vec) => { let mut word_vec = vec.iter().map(|hex| format!("{:02X}", hex)).collect::<Vec<String>>();
-                                                       while word_vec.len() < 3 { word_vec.insert(0, "00".to_owned()) };
-                                                       return Ok(word_vec.join(""))},
-                            }
-                         },
-            Err(msg) => return Err(msg.to_owned())
+    if dir == "BYTE" || dir == "WORD" {
+        let type_size = if dir == "BYTE" { 1 } else { 3 };
+        let mut code = String::new();
+        for value in values.iter() {
+            // value starts where previous one ended, so its modification record points at it
+            let value_scope = Scope{loc: scope.loc + code.len() / 2, ..*scope};
+            match handle_init(value, type_size, &value_scope, mod_record, warnings) {
+                Ok(value_code) => code.push_str(&value_code),
+                Err(diag) => return Err(diag)
+            }
+        }
+        return Ok(code);
+    }
+
+    if dir == "FILL" {
+        let count = match absolute_count(values[0], scope, "Fill count") {
+                        Ok(count) => count,
+                        Err(diag) => return Err(diag)
+                    };
+        // FILL without value is filled with zeros
+        let byte = match values.get(1) {
+                        Some(value) => match eval_expr(value, scope) {
+                                            Ok(byte) if byte.kind == ValueKind::Relative || byte.refs.len() > 0 =>
+                                                return Err(with_hint(token_error(&tokens_text(value), format!("FILL value {} must be absolute", tokens_text(value))),
+                                                                     "value is repeated in every byte, so it can't be relocated")),
+                                            Ok(byte) => encode_value(&tokens_text(value), byte.value as i64, 1, warnings),
+                                            Err(diag) => return Err(diag)
+                                       },
+                        None => "00".to_owned()
+                   };
+        return Ok(byte.repeat(count));
+    }
+
+    // other directives take one value, extra values are reported by determine_res_size
+    let value = values[0];
+    if dir == "FLOAT" {
+        match parse_float(value) {
+            Ok(ResType::Float(bytes)) => return Ok(bytes.iter().map(|byte| format!("{:02X}", byte)).collect::<Vec<String>>().join("")),
+            Ok(_) => return Err(token_error(dir, "Invalid float".to_owned())),
+            Err(diag) => return Err(diag)
+        }
+    }
+    else if dir == "ASCIZ" || dir == "ASCIC" {
+        match string_bytes(dir, value) {
+            Ok(bytes) => return Ok(bytes.iter().map(|byte| format!("{:02X}", byte)).collect::<Vec<String>>().join("")),
+            Err(diag) => return Err(diag)
         }
     }
     else if dir == "RESW" {
         return Ok("RESW".to_owned());
     }
+    else if dir == "RESF" {
+        return Ok("RESF".to_owned());
+    }
     else if dir == "RESB" {
         return Ok("RESB".to_owned());
     }
     else {
-        return Err("Invalid reservation".to_owned());
+        return Err(token_error(dir, "Invalid reservation".to_owned()));
     }
 }
\ No newline at end of file
@@ -0,0 +1,174 @@
+use crate::parser::{parse_reg, derived_line, SourceLine};
+use crate::diagnostics::{Diagnostic, at_token, with_hint, locate};
+use crate::lexer::{Token, TokenKind, line_tokens, COMPARISON_HINT};
+use crate::expressions::{Scope, ValueKind, parse_expression, expr_symbols, uses_location, eval_expr};
+use std::collections::{HashMap, HashSet};
+
+
+/*
+    conditional assembly, runs after macro expansion and before first pass
+
+    DEBUG   EQU     1
+    COUNT   SET     0
+            IF      DEBUG
+    COUNT   SET     COUNT+1         . SET symbol can be reassigned, EQU can't
+            LDA     #COUNT
+            ELSE
+            LDA     #0
+            ENDIF
+
+    IF uses only EQU and SET values defined before it, since both passes must agree on which lines are assembled
+*/
+struct Conditional {
+    // index of IF line and its IF, for error if ENDIF is missing
+    index: usize,
+    token: Token,
+    // lines of current branch are assembled
+    active: bool,
+    // IF is inside assembled branch of enclosing IF
+    parent_active: bool,
+    else_seen: bool
+}
+
+
+// evaluate expression of IF or SET from numbers and EQU and SET values known so far
+fn eval_known(op: &Token, tokens: &[Token], known: &HashMap<String, i32>) -> Result<i32, Diagnostic> {
+    if tokens.len() == 0 {
+        return Err(at_token(op, format!("{} needs a value", op.text)));
+    }
+    // = in expression is literal prefix, not comparison
+    match tokens.iter().find(|tok| tok.kind == TokenKind::Prefix('=')) {
+        Some(tok) => return Err(with_hint(at_token(tok, "comparison = is not supported".to_owned()), COMPARISON_HINT)),
+        None => ()
+    }
+    let expr = match parse_expression(tokens) {
+                    Ok(expr) => expr,
+                    Err(diag) => return Err(diag)
+               };
+    match expr_symbols(&expr).iter().find(|symbol| !known.contains_key(&symbol.text)) {
+        Some(symbol) => return Err(with_hint(at_token(symbol, format!("{} is not known here", symbol.text)),
+                                             "only numbers and EQU or SET values defined before can be used")),
+        None => ()
+    }
+    if uses_location(&expr) {
+        return Err(with_hint(at_token(op, format!("* can't be used in {}", op.text)), "location isn't known before first pass"));
+    }
+
+    let absolute: HashSet<String> = known.keys().cloned().collect();
+    let extref = Vec::new();
+    match eval_expr(tokens, &Scope{symtab: known, absolute: &absolute, extref: &extref, loc: 0}) {
+        Ok(value) if value.kind == ValueKind::Absolute => Ok(value.value),
+        Ok(_) => Err(at_token(op, format!("{} value must be absolute", op.text))),
+        Err(diag) => Err(diag)
+    }
+}
+
+
+// SET symbols in operands are replaced with value they have at this line, rest of text stays as it is
+fn fill_sets(text: &str, operands: &[Token], sets: &HashSet<String>, known: &HashMap<String, i32>) -> String {
+    let mut chars: Vec<char> = text.chars().collect();
+    // from last one, so columns of earlier tokens stay the same
+    for tok in operands.iter().rev() {
+        if tok.kind == TokenKind::Symbol && sets.contains(&tok.text) {
+            let start = tok.column - 1;
+            // negative value is in parentheses, so BUF-NEG doesn't become BUF--2
+            let value = if known[&tok.text] < 0 { format!("({})", known[&tok.text]) } else { known[&tok.text].to_string() };
+            chars.splice(start..start + tok.text.chars().count(), value.chars());
+        }
+    }
+    chars.iter().collect()
+}
+
+
+// mark lines in branches of IF that aren't taken, evaluate SET and fill SET symbols into operands
+// errors are added to diagnostics, IF that can't be evaluated isn't taken
+pub fn resolve_conditionals(program: &Vec<SourceLine>, diagnostics: &mut Vec<Diagnostic>) -> Vec<SourceLine> {
+    let mut result: Vec<SourceLine> = Vec::new();
+
+    // EQU values that could be computed so far and current values of SET symbols
+    let mut known: HashMap<String, i32> = HashMap::new();
+    let mut sets: HashSet<String> = HashSet::new();
+
+    let mut stack: Vec<Conditional> = Vec::new();
+
+    for (index, src) in program.iter().enumerate() {
+        let active = match stack.last() {
+            Some(cond) => cond.active,
+            None => true
+        };
+        let mut text = src.text.to_owned();
+        let mut skipped = !active;
+
+        // operation is either first token, or second one when line has label
+        let (tokens, complete) = line_tokens(&src.text);
+        let code: Vec<Token> = tokens.into_iter().filter(|tok| tok.kind != TokenKind::Comment).collect();
+        let label = code.first().filter(|tok| tok.kind == TokenKind::Label);
+        let op_index = if label.is_some() { 1 } else { 0 };
+        let op = code.get(op_index).filter(|tok| tok.kind == TokenKind::Mnemonic || tok.kind == TokenKind::Directive);
+        let operands = if op_index + 1 < code.len() { &code[op_index+1..] } else { &[] };
+        let name = match op {
+                        Some(op) => op.text.as_str(),
+                        None => ""
+                   };
+
+        match (name, label) {
+            ("IF" | "ELSE" | "ENDIF", Some(label)) => diagnostics.push(locate(at_token(label, format!("{} can't have label", name)), src)),
+            _ => ()
+        }
+        match name {
+            "IF" => { let op = op.unwrap();
+                      // lexer error is reported once the line is parsed
+                      let taken = if active && complete {
+                                    match eval_known(op, operands, &known) {
+                                        Ok(value) => value != 0,
+                                        Err(diag) => { diagnostics.push(locate(diag, src));
+                                                       false }
+                                    }
+                                  }
+                                  else {
+                                    false
+                                  };
+                      stack.push(Conditional{index: index, token: op.clone(), active: taken, parent_active: active, else_seen: false}); },
+            "ELSE" => match stack.last_mut() {
+                        Some(cond) if cond.else_seen => diagnostics.push(locate(at_token(op.unwrap(), "IF can have only one ELSE".to_owned()), src)),
+                        Some(cond) => { cond.else_seen = true;
+                                        cond.active = cond.parent_active && !cond.active;
+                                        skipped = !cond.parent_active; },
+                        None => diagnostics.push(locate(at_token(op.unwrap(), "ELSE without IF".to_owned()), src))
+                      },
+            "ENDIF" => match stack.pop() {
+                        Some(cond) => skipped = !cond.parent_active,
+                        None => diagnostics.push(locate(at_token(op.unwrap(), "ENDIF without IF".to_owned()), src))
+                       },
+            _ if !active || !complete || name == "" => (),
+            "SET" => match label {
+                        None => diagnostics.push(locate(at_token(op.unwrap(), "SET needs symbol as label".to_owned()), src)),
+                        Some(label) if (known.contains_key(&label.text) && !sets.contains(&label.text)) || parse_reg(&label.text).is_ok() =>
+                            diagnostics.push(locate(at_token(label, format!("{} can't be used as SET symbol", label.text)), src)),
+                        Some(label) => match eval_known(op.unwrap(), operands, &known) {
+                                            Ok(value) => { known.insert(label.text.to_owned(), value);
+                                                           sets.insert(label.text.to_owned()); },
+                                            Err(diag) => diagnostics.push(locate(diag, src))
+                                       }
+                     },
+            _ => { match label {
+                       Some(label) if sets.contains(&label.text) =>
+                           diagnostics.push(locate(at_token(label, format!("{} is SET symbol and can't be redefined", label.text)), src)),
+                       // remember EQU if its value can already be computed, so following IFs can use it
+                       Some(label) if name == "EQU" => match eval_known(op.unwrap(), operands, &known) {
+                                                            Ok(value) => { known.insert(label.text.to_owned(), value); },
+                                                            Err(_) => ()
+                                                       },
+                       _ => ()
+                   }
+                   text = fill_sets(&src.text, operands, &sets, &known); }
+        }
+
+        result.push(derived_line(text, src, src.expanded, skipped || src.skipped));
+    }
+
+    for cond in stack.iter() {
+        diagnostics.push(locate(at_token(&cond.token, "IF without ENDIF".to_owned()), &program[cond.index]));
+    }
+    result
+}
@@ -0,0 +1,203 @@
+use crate::parser::SourceLine;
+use crate::lexer::Token;
+use std::cmp;
+
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Severity {
+    Error,
+    Warning
+}
+
+
+// suspicious code that is still legal, each kind can be turned off
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum WarningKind {
+    UnusedLabel,
+    FallThrough,
+    BaseMismatch,
+    Truncation,
+    AddressingFallback,
+    Precision
+}
+
+
+// names used to turn warnings on and off, ie. -Wno-unused-label
+pub const WARNING_NAMES: [(&str, WarningKind); 6] = [("unused-label", WarningKind::UnusedLabel),
+                                                     ("fallthrough", WarningKind::FallThrough),
+                                                     ("base-mismatch", WarningKind::BaseMismatch),
+                                                     ("truncation", WarningKind::Truncation),
+                                                     ("addressing-fallback", WarningKind::AddressingFallback),
+                                                     ("float-precision", WarningKind::Precision)];
+
+
+pub fn find_warning(name: &str) -> Option<WarningKind> {
+    WARNING_NAMES.iter().find(|(warning, _)| *warning == name).map(|(_, kind)| *kind)
+}
+
+
+/*
+    problem found in source, errors stop object file from being written, warnings don't
+
+    functions that check single instruction or operand don't know which line they are working on,
+    so they create diagnostic without location and caller adds it with locate
+*/
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub file: String,
+    // 0 when problem isn't tied to a line
+    pub line: usize,
+    // columns of problem in line, first column is 1 and end isn't included, None when it's whole line
+    // columns are counted in line as it was written, tab is one column
+    pub span: Option<(usize, usize)>,
+    // word that caused problem, ie. unknown symbol, empty when there is no such word
+    pub token: String,
+    pub message: String,
+    pub hint: Option<String>,
+    // similar word that was probably meant instead of token, ie. LOOP for LOPP
+    pub suggestion: Option<String>,
+    // text of line, used to show where problem is
+    pub source: String,
+    // kind of warning, stays set when warning is turned into error by -Werror
+    pub warning: Option<WarningKind>
+}
+
+
+pub fn error(message: String) -> Diagnostic {
+    Diagnostic{severity: Severity::Error, file: String::new(), line: 0, span: None, token: String::new(), message: message, hint: None,
+               suggestion: None, source: String::new(), warning: None}
+}
+
+
+// error caused by given word of line
+pub fn token_error(token: &str, message: String) -> Diagnostic {
+    Diagnostic{token: token.to_owned(), ..error(message)}
+}
+
+
+// error caused by token from lexer, its column is known so span doesn't have to be searched for
+pub fn at_token(token: &Token, message: String) -> Diagnostic {
+    Diagnostic{span: Some((token.column, token.column + token.text.chars().count())), ..token_error(&token.text, message)}
+}
+
+
+pub fn warning(kind: WarningKind, token: &str, message: String) -> Diagnostic {
+    Diagnostic{severity: Severity::Warning, warning: Some(kind), ..token_error(token, message)}
+}
+
+
+pub fn with_hint(diag: Diagnostic, hint: &str) -> Diagnostic {
+    Diagnostic{hint: Some(hint.to_owned()), ..diag}
+}
+
+
+// suggest closest of candidates, when token is close enough to any of them
+pub fn with_suggestion(diag: Diagnostic, candidates: &Vec<&str>) -> Diagnostic {
+    let suggestion = closest_word(&diag.token, candidates);
+    Diagnostic{suggestion: suggestion, ..diag}
+}
+
+
+// optimal string alignment distance, swapping two neighbouring characters counts as one edit
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.to_uppercase().chars().collect();
+    let b: Vec<char> = b.to_uppercase().chars().collect();
+
+    let mut dist = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for i in 0..=a.len() {
+        dist[i][0] = i;
+    }
+    for j in 0..=b.len() {
+        dist[0][j] = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = if a[i-1] == b[j-1] { 0 } else { 1 };
+            dist[i][j] = (dist[i-1][j] + 1).min(dist[i][j-1] + 1).min(dist[i-1][j-1] + cost);
+            if i > 1 && j > 1 && a[i-1] == b[j-2] && a[i-2] == b[j-1] {
+                dist[i][j] = dist[i][j].min(dist[i-2][j-2] + 1);
+            }
+        }
+    }
+    dist[a.len()][b.len()]
+}
+
+
+// most similar word, short words may differ in one character and longer ones in one per three characters
+// only names are compared, a single character is too short to tell what it was meant to be, ie. 1 or #
+pub fn closest_word(word: &str, candidates: &Vec<&str>) -> Option<String> {
+    let name = word.strip_prefix('+').unwrap_or(word);
+    let identifier = name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '$') &&
+                     name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$');
+    if !identifier || name.chars().count() < 2 {
+        return None;
+    }
+    let limit = std::cmp::max(1, word.len() / 3);
+    candidates.iter()
+              .filter(|cand| **cand != word)
+              .map(|cand| (edit_distance(word, cand), *cand))
+              .filter(|(dist, _)| *dist <= limit)
+              .min()
+              .map(|(_, cand)| cand.to_owned())
+}
+
+
+// add location of source line, span covers first appearance of token in line
+pub fn locate(diag: Diagnostic, src: &SourceLine) -> Diagnostic {
+    let span = match diag.span {
+                    Some(span) => Some(span),
+                    None if diag.token != "" => src.text.find(&diag.token).map(|pos| { let col = src.text[..pos].chars().count() + 1;
+                                                                                      (col, col + diag.token.chars().count()) }),
+                    None => None
+               };
+    // columns are found in cleaned text, message shows them in line as it was written
+    if src.columns.len() == 0 {
+        return Diagnostic{file: src.file.to_owned(), line: src.line, span: span, source: src.text.to_owned(), ..diag};
+    }
+    let last = src.columns.len() - 1;
+    // end isn't included, so it's column after last character of span
+    let span = span.map(|(start, end)| { let first = cmp::min(start - 1, last);
+                                         let end = cmp::min(cmp::max(end, start + 1) - 2, last);
+                                         (src.columns[first], src.columns[end] + 1) });
+    Diagnostic{file: src.file.to_owned(), line: src.line, span: span, source: src.raw.to_owned(), ..diag}
+}
+
+
+// error caused by token of given line
+pub fn line_error(src: &SourceLine, token: &str, message: String) -> Diagnostic {
+    locate(token_error(token, message), src)
+}
+
+
+pub fn has_errors(diagnostics: &Vec<Diagnostic>) -> bool {
+    diagnostics.iter().any(|diag| diag.severity == Severity::Error)
+}
+
+
+// line already has an error, so checking it again would only repeat it
+pub fn line_has_error(diagnostics: &Vec<Diagnostic>, src: &SourceLine) -> bool {
+    diagnostics.iter().any(|diag| diag.severity == Severity::Error && diag.line == src.line && diag.file == src.file)
+}
+
+
+// name of warning tells how to turn it off, ie. [-Wunused-label]
+pub fn warning_flag(diag: &Diagnostic) -> String {
+    match diag.warning {
+        Some(kind) => format!(" [-W{}]", WARNING_NAMES.iter().find(|(_, warning)| *warning == kind).unwrap().0),
+        None => String::new()
+    }
+}
+
+
+fn plural(count: usize, word: &str) -> String {
+    if count == 1 { format!("{} {}", count, word) } else { format!("{} {}s", count, word) }
+}
+
+
+// ie. 3 errors, 1 warning
+pub fn summary(diagnostics: &Vec<Diagnostic>) -> String {
+    let errors = diagnostics.iter().filter(|diag| diag.severity == Severity::Error).count();
+    let warnings = diagnostics.len() - errors;
+    format!("{}, {}", plural(errors, "error"), plural(warnings, "warning"))
+}
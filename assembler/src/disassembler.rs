@@ -0,0 +1,531 @@
+use crate::commands::{find_mnemonic, determine_format};
+use crate::objreader::{read_obj_file, ObjSection};
+use crate::simulator::REG_NAMES;
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+
+/*
+    disassembler, turns .obj back into assembly that assembles to the same object code
+
+    code is found by following jumps from entry point, everything else is data
+    targets of instructions get synthetic labels (L0001A), symbols from D records keep their names
+    instructions whose addressing can't be written so assembler would produce the same bytes are kept as BYTE
+*/
+// modification records at one address, length of field and optional + or - external symbol
+type Modifications = Vec<(usize, Option<(char, String)>)>;
+
+
+struct Decoded {
+    mnem: &'static str,
+    size: usize,
+    ni: u8,
+    indexed: bool,
+    base_rel: bool,
+    pc_rel: bool,
+    extended: bool,
+    // displacement or address field, registers for format 2
+    field: usize,
+    r1: usize,
+    r2: usize
+}
+
+
+// bytes of one section with information gathered about them
+struct SectionInfo<'a> {
+    section: &'a ObjSection,
+    memory: HashMap<usize, u8>,
+    // modification records by address, with length and optional external symbol
+    mods: HashMap<usize, Modifications>,
+    code: HashMap<usize, Decoded>,
+    // address of every byte that belongs to instruction
+    code_bytes: HashSet<usize>,
+    labels: BTreeSet<usize>,
+    names: HashMap<usize, String>,
+    // labels used by instructions that work with words, their data is written as WORD
+    word_refs: HashSet<usize>
+}
+
+
+const JUMPS: [&str; 5] = ["J", "JEQ", "JGT", "JLT", "JSUB"];
+
+const WORD_INSTR: [&str; 20] = ["ADD", "AND", "COMP", "DIV", "LDA", "LDB", "LDL", "LDS", "LDT", "LDX", "MUL", "OR", "STA", "STB", "STL", "STS", "STT", "STX", "SUB", "TIX"];
+
+// characters that are safe inside C'...', apostrophe and dot are left out
+const CHAR_PUNCTUATION: &str = "!#$%&()*+,-/:;<=>?@[]^_{|}~ ";
+
+
+fn decode(info: &SectionInfo, addr: usize) -> Option<Decoded> {
+    let byte1 = *info.memory.get(&addr)?;
+    let mnem = find_mnemonic(byte1)?;
+    let format = determine_format(mnem).ok()?;
+
+    let mut decoded = Decoded{mnem: mnem, size: format, ni: byte1 & 0x03, indexed: false, base_rel: false, pc_rel: false, extended: false, field: 0, r1: 0, r2: 0};
+    if format == 1 {
+        // last 2 bits belong to opcode in format 1 and 2
+        return if decoded.ni == 0 { Some(decoded) } else { None };
+    }
+    let byte2 = *info.memory.get(&(addr + 1))?;
+    if format == 2 {
+        decoded.r1 = (byte2 >> 4) as usize;
+        decoded.r2 = (byte2 & 0x0F) as usize;
+        return if decoded.ni == 0 { Some(decoded) } else { None };
+    }
+
+    let byte3 = *info.memory.get(&(addr + 2))?;
+    decoded.indexed = byte2 & 0x80 != 0;
+    if decoded.ni == 0 {
+        // SIC format has 15 bit address
+        decoded.field = (((byte2 & 0x7F) as usize) << 8) | byte3 as usize;
+        return Some(decoded);
+    }
+    decoded.base_rel = byte2 & 0x40 != 0;
+    decoded.pc_rel = byte2 & 0x20 != 0;
+    decoded.extended = byte2 & 0x10 != 0;
+    if decoded.extended {
+        let byte4 = *info.memory.get(&(addr + 3))?;
+        decoded.size = 4;
+        decoded.field = (((byte2 & 0x0F) as usize) << 16) | ((byte3 as usize) << 8) | byte4 as usize;
+    }
+    else {
+        decoded.field = (((byte2 & 0x0F) as usize) << 8) | byte3 as usize;
+    }
+    Some(decoded)
+}
+
+
+// target address of instruction when it can be known without registers
+fn static_target(decoded: &Decoded, addr: usize) -> Option<usize> {
+    if decoded.size < 3 || decoded.indexed || decoded.base_rel {
+        return None;
+    }
+    if decoded.pc_rel {
+        let disp = if decoded.field & 0x800 != 0 { decoded.field as i64 - 0x1000 } else { decoded.field as i64 };
+        let target = (addr + 3) as i64 + disp;
+        return if target >= 0 { Some(target as usize) } else { None };
+    }
+    Some(decoded.field)
+}
+
+
+// follow flow of program from given addresses and mark instructions
+fn find_code(info: &mut SectionInfo, roots: Vec<usize>) {
+    let end = info.section.start + info.section.length;
+    let mut work = roots;
+
+    while let Some(addr) = work.pop() {
+        if addr < info.section.start || addr >= end || info.code_bytes.contains(&addr) {
+            continue;
+        }
+        let decoded = match decode(info, addr) {
+                        Some(decoded) => decoded,
+                        None => continue
+                      };
+        if (addr..addr+decoded.size).any(|a| info.code_bytes.contains(&a) || !info.memory.contains_key(&a)) {
+            continue;
+        }
+
+        let target = static_target(&decoded, addr);
+        if JUMPS.contains(&decoded.mnem) && decoded.ni == 3 {
+            if let Some(target) = target {
+                work.push(target);
+            }
+        }
+        // unconditional jumps and returns don't continue to next instruction
+        if decoded.mnem != "J" && decoded.mnem != "RSUB" {
+            work.push(addr + decoded.size);
+        }
+
+        for a in addr..addr+decoded.size {
+            info.code_bytes.insert(a);
+        }
+        info.code.insert(addr, decoded);
+    }
+
+    // everything referenced by instructions gets a label, base relative targets need B from last LDB
+    let mut targets: Vec<(usize, bool)> = Vec::new();
+    let mut addresses: Vec<&usize> = info.code.keys().collect();
+    addresses.sort();
+    let mut base: Option<usize> = None;
+    for addr in addresses {
+        let decoded = &info.code[addr];
+        // immediate numbers and external symbols don't point into this section
+        let external = decoded.extended && info.mods.get(&(addr + 1)).is_some_and(|mods| mods.iter().any(|(_, symbol)| symbol.is_some()));
+        let target = if decoded.size < 3 || (decoded.ni == 1 && !decoded.pc_rel && !has_mod(info, addr + 1, decoded.extended)) || external {
+                        None
+                     }
+                     else if decoded.base_rel && !decoded.pc_rel && !decoded.indexed {
+                        base.map(|b| b + decoded.field)
+                     }
+                     else {
+                        static_target(decoded, *addr)
+                     };
+        if let Some(target) = target {
+            targets.push((target, WORD_INSTR.contains(&decoded.mnem) && decoded.ni == 3));
+        }
+        if let Some(value) = loaded_base(decoded, *addr) {
+            base = value;
+        }
+    }
+    for (target, word) in targets {
+        info.labels.insert(target);
+        if word {
+            info.word_refs.insert(target);
+        }
+    }
+}
+
+
+// new value of B after LDB, None if instruction doesn't change it
+fn loaded_base(decoded: &Decoded, addr: usize) -> Option<Option<usize>> {
+    if decoded.mnem != "LDB" || decoded.size < 3 {
+        return None;
+    }
+    if decoded.ni == 1 && !decoded.base_rel {
+        return Some(static_target(decoded, addr));
+    }
+    // B is loaded from memory, so its value isn't known
+    Some(None)
+}
+
+
+// direct instruction with modification record, assembler would make it base relative if BASE covered its target
+fn needs_nobase(info: &SectionInfo, decoded: &Decoded, addr: usize, base: Option<usize>) -> bool {
+    let direct = decoded.size == 3 && decoded.ni != 0 && !decoded.base_rel && !decoded.pc_rel && has_mod(info, addr + 1, false);
+    match base {
+        Some(base) => direct && decoded.field >= base && decoded.field < base + 4096,
+        None => false
+    }
+}
+
+
+fn has_mod(info: &SectionInfo, addr: usize, extended: bool) -> bool {
+    let length = if extended { 5 } else { 3 };
+    match info.mods.get(&addr) {
+        Some(mods) => mods.iter().any(|(len, _)| *len == length),
+        None => false
+    }
+}
+
+
+// label can be written only at start of instruction, inside data or at end of section
+fn can_label(info: &SectionInfo, addr: usize) -> bool {
+    let end = info.section.start + info.section.length;
+    addr >= info.section.start && addr <= end && (info.code.contains_key(&addr) || !info.code_bytes.contains(&addr))
+}
+
+
+fn label_name(info: &SectionInfo, addr: usize) -> String {
+    match info.names.get(&addr) {
+        Some(name) => name.to_owned(),
+        None => format!("L{:05X}", addr)
+    }
+}
+
+
+// operand of instruction written so that assembler chooses the same encoding, None if that's not possible
+fn render_operands(info: &SectionInfo, decoded: &Decoded, addr: usize, base: Option<usize>) -> Option<String> {
+    let reg_name = |reg: usize| -> Option<&str> { if reg <= 6 { Some(REG_NAMES[reg]) } else { None } };
+
+    match decoded.size {
+        1 => return Some(String::new()),
+        2 => return match decoded.mnem {
+                        "CLEAR" | "TIXR" => if decoded.r2 == 0 { reg_name(decoded.r1).map(|r| r.to_owned()) } else { None },
+                        "SVC" => if decoded.r2 == 0 { Some(format!("{}", decoded.r1)) } else { None },
+                        // shift count is stored as is by assembler
+                        "SHIFTL" | "SHIFTR" => if decoded.r2 > 0 { reg_name(decoded.r1).map(|r| format!("{}, {}", r, decoded.r2)) } else { None },
+                        _ => match (reg_name(decoded.r1), reg_name(decoded.r2)) {
+                                (Some(r1), Some(r2)) => Some(format!("{}, {}", r1, r2)),
+                                _ => None
+                             }
+                     },
+        _ => ()
+    }
+
+    if decoded.mnem == "RSUB" {
+        return if decoded.ni == 3 && decoded.field == 0 && !decoded.indexed && !decoded.base_rel && !decoded.pc_rel && !decoded.extended { Some(String::new()) } else { None };
+    }
+    // SIC format and indexed immediate or indirect can't be written
+    if decoded.ni == 0 || (decoded.indexed && decoded.ni != 3) {
+        return None;
+    }
+    let prefix = match decoded.ni { 1 => "#", 2 => "@", _ => "" };
+    let suffix = if decoded.indexed { ",X" } else { "" };
+    let label = |target: usize| -> Option<String> { if can_label(info, target) { Some(format!("{}{}{}", prefix, label_name(info, target), suffix)) } else { None } };
+
+    if decoded.extended {
+        if decoded.base_rel || decoded.pc_rel {
+            return None;
+        }
+        match info.mods.get(&(addr + 1)) {
+            Some(mods) if mods.iter().all(|(len, _)| *len == 5) => {
+                // local address, or sum of external symbols and number
+                if mods.len() == 1 && mods[0].1.is_none() {
+                    return label(decoded.field);
+                }
+                let mut expr = String::new();
+                for (_, symbol) in mods.iter() {
+                    match symbol {
+                        Some((sign, name)) => { if expr != "" || *sign == '-' { expr.push(*sign); } expr.push_str(name); },
+                        None => return None
+                    }
+                }
+                if decoded.field != 0 {
+                    expr.push_str(&format!("+{}", decoded.field));
+                }
+                return Some(format!("{}{}{}", prefix, expr, suffix));
+            },
+            Some(_) => return None,
+            None => ()
+        }
+        // without modification record field is immediate number or absolute address, ie. in linked program
+        if decoded.ni == 1 {
+            let value = if decoded.field & 0x80000 != 0 { decoded.field as i64 - 0x100000 } else { decoded.field as i64 };
+            return Some(format!("#{}", value));
+        }
+        return Some(format!("{}{}{}", prefix, decoded.field, suffix));
+    }
+
+    let pc = addr + 3;
+    let pc_reachable = |target: usize| -> bool { let diff = target as i64 - pc as i64; (-2048..=2047).contains(&diff) };
+    if decoded.pc_rel && !decoded.base_rel {
+        return static_target(decoded, addr).and_then(label);
+    }
+    if decoded.base_rel && !decoded.pc_rel {
+        // base relative is used only when target is too far for PC relative
+        let target = base? + decoded.field;
+        return if pc_reachable(target) { None } else { label(target) };
+    }
+    if decoded.base_rel || decoded.pc_rel {
+        return None;
+    }
+
+    // direct addressing, immediate number or address that assembler couldn't reach relatively
+    if has_mod(info, addr + 1, false) {
+        return if pc_reachable(decoded.field) { None } else { label(decoded.field) };
+    }
+    if decoded.ni == 1 && !decoded.indexed {
+        let value = if decoded.field & 0x800 != 0 { decoded.field as i64 - 0x1000 } else { decoded.field as i64 };
+        return Some(format!("#{}", value));
+    }
+    None
+}
+
+
+fn is_char(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || CHAR_PUNCTUATION.contains(byte as char)
+}
+
+
+// length of string that can be written as C'...', double spaces would be reduced to one by assembler
+fn char_run(bytes: &[u8]) -> usize {
+    let mut len = 0;
+    while len < bytes.len() && is_char(bytes[len]) && !(bytes[len] == b' ' && len > 0 && bytes[len-1] == b' ') {
+        len += 1;
+    }
+    len
+}
+
+
+fn hex_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+
+fn statement(label: &str, operation: &str, operands: &str, addr: usize, code: &str) -> String {
+    let line = format!("{:<8}{:<8}{}", label, operation, operands);
+    format!("{:<40}. {:06X}  {}", line.trim_end(), addr, code).trim_end().to_owned()
+}
+
+
+// data between two labels, code or reservations
+fn render_data(info: &SectionInfo, bytes: &[u8], addr: usize, label: &mut String, output: &mut Vec<String>) {
+    let mut i = 0;
+    while i < bytes.len() {
+        let a = addr + i;
+        let rest = &bytes[i..];
+
+        // relocated word, either local address or external symbol
+        let word_mods = info.mods.get(&a).filter(|mods| mods.iter().all(|(len, _)| *len == 6));
+        if let (true, Some(mods)) = (rest.len() >= 3, word_mods) {
+            let value = ((rest[0] as usize) << 16) | ((rest[1] as usize) << 8) | rest[2] as usize;
+            let operand = if mods.len() == 1 && mods[0].1.is_none() && can_label(info, value) {
+                            Some(label_name(info, value))
+                          }
+                          else if mods.iter().all(|(_, symbol)| symbol.is_some()) {
+                            let mut expr = String::new();
+                            for (_, symbol) in mods.iter() {
+                                let (sign, name) = symbol.as_ref().unwrap();
+                                if expr != "" || *sign == '-' { expr.push(*sign); }
+                                expr.push_str(name);
+                            }
+                            if value != 0 { expr.push_str(&format!("+{}", value)); }
+                            Some(expr)
+                          }
+                          else {
+                            None
+                          };
+            if let Some(operand) = operand {
+                output.push(statement(label, "WORD", &operand, a, &hex_string(&rest[..3])));
+                label.clear();
+                i += 3;
+                continue;
+            }
+        }
+
+        if rest.len() >= 3 && i == 0 && info.word_refs.contains(&a) {
+            let value = ((rest[0] as usize) << 16) | ((rest[1] as usize) << 8) | rest[2] as usize;
+            output.push(statement(label, "WORD", &value.to_string(), a, &hex_string(&rest[..3])));
+            label.clear();
+            i += 3;
+            continue;
+        }
+
+        let run = char_run(rest);
+        if run >= 4 {
+            let len = if run > 30 { 30 } else { run };
+            output.push(statement(label, "BYTE", &format!("C'{}'", String::from_utf8_lossy(&rest[..len])), a, &hex_string(&rest[..len])));
+            label.clear();
+            i += len;
+            continue;
+        }
+
+        // hex bytes until next string
+        let mut len = 1;
+        while len < rest.len() && len < 16 && char_run(&rest[len..]) < 4 && !info.mods.contains_key(&(a + len)) {
+            len += 1;
+        }
+        output.push(statement(label, "BYTE", &format!("X'{}'", hex_string(&rest[..len])), a, &hex_string(&rest[..len])));
+        label.clear();
+        i += len;
+    }
+}
+
+
+// returns END statement, which is written after all sections
+fn disassemble_section(section: &ObjSection, first: bool, output: &mut Vec<String>) -> String {
+    let mut info = SectionInfo{section: section, memory: HashMap::new(), mods: HashMap::new(), code: HashMap::new(), code_bytes: HashSet::new(),
+                               labels: BTreeSet::new(), names: HashMap::new(), word_refs: HashSet::new()};
+    for (addr, bytes) in section.texts.iter() {
+        for (i, byte) in bytes.iter().enumerate() {
+            info.memory.insert(addr + i, *byte);
+        }
+    }
+    for modif in section.mods.iter() {
+        info.mods.entry(modif.address).or_default().push((modif.length, modif.symbol.clone()));
+    }
+    for (name, addr) in section.defs.iter() {
+        info.names.entry(*addr).or_insert(name.to_owned());
+        info.labels.insert(*addr);
+    }
+
+    // code is followed from entry point, or from start of section when it doesn't have one
+    let root = match section.entry {
+                    Some(entry) => entry,
+                    None => section.start
+               };
+    info.labels.insert(root);
+    find_code(&mut info, vec![root]);
+
+    // header of section
+    if first {
+        output.push(format!("{:<8}{:<8}{}", section.name, "START", section.start));
+    }
+    else {
+        output.push(String::new());
+        output.push(format!("{:<8}{}", section.name, "CSECT"));
+    }
+    let defs: Vec<String> = section.defs.iter().filter(|(_, addr)| can_label(&info, *addr)).map(|(name, _)| name.to_owned()).collect();
+    if defs.len() > 0 {
+        output.push(format!("{:<8}{:<8}{}", "", "EXTDEF", defs.join(",")));
+    }
+    if section.refs.len() > 0 {
+        output.push(format!("{:<8}{:<8}{}", "", "EXTREF", section.refs.join(",")));
+    }
+    output.push(String::new());
+
+    let end = section.start + section.length;
+    let mut addr = section.start;
+    // value of B known from last LDB and value of last BASE written
+    let mut base: Option<usize> = None;
+    let mut emitted_base: Option<usize> = None;
+
+    while addr < end {
+        let mut label = if info.labels.contains(&addr) { label_name(&info, addr) } else { String::new() };
+
+        if let Some(decoded) = info.code.get(&addr) {
+            let code = hex_string(&(addr..addr+decoded.size).map(|a| info.memory[&a]).collect::<Vec<u8>>());
+            let operation = if decoded.extended { format!("+{}", decoded.mnem) } else { decoded.mnem.to_owned() };
+
+            if needs_nobase(&info, decoded, addr, emitted_base) {
+                output.push(format!("{:<8}{}", "", "NOBASE"));
+                emitted_base = None;
+            }
+            match render_operands(&info, decoded, addr, base) {
+                Some(operands) => {
+                    if decoded.base_rel && emitted_base != base {
+                        output.push(format!("{:<8}{:<8}{}", "", "BASE", base.unwrap()));
+                        emitted_base = base;
+                    }
+                    output.push(statement(&label, &operation, &operands, addr, &code));
+                },
+                // instruction is kept as bytes, decoded instruction is in comment
+                None => output.push(format!("{}  {}", statement(&label, "BYTE", &format!("X'{}'", code), addr, &code), operation))
+            }
+            if let Some(value) = loaded_base(decoded, addr) {
+                base = value;
+            }
+            addr += decoded.size;
+            continue;
+        }
+
+        // data or reservation continues until next label or instruction
+        let mut next = addr + 1;
+        let defined = info.memory.contains_key(&addr);
+        while next < end && !info.labels.contains(&next) && !info.code.contains_key(&next) && info.memory.contains_key(&next) == defined {
+            next += 1;
+        }
+        if defined {
+            let bytes: Vec<u8> = (addr..next).map(|a| info.memory[&a]).collect();
+            render_data(&info, &bytes, addr, &mut label, output);
+        }
+        else {
+            output.push(statement(&label, "RESB", &(next - addr).to_string(), addr, ""));
+        }
+        addr = next;
+    }
+    // label can also point right after last byte of section
+    if info.labels.contains(&end) {
+        output.push(format!("{:<8}{:<8}*", label_name(&info, end), "EQU"));
+    }
+
+    match section.entry {
+        Some(entry) if can_label(&info, entry) => format!("{:<8}{:<8}{}", "", "END", label_name(&info, entry)),
+        _ => format!("{:<8}{}", "", "END")
+    }
+}
+
+
+// disassemble all sections of object file to lines of assembly
+pub fn disassemble(filename: &str) -> Result<Vec<String>, String> {
+    match read_obj_file(filename) {
+        Ok(sections) => Ok(disassemble_sections(&sections, filename)),
+        Err(msg) => Err(msg)
+    }
+}
+
+
+// disassemble sections that were already read, name is only written to first comment
+pub fn disassemble_sections(sections: &Vec<ObjSection>, name: &str) -> Vec<String> {
+    let mut output: Vec<String> = vec![format!(". disassembled from {}", name)];
+    let mut end = String::new();
+    for (i, section) in sections.iter().enumerate() {
+        let section_end = disassemble_section(section, i == 0, &mut output);
+        // execution starts in first section
+        if i == 0 {
+            end = section_end;
+        }
+    }
+    output.push(String::new());
+    output.push(end);
+    output
+}
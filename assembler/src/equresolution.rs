@@ -1,138 +1,142 @@
 use std::collections::HashMap;
-use crate::parser::parse_expr;
-
-#[derive(Debug)]
-pub struct EquExpression {
-    dep_count: usize,
-    expression: String,
-    value: i32,
-    valid_value: bool
+use crate::lexer::Token;
+use crate::parser::SourceLine;
+use crate::symbols::Section;
+use crate::expressions::{Scope, ValueKind, parse_expression, expr_symbols, eval_expr, undefined_symbol};
+use crate::diagnostics::{Diagnostic, at_token, with_hint, locate};
+
+
+// EQU that uses symbols defined after it, it's resolved when its section is closed
+pub struct EquExpression<'a> {
+    pub name: String,
+    expression: Vec<Token>,
+    // symbols expression uses, they must be resolved first
+    dependencies: Vec<Token>,
+    // * in expression is location of EQU inside its block
+    block: usize,
+    loc: usize,
+    pub src: &'a SourceLine
 }
 
 
-// used when inserting direct value, ie: EQU *
-pub fn insert_equ_number(equtab: &mut HashMap<String, EquExpression>, name: &str, value: i32) {
-    let entry = EquExpression{dep_count: 0, expression: String::new(), value: value, valid_value: true};
-    equtab.insert(name.to_owned(), entry);
+// used to insert arbitrary expression, examples: EQU 42, EQU LEN+BEN
+pub fn insert_equ_str<'a>(equtab: &mut Vec<EquExpression<'a>>, name: &str, equexpr: &[Token], block: usize, loc: usize, src: &'a SourceLine) -> Result<u8, Diagnostic> {
+    // expression is parsed right away, so syntax errors are found at EQU line
+    let dependencies = match parse_expression(equexpr) {
+                            Ok(expr) => expr_symbols(&expr).into_iter().cloned().collect(),
+                            Err(diag) => return Err(diag)
+                       };
+
+    equtab.push(EquExpression{name: name.to_owned(), expression: equexpr.to_vec(), dependencies: dependencies, block: block, loc: loc, src: src});
+    Ok(1)
 }
 
 
-// used to insert arbitrary expression, examples: EQU 42, EQU LEN+BEN
-pub fn insert_equ_str(equtab: &mut HashMap<String, EquExpression>, name: &str, equexpr: &Vec<&str>) -> Result<u8, String> {
-    // join entire expression and remove the comment
-    let mut equ_str = String::new();
-    for w in equexpr {
-        if w.starts_with(".") {
-            break;
-        }
-        equ_str.push_str(w);
-    }
-    // split by operators
-    let mut split_vars: Vec<String> = equ_str.split(&['*', '/', '+', '-'][..]).map(|s| s.to_string()).collect();
-    // filter to keep only variables
-    split_vars = split_vars.into_iter().filter(|var| var.parse::<i32>().is_err()).collect();
-
-    let mut expr_val = 0;
+// cycle of EQUs, ie. A (line 3) -> B (line 4) -> A (line 3)
+fn cycle_error(equtab: &Vec<EquExpression>, path: &[usize]) -> Diagnostic {
+    let names: Vec<String> = path.iter().map(|i| format!("{} (line {})", equtab[*i].name, equtab[*i].src.line)).collect();
+    let first = &equtab[path[0]];
+    with_hint(locate(at_token(&first.expression[0], format!("EQU {} depends on itself: {}", first.name, names.join(" -> "))), first.src),
+              "value of EQU can't be computed from itself, break the cycle with a number or label")
+}
 
-    // used to recognize if expression was evaluated or not
-    let mut valid = false;
 
-    if split_vars.len() == 0 {
-        // expression is pure numbers
-        valid = true;
-        match parse_expr(&equ_str) {
-            Ok(val) => expr_val = val,
-            Err(msg) => return Err(msg.to_owned())
+/*
+    depth first search of EQUs that given EQU depends on, EQU is added to order after all of its dependencies
+    state is 0 for EQUs that weren't visited, 1 while their dependencies are searched and 2 when they are in order
+*/
+fn visit(i: usize, equtab: &Vec<EquExpression>, index: &HashMap<&str, usize>, state: &mut Vec<u8>, path: &mut Vec<usize>, order: &mut Vec<usize>, failed: &mut Vec<bool>, diagnostics: &mut Vec<Diagnostic>) {
+    state[i] = 1;
+    path.push(i);
+    for dep in equtab[i].dependencies.iter() {
+        let j = match index.get(dep.text.as_str()) {
+                    Some(j) => *j,
+                    // label, or symbol that was already reported
+                    None => continue
+                };
+        match state[j] {
+            0 => visit(j, equtab, index, state, path, order, failed, diagnostics),
+            1 => { // dependency is on path, so path from it back to it is cycle
+                   let start = path.iter().position(|k| *k == j).unwrap();
+                   let mut cycle = path[start..].to_vec();
+                   cycle.push(j);
+                   diagnostics.push(cycle_error(equtab, &cycle));
+                   for k in cycle {
+                       failed[k] = true;
+                   } },
+            _ => ()
         }
     }
-
-    // insert expression entry to table
-    let entry = EquExpression{dep_count: split_vars.len(), expression: equ_str, value: expr_val, valid_value: valid};
-    equtab.insert(name.to_string(), entry);
-
-    Ok(1)
+    path.pop();
+    state[i] = 2;
+    order.push(i);
 }
 
 
-// before resolving equs insert already known variables
-fn insert_known_vars(symtab: &HashMap<String, i32>, equtab: &mut HashMap<String, EquExpression>) -> Result<u8, String> {
-    for (_, expr) in equtab.iter_mut() {
-        // if it's already pure number skip
-        if expr.dep_count == 0 {
-            continue;
-        }
-        let mut equ_str = expr.expression.clone();
-
-        // split by operators
-        let mut split_vars: Vec<String> = equ_str.split(&['*', '/', '+', '-'][..]).map(|s| s.to_string()).collect();
-        // filter to keep only variables
-        split_vars = split_vars.into_iter().filter(|var| var.parse::<i32>().is_err()).collect();
-
-        // insert already known variables
-        let mut dep_count = split_vars.len();
-        for var in split_vars.iter() {
-            if symtab.contains_key(var) {
-                let filled_equ_str = (&equ_str).replace(&*var, &(symtab[var].to_string()));
-                equ_str = filled_equ_str;
-                dep_count -= 1;
+/*
+    resolve EQUs of section in order of their dependencies, labels are already at their final addresses
+    errors are returned for every EQU that can't be resolved, those EQUs are 0 so lines using them don't report more errors
+*/
+pub fn resolve_equs(section: &mut Section, equtab: &Vec<EquExpression>) -> Vec<Diagnostic> {
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+    let index: HashMap<&str, usize> = equtab.iter().enumerate().map(|(i, equ)| (equ.name.as_str(), i)).collect();
+    let mut failed = vec![false; equtab.len()];
+
+    // symbol that is neither label nor other EQU will never be known
+    for (i, equ) in equtab.iter().enumerate() {
+        for dep in equ.dependencies.iter() {
+            if !section.symtab.contains_key(&dep.text) && !index.contains_key(dep.text.as_str()) && !section.extref.contains(&dep.text) {
+                diagnostics.push(locate(Diagnostic{span: Some((dep.column, dep.column + dep.text.len())),
+                                                   ..undefined_symbol(&dep.text, &section.symtab, &section.extref)}, equ.src));
+                failed[i] = true;
             }
         }
-
-        expr.expression = equ_str;
-        expr.dep_count = dep_count;
     }
-    Ok(1)
-}
 
-
-// resolve all equs
-pub fn resolve_equs(symtab: &mut HashMap<String, i32>, equtab: &mut HashMap<String, EquExpression>) -> Result<u8, String> {
-    // first insert known variables from symtab
-    match insert_known_vars(&symtab, equtab) {
-        Ok(_) => (),
-        Err(msg) => return Err(msg)
+    let mut state = vec![0; equtab.len()];
+    let mut path: Vec<usize> = Vec::new();
+    let mut order: Vec<usize> = Vec::new();
+    for i in 0..equtab.len() {
+        if state[i] == 0 {
+            visit(i, equtab, &index, &mut state, &mut path, &mut order, &mut failed, &mut diagnostics);
+        }
     }
 
-    // repeat until all equs are resolved, if equs are more than 420000 deep or we are stuck, break
-    let mut limiter = 420000;
-    while equtab.len() > 0 && limiter > 0 {
-        let mut current_resolved: String = String::new();
-        let mut resolved_val: i32 = 0;
-
-        // find equ that has no dependencies, ie. dep count is 0
-        for (name, equ_expr) in equtab.iter() {
-            if equ_expr.dep_count == 0 {
-                current_resolved = name.to_owned();
-                resolved_val = equ_expr.value;
+    // dependencies come first in order, so their values and kinds are known when EQU is evaluated
+    for i in order {
+        let equ = &equtab[i];
+        if !failed[i] && equ.dependencies.iter().any(|dep| index.get(dep.text.as_str()).is_some_and(|j| failed[*j])) {
+            // EQU it depends on was already reported
+            failed[i] = true;
+        }
+        if failed[i] {
+            section.symtab.insert(equ.name.to_owned(), 0);
+            section.absolute.insert(equ.name.to_owned());
+            continue;
+        }
 
-                // if value isn't already pure, evaluate it
-                if !equ_expr.valid_value {
-                    match parse_expr(&equ_expr.expression) {
-                        Ok(val) => resolved_val = val,
-                        Err(msg) => return Err(msg.to_owned())
-                    }
+        let loc = section.blocks[equ.block].start + equ.loc;
+        let value = eval_expr(&equ.expression, &Scope{symtab: &section.symtab, absolute: &section.absolute, extref: &section.extref, loc: loc});
+        match value {
+            Ok(value) if value.refs.len() > 0 => {
+                diagnostics.push(locate(at_token(&equ.expression[0], "EQU can't use external reference".to_owned()), equ.src));
+                section.symtab.insert(equ.name.to_owned(), 0);
+                section.absolute.insert(equ.name.to_owned());
+                failed[i] = true;
+            },
+            Ok(value) => {
+                section.symtab.insert(equ.name.to_owned(), value.value);
+                if value.kind == ValueKind::Absolute {
+                    section.absolute.insert(equ.name.to_owned());
                 }
+            },
+            Err(diag) => {
+                diagnostics.push(locate(diag, equ.src));
+                section.symtab.insert(equ.name.to_owned(), 0);
+                section.absolute.insert(equ.name.to_owned());
+                failed[i] = true;
             }
         }
-        
-        // update all expressions where current resolved one appears
-        for (_, equ_expr) in equtab.iter_mut() {
-            if (&equ_expr.expression).contains(&current_resolved) {
-                let filled_dependent = (&equ_expr.expression).replace(&current_resolved, &(resolved_val.to_string()));
-                equ_expr.expression = filled_dependent;
-                equ_expr.dep_count -= 1;
-            }
-        }
-
-        equtab.remove(&current_resolved);
-        symtab.insert(current_resolved, resolved_val);
-
-        limiter -= 1;
     }
-    if limiter > 0 {
-        Ok(1)
-    }
-    else {
-        Err("Endless cycle in resolving EQUs".to_owned())
-    }
-}
\ No newline at end of file
+    diagnostics
+}
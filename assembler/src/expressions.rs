@@ -0,0 +1,487 @@
+use crate::lexer::{Token, TokenKind, tokens_text};
+use crate::parser::char_bytes;
+use crate::diagnostics::{Diagnostic, error, token_error, at_token, with_hint, with_suggestion};
+use std::collections::{HashMap, HashSet};
+
+
+// absolute value stays the same wherever program is loaded, relative value is address inside program
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ValueKind {
+    Absolute,
+    Relative
+}
+
+
+// value of expression, external references count as 0 and are kept with their sign, ie. +BUFEND, -BUFFER
+#[derive(Debug)]
+pub struct Value {
+    pub value: i32,
+    pub kind: ValueKind,
+    pub refs: Vec<String>
+}
+
+
+// symbols that expression can use
+pub struct Scope<'a> {
+    pub symtab: &'a HashMap<String, i32>,
+    // symbols that aren't addresses, ie. EQU 42, every other symbol is relative
+    pub absolute: &'a HashSet<String>,
+    pub extref: &'a Vec<String>,
+    // value of *, location of current instruction
+    pub loc: usize
+}
+
+
+/*
+    parsed expression, every node keeps token it was made of so errors can point at it
+
+    LEN-(BUF+2)*3
+    is binary - of symbol LEN and binary * of parenthesized binary + and number 3
+*/
+#[derive(Clone, Debug)]
+pub enum Expr {
+    Number(i32, Token),
+    // C'A', characters are bytes of number
+    Char(i32, Token),
+    Symbol(Token),
+    // * in place of value, location of current instruction
+    Location(Token),
+    // - + ~
+    Unary(Token, Box<Expr>),
+    // + - * / % & | ^ << >>
+    Binary(Token, Box<Expr>, Box<Expr>)
+}
+
+
+// binary operators from lowest to highest precedence, operators of same level are evaluated from left
+const LEVELS: [&[&str]; 6] = [&["|"], &["^"], &["&"], &["<<", ">>"], &["+", "-"], &["*", "/", "%"]];
+
+// values are 24 bit words, either unsigned or two's complement
+const MIN_VALUE: i64 = -(1 << 23);
+const MAX_VALUE: i64 = (1 << 24) - 1;
+
+
+// value of expression so far, relative values count 1 and subtracted ones -1, so BUF-BUF is absolute
+struct Term {
+    value: i32,
+    relative: i32,
+    refs: Vec<(char, String)>
+}
+
+
+// symbol is neither label of section nor external reference
+pub fn undefined_symbol(symbol: &str, symtab: &HashMap<String, i32>, extref: &Vec<String>) -> Diagnostic {
+    // literals are in symbol table of instructions too, but they can't be misspelled labels
+    let names: Vec<&str> = symtab.keys().filter(|name| !name.starts_with("=")).chain(extref.iter()).map(|name| name.as_str()).collect();
+    with_suggestion(with_hint(token_error(symbol, format!("Symbol {} does not appear as a left label", symbol)),
+                              "define it as label, or declare it with EXTREF when it's in other section"),
+                    &names)
+}
+
+
+fn is_operator(tok: &Token, op: &str) -> bool {
+    matches!(tok.kind, TokenKind::Operator(_)) && tok.text == op
+}
+
+
+// value of char constant, ie. C'AB' is 0x4142, word has room for 3 characters
+fn char_value(tok: &Token) -> Result<i32, Diagnostic> {
+    let bytes = match char_bytes(&tok.text) {
+                    Ok(bytes) => bytes,
+                    Err(diag) => return Err(diag)
+                };
+    if bytes.len() > 3 {
+        return Err(at_token(tok, format!("{} must have 1 to 3 characters to be used as value", tok.text)));
+    }
+    Ok(bytes.iter().fold(0, |value, byte| (value << 8) | *byte as i32))
+}
+
+
+// number, char constant, symbol, * or expression in parentheses, with unary operators in front of them
+fn parse_unary(tokens: &[Token], pos: &mut usize) -> Result<Expr, Diagnostic> {
+    let tok = match tokens.get(*pos) {
+                    Some(tok) => tok,
+                    None => return Err(at_token(&tokens[tokens.len()-1], format!("expression can't end with {}", tokens[tokens.len()-1].text)))
+              };
+    *pos += 1;
+
+    match tok.kind {
+        TokenKind::Number(value) => Ok(Expr::Number(value, tok.clone())),
+        TokenKind::Char => match char_value(tok) {
+                                Ok(value) => Ok(Expr::Char(value, tok.clone())),
+                                Err(diag) => Err(diag)
+                           },
+        TokenKind::Symbol | TokenKind::Register => Ok(Expr::Symbol(tok.clone())),
+        TokenKind::Operator('*') => Ok(Expr::Location(tok.clone())),
+        TokenKind::Operator('-') | TokenKind::Operator('+') | TokenKind::Operator('~') => match parse_unary(tokens, pos) {
+                                                                                            Ok(operand) => Ok(Expr::Unary(tok.clone(), Box::new(operand))),
+                                                                                            Err(diag) => Err(diag)
+                                                                                          },
+        TokenKind::Operator('(') => { let inner = match parse_level(tokens, pos, 0) {
+                                                    Ok(inner) => inner,
+                                                    Err(diag) => return Err(diag)
+                                                };
+                                      match tokens.get(*pos) {
+                                          Some(close) if is_operator(close, ")") => { *pos += 1;
+                                                                                      Ok(inner) },
+                                          _ => Err(at_token(tok, "missing closing parenthesis".to_owned()))
+                                      } },
+        TokenKind::Float => Err(with_hint(at_token(tok, format!("float {} can't be used in expression", tok.text)),
+                                          "floats are only values of FLOAT and float literals, ie. =3.14")),
+        _ => Err(at_token(tok, format!("unexpected {} in expression", tok.text)))
+    }
+}
+
+
+// binary operators of given level and those with higher precedence
+fn parse_level(tokens: &[Token], pos: &mut usize, level: usize) -> Result<Expr, Diagnostic> {
+    if level == LEVELS.len() {
+        return parse_unary(tokens, pos);
+    }
+
+    let mut left = match parse_level(tokens, pos, level + 1) {
+                        Ok(expr) => expr,
+                        Err(diag) => return Err(diag)
+                   };
+    while *pos < tokens.len() && LEVELS[level].iter().any(|op| is_operator(&tokens[*pos], op)) {
+        let op = tokens[*pos].clone();
+        *pos += 1;
+        let right = match parse_level(tokens, pos, level + 1) {
+                        Ok(expr) => expr,
+                        Err(diag) => return Err(diag)
+                    };
+        left = Expr::Binary(op, Box::new(left), Box::new(right));
+    }
+    Ok(left)
+}
+
+
+/*
+    parse expression, ie. BUF+3, TABLE+2*3 or (LEN-1)<<2
+    precedence from lowest is | ^ & shifts, then + - and * / %, unary - + ~ are applied first
+    * in place of value is location of current instruction
+*/
+pub fn parse_expression(tokens: &[Token]) -> Result<Expr, Diagnostic> {
+    if tokens.len() == 0 {
+        return Err(error("missing expression".to_owned()));
+    }
+
+    let mut pos = 0;
+    let expr = match parse_level(tokens, &mut pos, 0) {
+                    Ok(expr) => expr,
+                    Err(diag) => return Err(diag)
+               };
+    if pos < tokens.len() {
+        if is_operator(&tokens[pos], ")") {
+            return Err(at_token(&tokens[pos], "missing opening parenthesis".to_owned()));
+        }
+        return Err(at_token(&tokens[pos], format!("unexpected {} in expression", tokens[pos].text)));
+    }
+    Ok(expr)
+}
+
+
+// names of symbols used in expression, in order of appearance
+pub fn expr_symbols(expr: &Expr) -> Vec<&Token> {
+    match expr {
+        Expr::Symbol(tok) => vec![tok],
+        Expr::Unary(_, operand) => expr_symbols(operand),
+        Expr::Binary(_, left, right) => { let mut symbols = expr_symbols(left);
+                                          symbols.extend(expr_symbols(right));
+                                          symbols },
+        _ => Vec::new()
+    }
+}
+
+
+// expression uses * as location of current instruction
+pub fn uses_location(expr: &Expr) -> bool {
+    match expr {
+        Expr::Location(_) => true,
+        Expr::Unary(_, operand) => uses_location(operand),
+        Expr::Binary(_, left, right) => uses_location(left) || uses_location(right),
+        _ => false
+    }
+}
+
+
+// number and result of operation must fit in 24 bits, so WORD 16777216 is an error just like WORD 1<<24
+pub fn checked(value: i64, token: &Token) -> Result<i32, Diagnostic> {
+    if !(MIN_VALUE..=MAX_VALUE).contains(&value) {
+        let message = match token.kind {
+                            TokenKind::Number(_) => format!("number {} doesn't fit in 24 bits", token.text),
+                            _ => format!("result of {} is {}, which doesn't fit in 24 bits", token.text, value)
+                      };
+        return Err(with_hint(at_token(token, message), "values must be in interval [-8388608, 16777215]"));
+    }
+    Ok(value as i32)
+}
+
+
+fn eval_node(expr: &Expr, scope: &Scope) -> Result<Term, Diagnostic> {
+    match expr {
+        Expr::Number(value, _) | Expr::Char(value, _) => Ok(Term{value: *value, relative: 0, refs: Vec::new()}),
+        Expr::Location(_) => Ok(Term{value: scope.loc as i32, relative: 1, refs: Vec::new()}),
+        Expr::Symbol(tok) => {
+            let name = &tok.text;
+            if scope.symtab.contains_key(name) {
+                let relative = if scope.absolute.contains(name) { 0 } else { 1 };
+                Ok(Term{value: scope.symtab[name], relative: relative, refs: Vec::new()})
+            }
+            else if scope.extref.contains(name) {
+                Ok(Term{value: 0, relative: 0, refs: vec![('+', name.to_owned())]})
+            }
+            else {
+                Err(Diagnostic{span: Some((tok.column, tok.column + name.len())), ..undefined_symbol(name, scope.symtab, scope.extref)})
+            }
+        },
+        Expr::Unary(op, operand) => {
+            let term = match eval_node(operand, scope) {
+                            Ok(term) => term,
+                            Err(diag) => return Err(diag)
+                       };
+            match op.text.as_str() {
+                "+" => Ok(term),
+                "-" => { let refs = term.refs.into_iter().map(|(sign, name)| (if sign == '+' { '-' } else { '+' }, name)).collect();
+                         match checked(-(term.value as i64), op) {
+                            Ok(value) => Ok(Term{value: value, relative: -term.relative, refs: refs}),
+                            Err(diag) => Err(diag)
+                         } },
+                _ => match only_absolute(op, &term, &term) {
+                        Ok(_) => Ok(Term{value: !term.value, relative: 0, refs: Vec::new()}),
+                        Err(diag) => Err(diag)
+                     }
+            }
+        },
+        Expr::Binary(op, left, right) => {
+            let left = match eval_node(left, scope) {
+                            Ok(term) => term,
+                            Err(diag) => return Err(diag)
+                       };
+            let right = match eval_node(right, scope) {
+                            Ok(term) => term,
+                            Err(diag) => return Err(diag)
+                        };
+            eval_binary(op, left, right)
+        }
+    }
+}
+
+
+// only + and - can be used with relative values and external references
+fn only_absolute(op: &Token, left: &Term, right: &Term) -> Result<u8, Diagnostic> {
+    // external reference is resolved by loader which can only add its address
+    match left.refs.iter().chain(right.refs.iter()).next() {
+        Some((_, name)) => return Err(with_hint(at_token(op, format!("External reference {} can only be added or subtracted", name)),
+                                                "loader adds address of external reference, so it can't be used with other operators")),
+        None => ()
+    }
+    if left.relative != 0 || right.relative != 0 {
+        return Err(with_hint(at_token(op, format!("relative value can't be used with {}", op.text)),
+                             "addresses change when program is loaded elsewhere, only their difference stays the same"));
+    }
+    Ok(1)
+}
+
+
+fn eval_binary(op: &Token, left: Term, right: Term) -> Result<Term, Diagnostic> {
+    if op.text == "+" || op.text == "-" {
+        let sign = if op.text == "+" { '+' } else { '-' };
+        let (value, relative) = if sign == '+' {
+                                    (left.value as i64 + right.value as i64, left.relative + right.relative)
+                                }
+                                else {
+                                    (left.value as i64 - right.value as i64, left.relative - right.relative)
+                                };
+        let mut refs = left.refs;
+        for (ref_sign, name) in right.refs {
+            refs.push((if ref_sign == sign { '+' } else { '-' }, name));
+        }
+        return match checked(value, op) {
+                    Ok(value) => Ok(Term{value: value, relative: relative, refs: refs}),
+                    Err(diag) => Err(diag)
+               };
+    }
+
+    match only_absolute(op, &left, &right) {
+        Ok(_) => (),
+        Err(diag) => return Err(diag)
+    }
+    let (a, b) = (left.value as i64, right.value as i64);
+    let value = match op.text.as_str() {
+                    "*" => a * b,
+                    "/" | "%" if b == 0 => return Err(at_token(op, "Division by zero".to_owned())),
+                    "/" => a / b,
+                    "%" => a % b,
+                    "&" => a & b,
+                    "|" => a | b,
+                    "^" => a ^ b,
+                    _ => { // shifts, word has 24 bits
+                           if !(0..=24).contains(&b) {
+                               return Err(at_token(op, format!("shift by {} is not in interval [0, 24]", b)));
+                           }
+                           if op.text == "<<" { a << b } else { a >> b } }
+                };
+    match checked(value, op) {
+        Ok(value) => Ok(Term{value: value, relative: 0, refs: Vec::new()}),
+        Err(diag) => Err(diag)
+    }
+}
+
+
+// expression is either absolute or relative, span covers whole expression
+fn expression_kind(tokens: &[Token], relative: i32) -> Result<ValueKind, Diagnostic> {
+    match relative {
+        0 => Ok(ValueKind::Absolute),
+        1 => Ok(ValueKind::Relative),
+        _ => { let first = &tokens[0];
+               let last = &tokens[tokens.len()-1];
+               let diag = token_error(&tokens_text(tokens), format!("{} is neither absolute nor relative", tokens_text(tokens)));
+               Err(with_hint(Diagnostic{span: Some((first.column, last.column + last.text.chars().count())), ..diag},
+                             "relative values can't be added together, but one can be subtracted from another")) }
+    }
+}
+
+
+// evaluate expression, ie. BUF+3, TABLE+2*3 or LEN-1, external references count as 0 and are returned with their sign
+pub fn eval_expr(tokens: &[Token], scope: &Scope) -> Result<Value, Diagnostic> {
+    let expr = match parse_expression(tokens) {
+                    Ok(expr) => expr,
+                    Err(diag) => return Err(diag)
+               };
+    let term = match eval_node(&expr, scope) {
+                    Ok(term) => term,
+                    Err(diag) => return Err(diag)
+               };
+    let kind = match expression_kind(tokens, term.relative) {
+                    Ok(kind) => kind,
+                    Err(diag) => return Err(diag)
+               };
+    Ok(Value{value: term.value, kind: kind, refs: term.refs.into_iter().map(|(sign, name)| format!("{}{}", sign, name)).collect()})
+}
+
+
+// scope without any symbols, used for values that must be known before program, ie. address of START
+pub fn eval_constant(tokens: &[Token]) -> Result<i32, Diagnostic> {
+    let symtab = HashMap::new();
+    let absolute = HashSet::new();
+    let extref = Vec::new();
+    match eval_expr(tokens, &Scope{symtab: &symtab, absolute: &absolute, extref: &extref, loc: 0}) {
+        Ok(value) => Ok(value.value),
+        Err(diag) => Err(diag)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::tokenize;
+
+    // tokens of expression, written as value of WORD so words in it are symbols
+    fn value(expr: &str) -> Vec<Token> {
+        tokenize(&format!("WORD {}", expr)).unwrap()[1..].to_vec()
+    }
+
+    fn constant(expr: &str) -> Result<i32, Diagnostic> {
+        eval_constant(&value(expr))
+    }
+
+    // BUF and LOOP are addresses, LEN is absolute and EXT is in other section
+    fn eval(expr: &str, loc: usize) -> Result<Value, Diagnostic> {
+        let symtab: HashMap<String, i32> = HashMap::from([("BUF".to_owned(), 0x100), ("LOOP".to_owned(), 0x10), ("LEN".to_owned(), 20)]);
+        let absolute: HashSet<String> = HashSet::from(["LEN".to_owned()]);
+        let extref = vec!["EXT".to_owned()];
+        eval_expr(&value(expr), &Scope{symtab: &symtab, absolute: &absolute, extref: &extref, loc: loc})
+    }
+
+    #[test]
+    fn precedence_and_parentheses() {
+        assert_eq!(constant("2+3*4").unwrap(), 14);
+        assert_eq!(constant("(2+3)*4").unwrap(), 20);
+        assert_eq!(constant("10-4-3").unwrap(), 3);
+        assert_eq!(constant("1<<3|1").unwrap(), 9);
+        assert_eq!(constant("6&3^1").unwrap(), 3);
+        assert_eq!(constant("17%5").unwrap(), 2);
+    }
+
+    #[test]
+    fn unary_operators() {
+        assert_eq!(constant("-5+2").unwrap(), -3);
+        assert_eq!(constant("+7").unwrap(), 7);
+        assert_eq!(constant("-(2*3)").unwrap(), -6);
+        assert_eq!(constant("~0&0xFF").unwrap(), 255);
+    }
+
+    #[test]
+    fn char_constants_are_values() {
+        assert_eq!(constant("C'A'+1").unwrap(), 66);
+        assert_eq!(constant("C'AB'").unwrap(), 0x4142);
+    }
+
+    #[test]
+    fn results_must_fit_in_24_bits() {
+        assert_eq!(constant("16777215").unwrap(), 16777215);
+        assert_eq!(constant("-8388608").unwrap(), -8388608);
+        assert_eq!(constant("16777215+1").unwrap_err().message, "result of + is 16777216, which doesn't fit in 24 bits");
+        assert!(constant("1<<24").is_err());
+        assert!(constant("-8388609").is_err());
+        assert!(constant("1<<25").is_err());
+    }
+
+    #[test]
+    fn division_by_zero_is_error() {
+        let diag = constant("7/(3-3)").unwrap_err();
+        assert_eq!(diag.message, "Division by zero");
+        assert_eq!(diag.span, Some((7, 8)));
+        assert!(constant("7%0").is_err());
+    }
+
+    #[test]
+    fn malformed_expressions() {
+        assert!(constant("(1").is_err());
+        assert!(constant("1+").is_err());
+        assert!(constant("1 2").is_err());
+        assert!(parse_expression(&[]).is_err());
+    }
+
+    #[test]
+    fn relative_and_absolute_values() {
+        let buf = eval("BUF+3", 0).unwrap();
+        assert_eq!((buf.value, buf.kind), (0x103, ValueKind::Relative));
+        let diff = eval("BUF-LOOP", 0).unwrap();
+        assert_eq!((diff.value, diff.kind), (0xF0, ValueKind::Absolute));
+        let len = eval("LEN*2", 0).unwrap();
+        assert_eq!((len.value, len.kind), (40, ValueKind::Absolute));
+        let here = eval("*-LOOP", 0x30).unwrap();
+        assert_eq!((here.value, here.kind), (0x20, ValueKind::Absolute));
+
+        assert!(eval("BUF+LOOP", 0).is_err());
+        assert!(eval("BUF*2", 0).is_err());
+    }
+
+    #[test]
+    fn external_references_keep_their_sign() {
+        let ext = eval("EXT+4", 0).unwrap();
+        assert_eq!((ext.value, ext.refs), (4, vec!["+EXT".to_owned()]));
+        let neg = eval("LEN-EXT", 0).unwrap();
+        assert_eq!((neg.value, neg.refs), (20, vec!["-EXT".to_owned()]));
+        assert!(eval("EXT*2", 0).is_err());
+    }
+
+    #[test]
+    fn undefined_symbol_suggests_similar_one() {
+        let diag = eval("LOPP+1", 0).unwrap_err();
+        assert_eq!(diag.message, "Symbol LOPP does not appear as a left label");
+        assert_eq!(diag.suggestion, Some("LOOP".to_owned()));
+        assert_eq!(diag.span, Some((6, 10)));
+    }
+
+    #[test]
+    fn symbols_and_location_of_expression() {
+        let expr = parse_expression(&value("BUF+LEN*(EXT-BUF)")).unwrap();
+        assert_eq!(expr_symbols(&expr).iter().map(|tok| tok.text.as_str()).collect::<Vec<&str>>(), vec!["BUF", "LEN", "EXT", "BUF"]);
+        assert!(!uses_location(&expr));
+        assert!(uses_location(&parse_expression(&value("*+3")).unwrap()));
+    }
+}
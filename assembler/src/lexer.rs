@@ -0,0 +1,343 @@
+use crate::commands::{is_instr, is_directive};
+use crate::parser::parse_num;
+use crate::expressions::checked;
+use crate::diagnostics::{Diagnostic, at_token, with_hint};
+
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TokenKind {
+    Label,
+    // instruction, extended format has + in front of it, ie. +LDA
+    Mnemonic,
+    Directive,
+    Register,
+    Number(i32),
+    // decimal number with point or exponent, ie. 3.14159, 1.5E-3 or 1E-3
+    Float,
+    // name in operands, also word in place of instruction that isn't one
+    Symbol,
+    // C'...'
+    Char,
+    // X'...'
+    Hex,
+    // + - * / % & | ^ ~ ( ), << and >> are < and >, * is also current location
+    Operator(char),
+    Comma,
+    // # immediate, @ indirect and = literal
+    Prefix(char),
+    Comment
+}
+
+
+#[derive(Clone, Debug)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub text: String,
+    // column of first character in line, first column is 1
+    pub column: usize
+}
+
+
+// expressions have no comparisons, shown when one is used
+pub const COMPARISON_HINT: &str = "IF assembles its lines when value of expression isn't 0, shifts are written << and >>";
+
+
+const REGISTERS: [&str; 7] = ["A", "X", "L", "B", "S", "T", "F"];
+
+
+fn token(kind: TokenKind, chars: &Vec<char>, start: usize, end: usize) -> Token {
+    Token{kind: kind, text: chars[start..end].iter().collect(), column: start + 1}
+}
+
+
+/*
+    split line to tokens, ie. LOOP +LDA BUF , X . comment
+    gives label, mnemonic, symbol, comma, register and comment
+
+    first word is label when it isn't instruction or directive, word after label is in place of instruction
+    even when it isn't one, so caller can report it
+    comment starts at . outside of quotes, it doesn't need space in front of it
+*/
+pub fn tokenize(line: &str) -> Result<Vec<Token>, Diagnostic> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut tokens: Vec<Token> = Vec::new();
+    // instruction or directive was already read, so words are operands
+    let mut operation_read = false;
+
+    let mut i = 0;
+    while i < chars.len() {
+        let ch = chars[i];
+        let start = i;
+
+        if ch.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        if ch == '.' {
+            tokens.push(token(TokenKind::Comment, &chars, start, chars.len()));
+            break;
+        }
+
+        // char and hex constants can contain anything up to closing apostrophe, including spaces
+        // two apostrophes inside char constant are apostrophe, not its end, ie. C'IT''S'
+        if (ch == 'C' || ch == 'X') && chars.get(i + 1) == Some(&'\'') {
+            let mut end = i + 2;
+            loop {
+                match chars.get(end) {
+                    Some('\'') if ch == 'C' && chars.get(end + 1) == Some(&'\'') => end += 2,
+                    Some('\'') => break,
+                    Some(_) => end += 1,
+                    None => return Err(at_token(&token(TokenKind::Char, &chars, start, chars.len()), "missing closing apostrophe".to_owned()))
+                }
+            }
+            i = end + 1;
+            let kind = if ch == 'C' { TokenKind::Char } else { TokenKind::Hex };
+            tokens.push(token(kind, &chars, start, i));
+            continue;
+        }
+
+        // + in place of instruction marks extended format, $ is part of labels local to macro expansion, ie. $AALOOP
+        let extended = ch == '+' && !operation_read && chars.get(i + 1).is_some_and(|c| c.is_ascii_alphabetic());
+        if ch.is_ascii_alphabetic() || ch == '_' || ch == '$' || extended {
+            i += 1;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_' || chars[i] == '$') {
+                i += 1;
+            }
+            let word: String = chars[start..i].iter().collect();
+
+            let kind = if operation_read {
+                            if REGISTERS.contains(&word.as_str()) { TokenKind::Register } else { TokenKind::Symbol }
+                       }
+                       else if is_instr(&word) {
+                            operation_read = true;
+                            TokenKind::Mnemonic
+                       }
+                       else if is_directive(&word) {
+                            operation_read = true;
+                            TokenKind::Directive
+                       }
+                       else if tokens.len() == 0 {
+                            TokenKind::Label
+                       }
+                       else {
+                            operation_read = true;
+                            TokenKind::Symbol
+                       };
+            tokens.push(token(kind, &chars, start, i));
+            continue;
+        }
+
+        if ch.is_ascii_digit() {
+            // base prefix and digits, ie. 0x1F
+            while i < chars.len() && chars[i].is_ascii_alphanumeric() {
+                i += 1;
+            }
+            // decimal point followed by digit makes float, its exponent can have sign, ie. 1.5E-3
+            let fraction = chars.get(i) == Some(&'.') && chars.get(i + 1).is_some_and(|c| c.is_ascii_digit());
+            // so do decimal digits with exponent, ie. 1E400 or 1E-3, but E in 0x1E-3 is hex digit
+            let word: String = chars[start..i].iter().collect();
+            let exponent = match word.find(['E', 'e']) {
+                                Some(pos) if !word[..pos].chars().all(|c| c.is_ascii_digit()) => false,
+                                Some(pos) if pos + 1 == word.len() => matches!(chars.get(i), Some('+') | Some('-')) && chars.get(i + 1).is_some_and(|c| c.is_ascii_digit()),
+                                Some(pos) => word[pos+1..].chars().all(|c| c.is_ascii_digit()),
+                                None => false
+                           };
+            if fraction || exponent {
+                if fraction {
+                    i += 1;
+                }
+                while i < chars.len() && (chars[i].is_ascii_alphanumeric() || ((chars[i] == '+' || chars[i] == '-') && (chars[i-1] == 'E' || chars[i-1] == 'e'))) {
+                    i += 1;
+                }
+                let number = token(TokenKind::Float, &chars, start, i);
+                if number.text.parse::<f64>().is_err() {
+                    return Err(at_token(&number, format!("{} is not a number", number.text)));
+                }
+                tokens.push(number);
+                continue;
+            }
+            let number = token(TokenKind::Number(0), &chars, start, i);
+            match parse_num(&number.text) {
+                Ok(value) => match checked(value as i64, &number) {
+                                Ok(value) => tokens.push(Token{kind: TokenKind::Number(value), ..number}),
+                                Err(diag) => return Err(diag)
+                             },
+                Err(_) => return Err(at_token(&number, format!("{} is not a number", number.text)))
+            }
+            continue;
+        }
+
+        i += 1;
+        // shifts are written with two characters
+        if (ch == '<' || ch == '>') && chars.get(i) == Some(&ch) {
+            i += 1;
+            tokens.push(token(TokenKind::Operator(ch), &chars, start, i));
+            continue;
+        }
+        // single < or > is comparison, ie. LEVEL>1, <= >= and <> are too
+        if ch == '<' || ch == '>' {
+            if chars.get(i).is_some_and(|c| *c == '=' || *c == '>') {
+                i += 1;
+            }
+            let op = token(TokenKind::Operator(ch), &chars, start, i);
+            return Err(with_hint(at_token(&op, format!("comparison {} is not supported", op.text)), COMPARISON_HINT));
+        }
+        let kind = match ch {
+                        '+' | '-' | '*' | '/' | '%' | '&' | '|' | '^' | '~' | '(' | ')' => TokenKind::Operator(ch),
+                        ',' => TokenKind::Comma,
+                        '#' | '@' | '=' => TokenKind::Prefix(ch),
+                        '\'' => return Err(with_hint(at_token(&token(TokenKind::Symbol, &chars, start, i), "unexpected character '".to_owned()),
+                                                     "apostrophe inside char constant is written twice, ie. C'IT''S'")),
+                        _ => return Err(at_token(&token(TokenKind::Symbol, &chars, start, i), format!("unexpected character {}", ch)))
+                   };
+        tokens.push(token(kind, &chars, start, i));
+    }
+
+    // FLOAT with value is data directive, FLOAT alone is instruction
+    match tokens.iter().position(|tok| tok.kind == TokenKind::Mnemonic) {
+        Some(op) if tokens[op].text == "FLOAT" && tokens.get(op + 1).is_some_and(|tok| tok.kind != TokenKind::Comment) =>
+            tokens[op].kind = TokenKind::Directive,
+        _ => ()
+    }
+
+    Ok(tokens)
+}
+
+
+// tokens of line and whether lexer read all of it, otherwise they are tokens in front of the problem
+// used before lines are parsed, to find label and operation of line, parsing reports the problem
+pub fn line_tokens(text: &str) -> (Vec<Token>, bool) {
+    match tokenize(text) {
+        Ok(tokens) => (tokens, true),
+        Err(diag) => match diag.span {
+                        Some((start, _)) => (tokenize(&text.chars().take(start - 1).collect::<String>()).unwrap_or_default(), false),
+                        None => (Vec::new(), false)
+                     }
+    }
+}
+
+
+// byte position in text right after token, ie. start of arguments after name of macro
+pub fn token_end(text: &str, tok: &Token) -> usize {
+    match text.char_indices().nth(tok.column - 1 + tok.text.chars().count()) {
+        Some((pos, _)) => pos,
+        None => text.len()
+    }
+}
+
+
+// text of tokens without spaces between them, ie. BUF + 3 is BUF+3
+pub fn tokens_text(tokens: &[Token]) -> String {
+    tokens.iter().map(|tok| tok.text.as_str()).collect()
+}
+
+
+// words of line as they were written, tokens that touch each other form one word, used for listing
+pub fn words(tokens: &Vec<Token>) -> Vec<String> {
+    let mut words: Vec<String> = Vec::new();
+    let mut end = 0;
+    for tok in tokens.iter() {
+        if tok.column == end && words.len() > 0 {
+            words.last_mut().unwrap().push_str(&tok.text);
+        }
+        else {
+            words.push(tok.text.to_owned());
+        }
+        end = tok.column + tok.text.chars().count();
+    }
+    words
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(line: &str) -> Vec<TokenKind> {
+        tokenize(line).unwrap().iter().map(|tok| tok.kind).collect()
+    }
+
+    fn texts(line: &str) -> Vec<String> {
+        tokenize(line).unwrap().iter().map(|tok| tok.text.to_owned()).collect()
+    }
+
+    #[test]
+    fn label_operation_operands_and_comment() {
+        assert_eq!(kinds("LOOP +LDA BUF,X . comment"),
+                   vec![TokenKind::Label, TokenKind::Mnemonic, TokenKind::Symbol, TokenKind::Comma, TokenKind::Register, TokenKind::Comment]);
+        let tokens = tokenize("LOOP +LDA BUF,X . comment").unwrap();
+        assert_eq!(tokens[1].text, "+LDA");
+        assert_eq!(tokens.iter().map(|tok| tok.column).collect::<Vec<usize>>(), vec![1, 6, 11, 14, 15, 17]);
+    }
+
+    #[test]
+    fn line_without_label_starts_with_operation() {
+        assert_eq!(kinds("RSUB"), vec![TokenKind::Mnemonic]);
+        assert_eq!(kinds("WORD 3"), vec![TokenKind::Directive, TokenKind::Number(3)]);
+        assert_eq!(kinds("LDA #5"), vec![TokenKind::Mnemonic, TokenKind::Prefix('#'), TokenKind::Number(5)]);
+    }
+
+    #[test]
+    fn dollar_is_part_of_labels_and_symbols() {
+        assert_eq!(texts("$AALOOP J $AALOOP"), vec!["$AALOOP", "J", "$AALOOP"]);
+        assert_eq!(kinds("$AALOOP J $AALOOP"), vec![TokenKind::Label, TokenKind::Mnemonic, TokenKind::Symbol]);
+        assert_eq!(texts("L$1 J L$1"), vec!["L$1", "J", "L$1"]);
+    }
+
+    #[test]
+    fn numbers_in_all_bases() {
+        assert_eq!(kinds("WORD 42"), vec![TokenKind::Directive, TokenKind::Number(42)]);
+        assert_eq!(kinds("WORD 0x1F"), vec![TokenKind::Directive, TokenKind::Number(31)]);
+        assert_eq!(kinds("WORD 0b101"), vec![TokenKind::Directive, TokenKind::Number(5)]);
+        assert_eq!(kinds("WORD 0o17"), vec![TokenKind::Directive, TokenKind::Number(15)]);
+        assert!(tokenize("WORD 12AB").is_err());
+    }
+
+    #[test]
+    fn numbers_must_fit_in_24_bits() {
+        assert_eq!(kinds("WORD 16777215"), vec![TokenKind::Directive, TokenKind::Number(16777215)]);
+        assert!(tokenize("WORD 16777216").is_err());
+        assert!(tokenize("WORD 0x1000000").is_err());
+    }
+
+    #[test]
+    fn floats_with_fraction_or_exponent() {
+        assert_eq!(texts("FLOAT 1.5E-3"), vec!["FLOAT", "1.5E-3"]);
+        assert_eq!(kinds("FLOAT 1.5E-3"), vec![TokenKind::Directive, TokenKind::Float]);
+        assert_eq!(texts("FLOAT 1E-3"), vec!["FLOAT", "1E-3"]);
+        assert_eq!(kinds("FLOAT 1E400"), vec![TokenKind::Directive, TokenKind::Float]);
+        assert_eq!(kinds("FLOAT 2e+5"), vec![TokenKind::Directive, TokenKind::Float]);
+        // E is hex digit here, and minus is operator
+        assert_eq!(kinds("WORD 0x1E-3"), vec![TokenKind::Directive, TokenKind::Number(30), TokenKind::Operator('-'), TokenKind::Number(3)]);
+    }
+
+    #[test]
+    fn float_without_value_is_instruction() {
+        assert_eq!(kinds("FLOAT"), vec![TokenKind::Mnemonic]);
+        assert_eq!(kinds("FLOAT . A to F"), vec![TokenKind::Mnemonic, TokenKind::Comment]);
+        assert_eq!(kinds("PI FLOAT 3.14159"), vec![TokenKind::Label, TokenKind::Directive, TokenKind::Float]);
+    }
+
+    #[test]
+    fn char_and_hex_constants() {
+        assert_eq!(texts("BYTE C'IT''S', X'0A'"), vec!["BYTE", "C'IT''S'", ",", "X'0A'"]);
+        assert_eq!(kinds("BYTE C'A B', X'0A'"), vec![TokenKind::Directive, TokenKind::Char, TokenKind::Comma, TokenKind::Hex]);
+        // dot inside quotes doesn't start comment
+        assert_eq!(kinds("BYTE C'.'"), vec![TokenKind::Directive, TokenKind::Char]);
+        assert!(tokenize("BYTE C'ABC").is_err());
+    }
+
+    #[test]
+    fn operators_and_shifts() {
+        assert_eq!(texts("WORD (1<<3)|~2"), vec!["WORD", "(", "1", "<<", "3", ")", "|", "~", "2"]);
+        assert_eq!(kinds("WORD 8>>1"), vec![TokenKind::Directive, TokenKind::Number(8), TokenKind::Operator('>'), TokenKind::Number(1)]);
+        assert!(tokenize("WORD 1<2").is_err());
+    }
+
+    #[test]
+    fn errors_point_at_column() {
+        let diag = tokenize("LDA #5 ? 3").unwrap_err();
+        assert_eq!(diag.span, Some((8, 9)));
+    }
+}
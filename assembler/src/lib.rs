@@ -0,0 +1,188 @@
+// explicit returns, match on single pattern and &Vec parameters are used on purpose throughout the assembler
+#![allow(clippy::needless_return, clippy::single_match, clippy::ptr_arg, clippy::comparison_to_empty, clippy::len_zero,
+         clippy::int_plus_one, clippy::needless_late_init, clippy::redundant_field_names, clippy::needless_range_loop,
+         clippy::question_mark, clippy::result_large_err,
+         clippy::too_many_arguments)]
+
+pub mod parser;
+pub mod lexer;
+pub mod ast;
+pub mod expressions;
+pub mod symbols;
+pub mod commands;
+pub mod assemble;
+pub mod objwriter;
+pub mod equresolution;
+pub mod macros;
+pub mod conditional;
+pub mod objreader;
+pub mod linker;
+pub mod simulator;
+pub mod disassembler;
+pub mod diagnostics;
+pub mod warnings;
+pub mod render;
+
+use symbols::Section;
+use objreader::ObjSection;
+use parser::SourceLine;
+use lexer::{TokenKind, line_tokens, token_end};
+pub use diagnostics::{Diagnostic, Severity, WarningKind};
+
+
+// name of source is used in messages and to find included files next to it
+#[derive(Default, Clone)]
+pub struct AssemblyOptions {
+    pub source_name: String,
+    // directories searched for INCLUDE files, after directory of file that includes them
+    pub include_paths: Vec<String>,
+    // warnings that aren't reported, all are reported by default
+    pub disabled_warnings: Vec<WarningKind>,
+    // -Werror, warnings are reported as errors, so object isn't created
+    pub warnings_as_errors: bool,
+    // -D NAME=value, symbols defined with EQU in first section, before any line of source
+    pub defines: Vec<(String, String)>,
+    // replaces address given to START
+    pub start_address: Option<usize>
+}
+
+
+// file of lines that come from options, ie. symbols given with -D
+pub const COMMAND_LINE: &str = "<command line>";
+
+
+#[derive(Debug)]
+pub struct AssemblyOutput {
+    // name of program, given to START
+    pub name: String,
+    // H, D, R, T, M and E records of every control section
+    pub object: Vec<ObjSection>,
+    pub listing: Vec<String>,
+    // control sections with their symbol tables
+    pub sections: Vec<Section>,
+    // warnings that weren't turned off, there are no errors when assembling succeeds
+    pub diagnostics: Vec<Diagnostic>
+}
+
+
+// -D is checked like EQU before it's added to source, so its value can't turn into other words of line
+fn define_line(name: &str, value: &str) -> Result<String, Diagnostic> {
+    let line = match ast::parse_line(&format!("{} EQU {}", name, value)) {
+                    Ok(line) => line,
+                    Err(diag) => return Err(diag)
+               };
+    if !matches!(&line.label, Some(label) if label.text == name) || ast::operation(&line) != "EQU" {
+        return Err(diagnostics::error(format!("{} can't be used as name of symbol", name)));
+    }
+    if line.comment.is_some() {
+        return Err(diagnostics::error("value can only be a number or expression".to_owned()));
+    }
+    match ast::directive_value(&line) {
+        Ok(value) => match expressions::parse_expression(value) {
+                        Ok(_) => Ok(format!("{} EQU {}", name, lexer::tokens_text(value))),
+                        Err(diag) => Err(diag)
+                     },
+        Err(diag) => Err(diag)
+    }
+}
+
+
+// START address and defined symbols are applied to source, as if they were written in it, wrong defines are returned as errors
+fn apply_options(program: &mut Vec<SourceLine>, options: &AssemblyOptions) -> Vec<Diagnostic> {
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+    let st_index = match program.iter().position(|src| src.text != "" && !src.text.starts_with(".")) {
+                        Some(index) => index,
+                        // first pass reports missing START
+                        None => return diagnostics
+                   };
+
+    // address after START is replaced, label stays as it is
+    let text = &program[st_index].text;
+    let start = line_tokens(text).0.into_iter().nth(1).filter(|tok| tok.kind == TokenKind::Directive && tok.text == "START");
+    match (options.start_address, start) {
+        (Some(addr), Some(start)) => program[st_index] = parser::derived_line(format!("{} 0x{:X}", &text[..token_end(text, &start)], addr), &program[st_index], false, false),
+        _ => ()
+    }
+
+    let mut index = st_index + 1;
+    for (name, value) in options.defines.iter() {
+        let text = match define_line(name, value) {
+                        Ok(text) => text,
+                        Err(diag) => { diagnostics.push(Diagnostic{file: COMMAND_LINE.to_owned(), line: 0, span: None, source: String::new(),
+                                                                   message: format!("-D {}={}: {}", name, value, diag.message), ..diag});
+                                       // valid name is still defined, so its uses aren't reported too
+                                       match define_line(name, "0") {
+                                           Ok(text) => text,
+                                           Err(_) => continue
+                                       } }
+                   };
+        program.insert(index, SourceLine{text: text, raw: String::new(), columns: Vec::new(), file: COMMAND_LINE.to_owned(), line: 0,
+                                         expanded: false, skipped: false});
+        index += 1;
+    }
+    diagnostics
+}
+
+
+/*
+    assemble source to object code and listing, nothing is written to files
+    both passes continue after lines with errors, so all of them are returned at once
+*/
+pub fn assemble(source: &str, options: &AssemblyOptions) -> Result<AssemblyOutput, Vec<Diagnostic>> {
+    let mut asm_source;
+    match parser::read_asm_source(source, &options.source_name, &options.include_paths) {
+        Ok(value) => asm_source = value,
+        Err(diag) => return Err(vec![diag])
+    }
+    let mut diagnostics = apply_options(&mut asm_source, options);
+
+    // macros are expanded before first pass
+    let asm_expanded = macros::expand_macros(&asm_source, &mut diagnostics);
+
+    // then conditional assembly decides which lines are assembled
+    let asm_in = conditional::resolve_conditionals(&asm_expanded, &mut diagnostics);
+
+    // every line is parsed once, both passes work on the same lines
+    let lines = ast::parse_program(&asm_in, &mut diagnostics);
+
+    // first pass
+    let sections;
+    match symbols::get_symbol_table(&asm_in, &lines, &mut diagnostics) {
+        Ok(value) => sections = value,
+        Err(diag) => { diagnostics.push(diag);
+                       return Err(diagnostics); }
+    }
+
+    // second pass
+    let obj_lst = assemble::generate_obj(&asm_in, &lines, &sections, &mut diagnostics);
+    if !diagnostics::has_errors(&diagnostics) {
+        diagnostics.extend(warnings::check_warnings(&asm_in, &lines, &sections));
+    }
+
+    diagnostics.retain(|diag| match diag.warning {
+                                Some(kind) => !options.disabled_warnings.contains(&kind),
+                                None => true
+                              });
+    if options.warnings_as_errors {
+        for diag in diagnostics.iter_mut() {
+            diag.severity = Severity::Error;
+        }
+    }
+    // passes report in their own order, but problems are easier to fix from top to bottom
+    diagnostics.sort_by(|a, b| (&a.file, a.line).cmp(&(&b.file, b.line)));
+
+    match obj_lst {
+        Ok((object, listing)) => if diagnostics::has_errors(&diagnostics) {
+                                    Err(diagnostics)
+                                 }
+                                 else {
+                                    Ok(AssemblyOutput{name: sections[0].name.to_owned(),
+                                                      object: object,
+                                                      listing: listing.lines().map(|line| line.to_owned()).collect(),
+                                                      sections: sections,
+                                                      diagnostics: diagnostics})
+                                 },
+        Err(diag) => { diagnostics.insert(0, diag);
+                       Err(diagnostics) }
+    }
+}
@@ -0,0 +1,211 @@
+use crate::objreader::{read_obj_file, ObjSection, ObjModification};
+use crate::objwriter::{write_obj_header, write_obj_text, write_obj_end};
+use std::collections::HashMap;
+
+
+/*
+    linking loader, combines control sections of one or more .obj files
+
+    sections are placed one after another starting at load address, in order of files and sections inside them
+    external symbol table (ESTAB) holds names of sections and symbols from D records
+    M records without symbol are relative to their own section, others add or subtract external symbol
+*/
+pub struct LinkedProgram {
+    pub name: String,
+    pub start: usize,
+    pub length: usize,
+    // memory from start to start + length, defined marks bytes written by text records
+    pub memory: Vec<u8>,
+    pub defined: Vec<bool>,
+    pub entry: usize,
+    // load address of every control section
+    pub sections: Vec<(String, usize, usize)>,
+    pub estab: Vec<(String, usize)>
+}
+
+
+// addresses are 24 bits wide, like everything else in SIC/XE
+const ADDRESS_LIMIT: usize = 1 << 24;
+
+
+// add external symbol, each name can be defined only once in whole program
+fn add_external(estab: &mut HashMap<String, usize>, order: &mut Vec<(String, usize)>, name: &str, addr: usize, file: &str) -> Result<u8, String> {
+    if estab.contains_key(name) {
+        return Err(format!("{}, duplicate external symbol {}", file, name));
+    }
+    estab.insert(name.to_owned(), addr);
+    order.push((name.to_owned(), addr));
+    Ok(1)
+}
+
+
+// add value to field of given length in half-bytes, which ends at last half-byte of its bytes
+fn modify_field(memory: &mut Vec<u8>, index: usize, length: usize, value: i64) -> Result<u8, String> {
+    let bytes = length.div_ceil(2);
+    if length == 0 || length > 8 || index + bytes > memory.len() {
+        return Err("modification record is outside of program".to_owned());
+    }
+
+    let mut field: i64 = 0;
+    for byte in memory[index..index+bytes].iter() {
+        field = (field << 8) | *byte as i64;
+    }
+    let mask: i64 = (1 << (length * 4)) - 1;
+    field = (field & !mask) | ((field + value) & mask);
+
+    for i in (0..bytes).rev() {
+        memory[index + i] = (field & 0xFF) as u8;
+        field >>= 8;
+    }
+    Ok(1)
+}
+
+
+// where modification record is in messages, sections made by assembler aren't read from file, so they have no line
+fn record_location(modif: &ObjModification, file: &str) -> String {
+    if modif.line == 0 { file.to_owned() } else { format!("Line {} in {}", modif.line, file) }
+}
+
+
+// link sections of given object files, first section is loaded at load address or its own start when it isn't given
+pub fn link(files: &Vec<String>, load_addr: Option<usize>) -> Result<LinkedProgram, String> {
+    let mut modules: Vec<(String, Vec<ObjSection>)> = Vec::new();
+    for file in files.iter() {
+        match read_obj_file(file) {
+            Ok(sections) => modules.push((file.to_owned(), sections)),
+            Err(msg) => return Err(msg)
+        }
+    }
+    link_modules(&modules, load_addr)
+}
+
+
+// link sections that were already read, each module has name of its file and its sections
+pub fn link_modules(modules: &Vec<(String, Vec<ObjSection>)>, load_addr: Option<usize>) -> Result<LinkedProgram, String> {
+    if modules.len() == 0 {
+        return Err("No object files to link".to_owned());
+    }
+
+    // pass 1, assign addresses to sections and build ESTAB
+    let start = match load_addr {
+                    Some(addr) => addr,
+                    None => modules[0].1[0].start
+                };
+    let mut estab: HashMap<String, usize> = HashMap::new();
+    let mut estab_order: Vec<(String, usize)> = Vec::new();
+    let mut placed: Vec<(String, usize, usize)> = Vec::new();
+    let mut cs_addr = start;
+
+    for (file, sections) in modules.iter() {
+        for section in sections.iter() {
+            match add_external(&mut estab, &mut estab_order, &section.name, cs_addr, file) {
+                Ok(_) => (),
+                Err(msg) => return Err(msg)
+            }
+            for (name, addr) in section.defs.iter() {
+                if *addr < section.start || *addr > section.start + section.length {
+                    return Err(format!("{}, symbol {} is outside of section {}", file, name, section.name));
+                }
+                match add_external(&mut estab, &mut estab_order, name, addr - section.start + cs_addr, file) {
+                    Ok(_) => (),
+                    Err(msg) => return Err(msg)
+                }
+            }
+            placed.push((section.name.to_owned(), cs_addr, section.length));
+            cs_addr += section.length;
+        }
+    }
+    if cs_addr > ADDRESS_LIMIT {
+        return Err(format!("Linked program ends at {:X}, which is outside of memory", cs_addr));
+    }
+
+    // pass 2, copy text records to memory and apply modification records
+    let length = cs_addr - start;
+    let mut memory = vec![0; length];
+    let mut defined = vec![false; length];
+    let mut entry: Option<usize> = None;
+    let mut sect = 0;
+
+    for (file, sections) in modules.iter() {
+        for section in sections.iter() {
+            let cs_addr = placed[sect].1;
+            sect += 1;
+
+            for refer in section.refs.iter() {
+                if !estab.contains_key(refer) {
+                    return Err(format!("{}, undefined external symbol {} referenced in section {}", file, refer, section.name));
+                }
+            }
+
+            for (addr, bytes) in section.texts.iter() {
+                if *addr < section.start || addr + bytes.len() > section.start + section.length {
+                    return Err(format!("{}, text record at {:06X} is outside of section {}", file, addr, section.name));
+                }
+                let index = addr - section.start + cs_addr - start;
+                memory[index..index+bytes.len()].copy_from_slice(bytes);
+                for i in index..index+bytes.len() {
+                    defined[i] = true;
+                }
+            }
+
+            for modif in section.mods.iter() {
+                let value = match &modif.symbol {
+                                None => cs_addr as i64 - section.start as i64,
+                                Some((sign, name)) => match estab.get(name) {
+                                                        Some(addr) => if *sign == '-' { -(*addr as i64) } else { *addr as i64 },
+                                                        None => return Err(format!("{}, undefined external symbol {}", record_location(modif, file), name))
+                                                      }
+                            };
+                if modif.address < section.start {
+                    return Err(format!("{}, modification record is outside of section {}", record_location(modif, file), section.name));
+                }
+                match modify_field(&mut memory, modif.address - section.start + cs_addr - start, modif.length, value) {
+                    Ok(_) => (),
+                    Err(msg) => return Err(format!("{}, {}", record_location(modif, file), msg))
+                }
+            }
+
+            // execution starts at first end record with address
+            if entry.is_none() {
+                entry = section.entry.map(|addr| addr + cs_addr - section.start);
+            }
+        }
+    }
+
+    Ok(LinkedProgram{name: modules[0].1[0].name.to_owned(), start: start, length: length, memory: memory, defined: defined,
+                     entry: entry.unwrap_or(start), sections: placed, estab: estab_order})
+}
+
+
+// linked program as absolute object file, without D, R and M records
+pub fn linked_obj(program: &LinkedProgram) -> String {
+    let mut obj_file = String::new();
+    write_obj_header(&mut obj_file, program.name.to_owned(), program.start, program.length);
+
+    // text records cover only bytes that were defined, at most 30 bytes each
+    let mut i = 0;
+    while i < program.length {
+        if !program.defined[i] {
+            i += 1;
+            continue;
+        }
+        let mut end = i;
+        while end < program.length && end - i < 30 && program.defined[end] {
+            end += 1;
+        }
+        let code: String = program.memory[i..end].iter().map(|byte| format!("{:02X}", byte)).collect();
+        write_obj_text(&mut obj_file, program.start + i, end - i, code);
+        i = end;
+    }
+
+    write_obj_end(&mut obj_file, Some(program.entry));
+    obj_file
+}
+
+
+// load map is estab in order of sections, each followed by its symbols, only sections have length
+pub fn load_map(program: &LinkedProgram) -> Vec<(String, usize, Option<usize>)> {
+    program.estab.iter()
+                 .map(|(name, addr)| (name.to_owned(), *addr, program.sections.iter().find(|(sect, _, _)| sect == name).map(|(_, _, length)| *length)))
+                 .collect()
+}
@@ -0,0 +1,356 @@
+use crate::parser::{derived_line, SourceLine};
+use crate::lexer::{Token, TokenKind, line_tokens, token_end};
+use crate::diagnostics::{Diagnostic, token_error, at_token, locate, line_error, line_has_error};
+use std::collections::HashMap;
+
+
+/*
+    macro processor, expands macros before first pass
+
+    NAME    MACRO   &POS,&KEY=default
+    $LOOP   LDA     &POS            . labels starting with $ are unique for each expansion
+            MEND
+
+    macro is called with positional and keyword arguments: NAME BUF,KEY=42
+*/
+struct Macro {
+    // parameter names without &, in order of definition
+    params: Vec<String>,
+    // default values, positional parameters default to empty string
+    defaults: Vec<String>,
+    body: Vec<String>
+}
+
+
+// nested calls deeper than this are most likely macro calling itself
+const MAX_DEPTH: usize = 64;
+
+
+// split arguments by commas, but not inside apostrophes (C'A,B'), stop at comment
+fn split_args(args: &str) -> Vec<String> {
+    let mut result: Vec<String> = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut prev = ' ';
+
+    for ch in args.chars() {
+        if ch == '\'' {
+            in_quotes = !in_quotes;
+        }
+        if !in_quotes && ch == '.' && prev == ' ' {
+            break;
+        }
+        if !in_quotes && ch == ',' {
+            result.push(current.trim().to_owned());
+            current = String::new();
+        }
+        else {
+            current.push(ch);
+        }
+        prev = ch;
+    }
+    if current.trim() != "" || result.len() > 0 {
+        result.push(current.trim().to_owned());
+    }
+    result
+}
+
+
+// names of parameters used in line, ie. &BUF
+fn used_params(line: &str) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    let chars: Vec<char> = line.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i] == '&' {
+            let mut name = String::new();
+            i += 1;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                name.push(chars[i]);
+                i += 1;
+            }
+            names.push(name);
+        }
+        else {
+            i += 1;
+        }
+    }
+    names
+}
+
+
+// replace parameters in line with values of arguments
+fn substitute(line: &str, params: &Vec<String>, values: &Vec<String>) -> String {
+    let mut result = String::new();
+    let chars: Vec<char> = line.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i] == '&' {
+            let mut name = String::new();
+            i += 1;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                name.push(chars[i]);
+                i += 1;
+            }
+            match params.iter().position(|param| *param == name) {
+                Some(index) => result.push_str(&values[index]),
+                None => { result.push('&'); result.push_str(&name); }
+            }
+        }
+        else {
+            result.push(chars[i]);
+            i += 1;
+        }
+    }
+    result
+}
+
+
+// id of expansion made from letters, 1 -> AA, 2 -> AB, ...
+fn unique_id(count: usize) -> String {
+    let mut id = String::new();
+    let mut n = count - 1;
+    while id.len() < 2 || n > 0 {
+        id.insert(0, (b'A' + (n % 26) as u8) as char);
+        n /= 26;
+    }
+    id
+}
+
+
+// $ starts label that is local to expansion, so $LOOP becomes $AALOOP, $ABLOOP, ...
+fn make_unique(line: &str, id: &str) -> String {
+    let mut result = String::new();
+    let mut in_quotes = false;
+    for ch in line.chars() {
+        if ch == '\'' {
+            in_quotes = !in_quotes;
+        }
+        result.push(ch);
+        if ch == '$' && !in_quotes {
+            result.push_str(id);
+        }
+    }
+    result
+}
+
+
+// first two words of line, ie. label and operation, or operation and its first operand, comment has none
+fn first_words(text: &str) -> Vec<Token> {
+    line_tokens(text).0.into_iter().filter(|tok| tok.kind != TokenKind::Comment).take(2).collect()
+}
+
+
+// word is text of token, first and second word of line are checked for macro names, MACRO and MEND
+fn is_word(words: &Vec<Token>, index: usize, text: &str) -> bool {
+    words.get(index).is_some_and(|word| word.text == text)
+}
+
+
+// error at word found at byte position of line, for words that can appear in line more than once
+fn error_at(src: &SourceLine, pos: usize, word: &str, message: String) -> Diagnostic {
+    let col = src.text[..pos].chars().count() + 1;
+    locate(Diagnostic{span: Some((col, col + word.chars().count())), ..token_error(word, message)}, src)
+}
+
+
+// read definition starting at line index, returns macro and index of line after MEND
+// wrong parameters and body lines are reported and left out, so calls of macro can still be expanded
+fn read_definition(program: &Vec<SourceLine>, index: usize, diagnostics: &mut Vec<Diagnostic>) -> (Macro, usize) {
+    let text = &program[index].text;
+    // line is <name> MACRO <parameters>
+    let words = first_words(text);
+    let name = &words[0];
+
+    let mut params: Vec<String> = Vec::new();
+    let mut defaults: Vec<String> = Vec::new();
+    // parameters are searched in line one after another, so duplicate is found at its second appearance
+    let mut search = token_end(text, &words[1]);
+    for param in split_args(&text[search..]) {
+        let pos = match text[search..].find(&param) {
+                    Some(pos) => search + pos,
+                    None => search
+                  };
+        search = pos + param.len();
+        if !param.starts_with("&") || param.len() < 2 {
+            diagnostics.push(error_at(&program[index], pos, &param, format!("macro parameters must start with &, {} doesn't", param)));
+            continue;
+        }
+        // keyword parameter has default value after =, positional one doesn't
+        let (name, default) = match param.find('=') {
+            Some(pos) => (param[1..pos].to_owned(), param[pos+1..].to_owned()),
+            None => (param[1..].to_owned(), String::new())
+        };
+        if params.contains(&name) {
+            diagnostics.push(error_at(&program[index], pos, &param, format!("duplicate macro parameter &{}", name)));
+            continue;
+        }
+        params.push(name);
+        defaults.push(default);
+    }
+
+    let mut body: Vec<String> = Vec::new();
+    let mut i = index + 1;
+    while i < program.len() {
+        let words = first_words(&program[i].text);
+        if is_word(&words, 0, "MEND") || is_word(&words, 1, "MEND") {
+            return (Macro{params: params, defaults: defaults, body: body}, i + 1);
+        }
+        i += 1;
+        if is_word(&words, 1, "MACRO") {
+            diagnostics.push(locate(at_token(&words[1], "macro can't be defined inside another macro".to_owned()), &program[i-1]));
+            continue;
+        }
+        if !program[i-1].text.starts_with(".") {
+            match used_params(&program[i-1].text).iter().find(|name| !params.contains(name)) {
+                Some(name) => { diagnostics.push(line_error(&program[i-1], &format!("&{}", name), format!("&{} is not a parameter of macro {}", name, words[0].text)));
+                                continue; },
+                None => ()
+            }
+        }
+        body.push(program[i-1].text.to_owned());
+    }
+    // rest of program is body, there is no way to tell where macro was meant to end
+    diagnostics.push(locate(at_token(name, format!("macro {} is missing MEND", name.text)), &program[index]));
+    (Macro{params: params, defaults: defaults, body: body}, i)
+}
+
+
+// match arguments of call to parameters of macro
+fn bind_args(mac: &Macro, name: &str, args: &str) -> Result<Vec<String>, Diagnostic> {
+    let mut values = mac.defaults.clone();
+    let mut is_set = vec![false; mac.params.len()];
+    let mut position = 0;
+
+    for arg in split_args(args) {
+        // keyword argument is NAME=value or &NAME=value, literals like =X'05' are positional
+        let keyword = match arg.find('=') {
+            Some(pos) if pos > 0 => Some((arg[..pos].trim_start_matches('&').to_owned(), arg[pos+1..].to_owned())),
+            _ => None
+        };
+
+        match keyword {
+            Some((key, value)) => match mac.params.iter().position(|param| *param == key) {
+                                    Some(index) if is_set[index] => return Err(token_error(&arg, format!("argument {} of macro {} is given more than once", key, name))),
+                                    Some(index) => { values[index] = value; is_set[index] = true; },
+                                    None => return Err(token_error(&arg, format!("macro {} has no parameter {}", name, key)))
+                                  },
+            None => { if position >= mac.params.len() {
+                          return Err(token_error(&arg, format!("too many arguments for macro {}, it takes {}", name, mac.params.len())));
+                      }
+                      values[position] = arg;
+                      is_set[position] = true;
+                      position += 1; }
+        }
+    }
+    Ok(values)
+}
+
+
+// copy line to program, or expand it if it's a macro call, generated lines get location of call
+// call that can't be expanded is kept as comment, only its label is defined
+fn expand_line(text: &str, origin: &SourceLine, expanded: bool, macros: &HashMap<String, Macro>, program: &mut Vec<SourceLine>, count: &mut usize, depth: usize,
+               diagnostics: &mut Vec<Diagnostic>) {
+    let words = first_words(text);
+
+    // macro call is either <macro> <args> or <label> <macro> <args>
+    let (mut label, name) = if words.len() > 0 && macros.contains_key(&words[0].text) {
+                                ("", &words[0])
+                            }
+                            else if words.len() > 1 && macros.contains_key(&words[1].text) {
+                                (words[0].text.as_str(), &words[1])
+                            }
+                            else {
+                                    program.push(derived_line(text.to_owned(), origin, expanded, false));
+                                    return;
+                                  };
+
+    // call itself is kept as comment, so listing shows where expansion came from
+    program.push(derived_line(format!(".{}", text), origin, expanded, false));
+
+    let mac = &macros[&name.text];
+    let values = if depth >= MAX_DEPTH {
+                    Err(line_error(origin, &name.text, format!("macro {} is nested too deep, does it call itself?", name.text)))
+                 }
+                 else {
+                    bind_args(mac, &name.text, &text[token_end(text, name)..]).map_err(|diag| locate(diag, origin))
+                 };
+    let values = match values {
+                    Ok(values) => values,
+                    Err(diag) => { diagnostics.push(diag);
+                                   if label != "" {
+                                       program.push(derived_line(format!("{} .", label), origin, true, false));
+                                   }
+                                   return; }
+                 };
+    *count += 1;
+    let id = unique_id(*count);
+
+    for body_line in mac.body.iter() {
+        // unique labels first, so $ in arguments belongs to caller
+        let mut generated = substitute(&make_unique(body_line, &id), &mac.params, &values);
+
+        // label of call is given to first generated statement
+        if label != "" && generated != "" && !generated.starts_with(".") {
+            let statement = match first_words(&generated).first() {
+                                Some(first) => first.kind == TokenKind::Mnemonic || first.kind == TokenKind::Directive || macros.contains_key(&first.text),
+                                None => false
+                            };
+            if statement {
+                generated = format!("{} {}", label, generated);
+            }
+            else {
+                // statement already has a label, so label of call gets its own line
+                program.push(derived_line(format!("{} .", label), origin, true, false));
+            }
+            label = "";
+        }
+
+        expand_line(&generated, origin, true, macros, program, count, depth + 1, diagnostics);
+        // every generated line has location of call, so error in nested call is already reported for this call
+        if line_has_error(diagnostics, origin) {
+            break;
+        }
+    }
+    if label != "" {
+        // macro without statements still defines label of call
+        program.push(derived_line(format!("{} .", label), origin, true, false));
+    }
+}
+
+
+// remove macro definitions and replace every call with body of macro
+// errors are added to diagnostics and expansion continues with next line
+pub fn expand_macros(program: &Vec<SourceLine>, diagnostics: &mut Vec<Diagnostic>) -> Vec<SourceLine> {
+    let mut macros: HashMap<String, Macro> = HashMap::new();
+    let mut expanded: Vec<SourceLine> = Vec::new();
+
+    // number of expansions so far, used to make unique labels
+    let mut count = 0;
+
+    let mut i = 0;
+    while i < program.len() {
+        let words = first_words(&program[i].text);
+
+        if is_word(&words, 1, "MACRO") {
+            let (mac, next) = read_definition(program, i, diagnostics);
+            // body is still read, so it isn't assembled as part of program
+            let name = &words[0];
+            if name.kind == TokenKind::Mnemonic || name.kind == TokenKind::Directive {
+                diagnostics.push(locate(at_token(name, format!("{} can't be used as name of macro", name.text)), &program[i]));
+            }
+            else {
+                macros.insert(name.text.to_owned(), mac);
+            }
+            i = next;
+            continue;
+        }
+        match words.first() {
+            Some(word) if word.text == "MACRO" => diagnostics.push(locate(at_token(word, "MACRO needs name of macro as label".to_owned()), &program[i])),
+            Some(word) if word.text == "MEND" => diagnostics.push(locate(at_token(word, "MEND without MACRO".to_owned()), &program[i])),
+            _ => expand_line(&program[i].text, &program[i], false, &macros, &mut expanded, &mut count, 0, diagnostics)
+        }
+        i += 1;
+    }
+    expanded
+}
@@ -1,31 +1,460 @@
-mod parser;
-mod symbols;
-mod commands;
-mod assemble;
-mod objwriter;
-mod equresolution;
+// explicit returns, match on single pattern and &Vec parameters are used on purpose throughout the assembler
+#![allow(clippy::needless_return, clippy::single_match, clippy::ptr_arg, clippy::comparison_to_empty, clippy::len_zero,
+         clippy::int_plus_one, clippy::needless_late_init, clippy::redundant_field_names, clippy::needless_range_loop,
+         clippy::question_mark)]
 
+use assembler::{parser, linker, simulator, disassembler, objwriter, AssemblyOptions, Diagnostic};
+use assembler::objreader::ObjSection;
+use assembler::diagnostics::{summary, find_warning, WARNING_NAMES};
+use assembler::render::render_diagnostic;
 use std::env;
+use std::fs;
+use std::path::Path;
+use std::collections::HashMap;
+use std::io::IsTerminal;
 
-fn main() {
-    let asm_file;
-    match env::args().nth(1) {
-        Some(file) => asm_file = file,
-        None => {println!("No asm file provided."); return;}
+
+// program couldn't be assembled, linked or run
+const EXIT_FAILURE: i32 = 1;
+// arguments are wrong
+const EXIT_USAGE: i32 = 2;
+
+
+// write lines to file, each ends with newline
+fn write_lines(filename: &str, lines: &Vec<String>) -> Result<u8, String> {
+    let mut data = String::new();
+    for line in lines.iter() {
+        data.push_str(line);
+        data.push('\n');
+    }
+    match fs::write(filename, data) {
+        Ok(_) => Ok(1),
+        Err(_) => Err(format!("Can't write file {}", filename))
+    }
+}
+
+
+// every diagnostic, followed by number of errors and warnings
+fn print_diagnostics(diagnostics: &Vec<Diagnostic>, use_color: bool) {
+    for diag in diagnostics.iter() {
+        eprintln!("{}", render_diagnostic(diag, use_color));
+    }
+    eprintln!("{}", summary(diagnostics));
+}
+
+
+// assembler link [-o <output>] [--load <address>] [--raw] <.obj files>
+fn run_linker(args: &Vec<String>) -> i32 {
+    let mut obj_files: Vec<String> = Vec::new();
+    let mut output: Option<String> = None;
+    let mut load_addr: Option<usize> = None;
+    let mut raw = false;
+
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "-o" | "--load" => { let value = match args.get(i + 1) {
+                                                Some(value) => value,
+                                                None => { eprintln!("{} needs a value.", args[i]); return EXIT_USAGE; }
+                                             };
+                                 if args[i] == "-o" {
+                                     output = Some(value.to_owned());
+                                 }
+                                 else {
+                                     match parser::parse_num(value) {
+                                         Ok(addr) if addr >= 0 => load_addr = Some(addr as usize),
+                                         _ => { eprintln!("Load address {} is not a valid number.", value); return EXIT_USAGE; }
+                                     }
+                                 }
+                                 i += 1; },
+            "--raw" => raw = true,
+            _ => obj_files.push(args[i].to_owned())
+        }
+        i += 1;
+    }
+
+    let program;
+    match linker::link(&obj_files, load_addr) {
+        Ok(value) => program = value,
+        Err(msg) => { eprintln!("Error linking: {}", msg); return EXIT_FAILURE; }
+    }
+    println!("Section  Symbol   Address  Length");
+    for (name, addr, length) in linker::load_map(&program) {
+        match length {
+            Some(length) => println!("{:<9}         {:06X}   {:06X}", name, addr, length),
+            None => println!("         {:<9}{:06X}", name, addr)
+        }
+    }
+
+    // by default output is named after first section, so it doesn't overwrite its object file
+    let extension = if raw { "bin" } else { "obj" };
+    let filename = match output {
+                        Some(name) => name,
+                        None => format!("./{}_linked.{}", program.name, extension)
+                   };
+    let written = if raw { fs::write(&filename, &program.memory).is_ok() } else { fs::write(&filename, linker::linked_obj(&program)).is_ok() };
+    if !written {
+        eprintln!("Error linking: Can't write file {}", filename);
+        return EXIT_FAILURE;
+    }
+    0
+}
+
+
+// assembler run [--load <address>] [--device <number>=<file>] [--max-steps <count>] [--regs] <.obj files>
+fn run_simulator(args: &Vec<String>) -> i32 {
+    let mut obj_files: Vec<String> = Vec::new();
+    let mut load_addr: Option<usize> = None;
+    let mut device_paths: HashMap<u8, String> = HashMap::new();
+    let mut max_steps: Option<usize> = None;
+    let mut show_regs = false;
+
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--load" | "--device" | "--max-steps" => {
+                let value = match args.get(i + 1) {
+                                Some(value) => value,
+                                None => { eprintln!("{} needs a value.", args[i]); return EXIT_USAGE; }
+                            };
+                match args[i].as_str() {
+                    "--load" => match parser::parse_num(value) {
+                                    Ok(addr) if addr >= 0 => load_addr = Some(addr as usize),
+                                    _ => { eprintln!("Load address {} is not a valid number.", value); return EXIT_USAGE; }
+                                },
+                    "--max-steps" => match value.parse::<usize>() {
+                                        Ok(steps) => max_steps = Some(steps),
+                                        Err(_) => { eprintln!("Max steps {} is not a valid number.", value); return EXIT_USAGE; }
+                                     },
+                    // device number is hex, like in BYTE X'F1'
+                    _ => match value.split_once('=') {
+                            Some((number, path)) => match u8::from_str_radix(number, 16) {
+                                                        Ok(number) => { device_paths.insert(number, path.to_owned()); },
+                                                        Err(_) => { eprintln!("Device {} is not a valid hex number.", number); return EXIT_USAGE; }
+                                                    },
+                            None => { eprintln!("Device needs to be given as <number>=<file>, ie. F1=input.txt"); return EXIT_USAGE; }
+                         }
+                }
+                i += 1; },
+            "--regs" => show_regs = true,
+            _ => obj_files.push(args[i].to_owned())
+        }
+        i += 1;
+    }
+
+    let program;
+    match linker::link(&obj_files, load_addr) {
+        Ok(value) => program = value,
+        Err(msg) => { eprintln!("Error loading: {}", msg); return EXIT_FAILURE; }
+    }
+    let mut machine;
+    match simulator::new_machine(&program, &device_paths) {
+        Ok(value) => machine = value,
+        Err(msg) => { eprintln!("Error loading: {}", msg); return EXIT_FAILURE; }
     }
 
-    let asm_in = parser::read_asm_file(&asm_file);
+    let code = match simulator::run(&mut machine, max_steps) {
+                    Ok(steps) => { if show_regs {
+                                       println!();
+                                       println!("Halted after {} instructions", steps);
+                                   }
+                                   0 },
+                    Err(msg) => { eprintln!("Error running: {}", msg);
+                                  EXIT_FAILURE }
+               };
+    if show_regs {
+        for line in simulator::registers(&machine) {
+            println!("{}", line);
+        }
+    }
+    code
+}
+
 
-    // first pass
-    let symtab;
-    let len;
-    match symbols::get_symbol_table(&asm_in) {
-        Ok(value) => {symtab = value.1; len = value.0},
-        Err(msg) => { println!("Error generating symtab: {}", msg); return; }
+// assembler disasm [-o <output>] <.obj file>
+fn run_disassembler(args: &Vec<String>) -> i32 {
+    let mut obj_file = String::new();
+    let mut output: Option<String> = None;
+
+    let mut i = 0;
+    while i < args.len() {
+        if args[i] == "-o" {
+            match args.get(i + 1) {
+                Some(value) => output = Some(value.to_owned()),
+                None => { eprintln!("-o needs a value."); return EXIT_USAGE; }
+            }
+            i += 1;
+        }
+        else {
+            obj_file = args[i].to_owned();
+        }
+        i += 1;
+    }
+    if obj_file == "" {
+        eprintln!("No obj file provided.");
+        return EXIT_USAGE;
     }
 
-    match assemble::generate_obj(&asm_in, &symtab, len) {
-        Ok(_) => (),
-        Err(msg) => { println!("Error assembling: {}", msg); return; }
+    let lines;
+    match disassembler::disassemble(&obj_file) {
+        Ok(value) => lines = value,
+        Err(msg) => { eprintln!("Error disassembling: {}", msg); return EXIT_FAILURE; }
     }
+
+    // by default output is named after object file, ie. PROG.obj -> PROG_dis.asm
+    let filename = match output {
+                        Some(name) => name,
+                        None => format!("{}_dis.asm", obj_file.strip_suffix(".obj").unwrap_or(&obj_file))
+                   };
+    match write_lines(&filename, &lines) {
+        Ok(_) => 0,
+        Err(msg) => { eprintln!("Error disassembling: {}", msg);
+                      EXIT_FAILURE }
+    }
+}
+
+
+// where and in which formats assembled program is written
+struct OutputOptions {
+    // -o, path of first output format
+    output: Option<String>,
+    out_dir: String,
+    // obj and bin, which is memory image
+    formats: Vec<String>,
+    // None when file isn't written, empty path means it's named after program
+    listing: Option<String>,
+    symbols: Option<String>
+}
+
+
+// path of output, by default it's named after program and placed in output directory
+fn output_path(path: &str, out_dir: &str, name: &str, extension: &str) -> String {
+    if path != "" {
+        return path.to_owned();
+    }
+    Path::new(out_dir).join(format!("{}.{}", name, extension)).display().to_string()
+}
+
+
+// memory image of assembled program, like linker makes it from single object file
+fn memory_image(object: &Vec<ObjSection>, asm_file: &str) -> Result<Vec<u8>, String> {
+    match linker::link_modules(&vec![(asm_file.to_owned(), object.clone())], None) {
+        Ok(program) => Ok(program.memory),
+        Err(msg) => Err(msg)
+    }
+}
+
+
+fn assemble_file(asm_file: &str, options: &AssemblyOptions, out: &OutputOptions, use_color: bool) -> i32 {
+    let source;
+    match fs::read_to_string(asm_file) {
+        Ok(value) => source = value,
+        Err(_) => { eprintln!("Error reading source: Can't read file {}. Make sure you specified the right path.", asm_file);
+                    return EXIT_FAILURE; }
+    }
+
+    let options = AssemblyOptions{source_name: asm_file.to_owned(), ..options.clone()};
+    let output;
+    match assembler::assemble(&source, &options) {
+        Ok(value) => output = value,
+        Err(diagnostics) => { print_diagnostics(&diagnostics, use_color);
+                              return EXIT_FAILURE; }
+    }
+    if output.diagnostics.len() > 0 {
+        print_diagnostics(&output.diagnostics, use_color);
+    }
+
+    if out.out_dir != "." && fs::create_dir_all(&out.out_dir).is_err() {
+        eprintln!("Error writing output: Can't create directory {}", out.out_dir);
+        return EXIT_FAILURE;
+    }
+
+    // -o names first format, others are named after program
+    for (i, format) in out.formats.iter().enumerate() {
+        let path = if i == 0 { out.output.to_owned().unwrap_or_default() } else { String::new() };
+        let path = output_path(&path, &out.out_dir, &output.name, format);
+        let written = match format.as_str() {
+                        "bin" => match memory_image(&output.object, asm_file) {
+                                    Ok(memory) => if fs::write(&path, memory).is_ok() { Ok(1) } else { Err(format!("Can't write file {}", path)) },
+                                    Err(msg) => Err(format!("Can't make memory image, {}", msg))
+                                 },
+                        _ => { let mut obj_file = String::new();
+                               for section in output.object.iter() {
+                                   objwriter::write_obj_section(&mut obj_file, section);
+                               }
+                               if fs::write(&path, obj_file).is_ok() { Ok(1) } else { Err(format!("Can't write file {}", path)) } }
+                      };
+        match written {
+            Ok(_) => (),
+            Err(msg) => { eprintln!("Error writing output: {}", msg);
+                          return EXIT_FAILURE; }
+        }
+    }
+
+    match &out.listing {
+        Some(path) => match write_lines(&output_path(path, &out.out_dir, &output.name, "lst"), &output.listing) {
+                        Ok(_) => (),
+                        Err(msg) => { eprintln!("Error writing output: {}", msg);
+                                      return EXIT_FAILURE; }
+                      },
+        None => ()
+    }
+
+    match &out.symbols {
+        Some(path) => { let mut sym_file = String::new();
+                        for section in output.sections.iter() {
+                            objwriter::write_sym_section(&mut sym_file, section);
+                        }
+                        let path = output_path(path, &out.out_dir, &output.name, "sym");
+                        if fs::write(&path, sym_file).is_err() {
+                            eprintln!("Error writing output: Can't write file {}", path);
+                            return EXIT_FAILURE;
+                        } },
+        None => ()
+    }
+
+    // export file is written only for programs that use EXPORTS
+    if output.sections.iter().any(|section| section.exports.len() > 0) {
+        let mut exp_file = String::new();
+        for section in output.sections.iter().filter(|section| section.exports.len() > 0) {
+            objwriter::write_exp_section(&mut exp_file, section);
+        }
+        let path = output_path("", &out.out_dir, &output.name, "exp");
+        if fs::write(&path, exp_file).is_err() {
+            eprintln!("Error writing output: Can't write file {}", path);
+            return EXIT_FAILURE;
+        }
+    }
+    0
+}
+
+
+/*
+    assembler [options] <.asm files>
+
+    -o <file>                 name of output, only with one input file
+    --out-dir <dir>           directory of outputs, default is current directory
+    --format <obj|bin>        output formats, can be repeated or separated by commas, default is obj
+    --listing=<file>          name of listing, --no-listing turns it off
+    --symbols[=<file>]        write symbol table
+    -I <dir>                  directory searched for INCLUDE files
+    -D <name>[=<value>]       define symbol with EQU, value is number or expression, default is 1
+    --start-address <addr>    replaces address given to START
+    -W<warning>, -Wno-<warning>, -Werror
+    --color[=always|never|auto]
+*/
+fn run_assembler(args: &Vec<String>) -> i32 {
+    let mut asm_files: Vec<String> = Vec::new();
+    let mut options = AssemblyOptions::default();
+    let mut out = OutputOptions{output: None, out_dir: ".".to_owned(), formats: Vec::new(), listing: Some(String::new()), symbols: None};
+    // colors are used when diagnostics go to terminal, unless --color says otherwise
+    let mut use_color = std::io::stderr().is_terminal();
+
+    let mut i = 0;
+    while i < args.len() {
+        let arg = args[i].as_str();
+        // value is either next argument or joined to short option, ie. -I lib or -Ilib
+        let (option, joined) = match arg {
+                                _ if arg.starts_with("-I") && arg.len() > 2 => ("-I", Some(&arg[2..])),
+                                _ if arg.starts_with("-D") && arg.len() > 2 => ("-D", Some(&arg[2..])),
+                                _ if arg.starts_with("--format=") => ("--format", Some(&arg[9..])),
+                                _ => (arg, None)
+                               };
+
+        match option {
+            "-o" | "--out-dir" | "--format" | "-I" | "-D" | "--start-address" => {
+                let value = match joined {
+                                Some(value) => value,
+                                None => match args.get(i + 1) {
+                                            Some(value) => { i += 1;
+                                                             value.as_str() },
+                                            None => { eprintln!("{} needs a value.", option); return EXIT_USAGE; }
+                                        }
+                            };
+                match option {
+                    "-o" => out.output = Some(value.to_owned()),
+                    "--out-dir" => out.out_dir = value.to_owned(),
+                    "--format" => for format in value.split(",") {
+                                      if format != "obj" && format != "bin" {
+                                          eprintln!("Unknown format {}, formats are obj and bin.", format);
+                                          return EXIT_USAGE;
+                                      }
+                                      out.formats.push(format.to_owned());
+                                  },
+                    "-I" => options.include_paths.push(value.to_owned()),
+                    "-D" => { let (name, value) = value.split_once('=').unwrap_or((value, "1"));
+                              if name == "" || value == "" {
+                                  eprintln!("-D needs symbol given as <name>=<value>, ie. -D SIZE=10");
+                                  return EXIT_USAGE;
+                              }
+                              options.defines.push((name.to_owned(), value.to_owned())); },
+                    _ => match parser::parse_num(value) {
+                            Ok(addr) if addr >= 0 => options.start_address = Some(addr as usize),
+                            _ => { eprintln!("Start address {} is not a valid number.", value); return EXIT_USAGE; }
+                         }
+                } },
+            "--no-listing" => out.listing = None,
+            "--symbols" => out.symbols = Some(String::new()),
+            _ if option.starts_with("--listing=") => out.listing = Some(option[10..].to_owned()),
+            _ if option.starts_with("--symbols=") => out.symbols = Some(option[10..].to_owned()),
+            _ if option.starts_with("--color") => match option {
+                                                    "--color" | "--color=always" => use_color = true,
+                                                    "--color=never" => use_color = false,
+                                                    "--color=auto" => use_color = std::io::stderr().is_terminal(),
+                                                    _ => { eprintln!("--color can be always, never or auto."); return EXIT_USAGE; }
+                                                  },
+            "-Werror" => options.warnings_as_errors = true,
+            _ if option.starts_with("-W") => {
+                // -Wno-<warning> turns warning off, -W<warning> turns it back on
+                let name = option[2..].strip_prefix("no-").unwrap_or(&option[2..]);
+                match find_warning(name) {
+                    Some(kind) => { options.disabled_warnings.retain(|warning| *warning != kind);
+                                    if option.starts_with("-Wno-") {
+                                        options.disabled_warnings.push(kind);
+                                    } },
+                    None => { eprintln!("Unknown warning {}, warnings are: {}.", name, WARNING_NAMES.map(|(name, _)| name).join(", "));
+                              return EXIT_USAGE; }
+                }
+            },
+            _ if option.starts_with("-") => { eprintln!("Unknown option {}.", option); return EXIT_USAGE; }
+            _ => asm_files.push(option.to_owned())
+        }
+        i += 1;
+    }
+
+    if asm_files.len() == 0 {
+        eprintln!("No asm file provided.");
+        return EXIT_USAGE;
+    }
+    // same name for every input would make them overwrite each other
+    let named_listing = matches!(&out.listing, Some(path) if path != "");
+    let named_symbols = matches!(&out.symbols, Some(path) if path != "");
+    if asm_files.len() > 1 && (out.output.is_some() || named_listing || named_symbols) {
+        eprintln!("-o, --listing=<file> and --symbols=<file> can only be used with one asm file.");
+        return EXIT_USAGE;
+    }
+    if out.formats.len() == 0 {
+        out.formats.push("obj".to_owned());
+    }
+
+    // every file is assembled, even when some before it fail
+    let mut code = 0;
+    for asm_file in asm_files.iter() {
+        if assemble_file(asm_file, &options, &out, use_color) != 0 {
+            code = EXIT_FAILURE;
+        }
+    }
+    code
+}
+
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let code = match args.first().map(|arg| arg.as_str()) {
+                    Some("link") => run_linker(&args[1..].to_vec()),
+                    Some("disasm") => run_disassembler(&args[1..].to_vec()),
+                    Some("run") => run_simulator(&args[1..].to_vec()),
+                    _ => run_assembler(&args)
+               };
+    std::process::exit(code);
 }
@@ -0,0 +1,181 @@
+use std::fs;
+
+
+// modification record, symbol is None when address is relative to start of its own section
+#[derive(Clone, Debug)]
+pub struct ObjModification {
+    pub address: usize,
+    // length of modified field in half-bytes
+    pub length: usize,
+    // + or - and name of external symbol
+    pub symbol: Option<(char, String)>,
+    // line of record in object file, 0 for sections made by assembler
+    pub line: usize
+}
+
+
+// one control section of object file, from H record to E record
+#[derive(Clone, Debug)]
+pub struct ObjSection {
+    pub name: String,
+    pub start: usize,
+    pub length: usize,
+    pub defs: Vec<(String, usize)>,
+    pub refs: Vec<String>,
+    // start address and bytes of every text record
+    pub texts: Vec<(usize, Vec<u8>)>,
+    pub mods: Vec<ObjModification>,
+    pub entry: Option<usize>
+}
+
+
+fn parse_hex_field(record: &str, from: usize, to: usize) -> Result<usize, String> {
+    if record.len() < to {
+        return Err(format!("record {} is too short", &record[..1]));
+    }
+    match usize::from_str_radix(&record[from..to], 16) {
+        Ok(value) => Ok(value),
+        Err(_) => Err(format!("{} is not a hex number", &record[from..to]))
+    }
+}
+
+
+fn parse_hex_bytes(code: &str) -> Result<Vec<u8>, String> {
+    if code.len() % 2 == 1 {
+        return Err("text record has odd number of half-bytes".to_owned());
+    }
+    let mut bytes: Vec<u8> = Vec::new();
+    for i in (0..code.len()).step_by(2) {
+        match u8::from_str_radix(&code[i..i+2], 16) {
+            Ok(byte) => bytes.push(byte),
+            Err(_) => return Err(format!("{} is not a hex byte", &code[i..i+2]))
+        }
+    }
+    Ok(bytes)
+}
+
+
+fn parse_record(record: &str, section: &mut ObjSection, line: usize) -> Result<u8, String> {
+    match &record[..1] {
+        "D" => { // each definition is 6 columns of name and 6 columns of address
+                 for i in (1..record.len()).step_by(12) {
+                     if i + 12 > record.len() {
+                         return Err("define record must consist of 12 column definitions".to_owned());
+                     }
+                     let addr = match parse_hex_field(record, i + 6, i + 12) {
+                                    Ok(addr) => addr,
+                                    Err(msg) => return Err(msg)
+                                };
+                     section.defs.push((record[i..i+6].trim_end().to_owned(), addr));
+                 } },
+        "R" => { for i in (1..record.len()).step_by(6) {
+                     let end = if i + 6 < record.len() { i + 6 } else { record.len() };
+                     section.refs.push(record[i..end].trim_end().to_owned());
+                 } },
+        "T" => { let addr = match parse_hex_field(record, 1, 7) {
+                                Ok(addr) => addr,
+                                Err(msg) => return Err(msg)
+                            };
+                 let len = match parse_hex_field(record, 7, 9) {
+                                Ok(len) => len,
+                                Err(msg) => return Err(msg)
+                           };
+                 let bytes = match parse_hex_bytes(&record[9..]) {
+                                Ok(bytes) => bytes,
+                                Err(msg) => return Err(msg)
+                             };
+                 if bytes.len() != len {
+                     return Err(format!("text record says it has {} bytes, but has {}", len, bytes.len()));
+                 }
+                 section.texts.push((addr, bytes)); },
+        "M" => { let addr = match parse_hex_field(record, 1, 7) {
+                                Ok(addr) => addr,
+                                Err(msg) => return Err(msg)
+                            };
+                 let len = match parse_hex_field(record, 7, 9) {
+                                Ok(len) => len,
+                                Err(msg) => return Err(msg)
+                           };
+                 let symbol = if record.len() > 10 {
+                                let sign = record[9..10].chars().next().unwrap();
+                                if sign != '+' && sign != '-' {
+                                    return Err(format!("modification record needs + or - before symbol, not {}", sign));
+                                }
+                                Some((sign, record[10..].trim_end().to_owned()))
+                              }
+                              else {
+                                None
+                              };
+                 section.mods.push(ObjModification{address: addr, length: len, symbol: symbol, line: line}); },
+        "E" => section.entry = if record.len() > 1 {
+                                    match parse_hex_field(record, 1, 7) {
+                                        Ok(addr) => Some(addr),
+                                        Err(msg) => return Err(msg)
+                                    }
+                               }
+                               else {
+                                    None
+                               },
+        _ => return Err(format!("unknown record type {}", &record[..1]))
+    }
+    Ok(1)
+}
+
+
+// read all control sections of object file written by objwriter
+pub fn read_obj_file(filename: &str) -> Result<Vec<ObjSection>, String> {
+    match fs::read_to_string(filename) {
+        Ok(data) => read_obj(&data, filename),
+        Err(_) => Err(format!("Can't read file {}. Make sure you specified the right path.", filename))
+    }
+}
+
+
+// read control sections from object code, filename is only used in messages
+pub fn read_obj(data: &str, filename: &str) -> Result<Vec<ObjSection>, String> {
+    let mut sections: Vec<ObjSection> = Vec::new();
+    let mut current: Option<ObjSection> = None;
+
+    for (index, line) in data.lines().enumerate() {
+        let record = line.trim_end();
+        if record == "" {
+            continue;
+        }
+        if !record.is_ascii() {
+            return Err(format!("Line {} in {}, record contains non ASCII characters", index + 1, filename));
+        }
+
+        if record.starts_with("H") {
+            if current.is_some() {
+                return Err(format!("Line {} in {}, header record before end record of previous section", index + 1, filename));
+            }
+            let start = parse_hex_field(record, 7, 13);
+            let length = parse_hex_field(record, 13, 19);
+            match (start, length) {
+                (Ok(start), Ok(length)) => current = Some(ObjSection{name: record[1..7].trim_end().to_owned(), start: start, length: length,
+                                                                     defs: Vec::new(), refs: Vec::new(), texts: Vec::new(), mods: Vec::new(), entry: None}),
+                (Err(msg), _) | (_, Err(msg)) => return Err(format!("Line {} in {}, {}", index + 1, filename, msg))
+            }
+            continue;
+        }
+
+        match current.as_mut() {
+            Some(section) => match parse_record(record, section, index + 1) {
+                                Ok(_) => (),
+                                Err(msg) => return Err(format!("Line {} in {}, {}", index + 1, filename, msg))
+                             },
+            None => return Err(format!("Line {} in {}, record before header record", index + 1, filename))
+        }
+        if record.starts_with("E") {
+            sections.push(current.take().unwrap());
+        }
+    }
+
+    if current.is_some() {
+        return Err(format!("{} is missing end record", filename));
+    }
+    if sections.len() == 0 {
+        return Err(format!("{} has no header record", filename));
+    }
+    Ok(sections)
+}
@@ -1,52 +1,143 @@
-use std::fs::File;
-use std::io::Write;
+use crate::symbols::{Block, Section};
+use crate::objreader::ObjSection;
+use std::fmt::Write;
 
 
-pub fn write_lst_instr(output: &mut File, loc_counter: usize, mut machine_code: String, instr_line: &Vec<&str>) {
+pub fn write_lst_instr(output: &mut String, loc_counter: usize, mut machine_code: String, instr_line: &Vec<&str>, expanded: bool) {
     // shorten long codes to 6 chars
     if machine_code.len() > 6 {
         machine_code = format!("{}..{}", &machine_code[0..2], &machine_code[machine_code.len()-2..machine_code.len()])
     }
+    // lines generated by macro expansion are marked with +
+    let marker = if expanded { "+" } else { " " };
     // first line in lst is loc. counter and machine code
-    write!(output, "{:05X}  {:>6}    ", loc_counter, machine_code).expect("Can't write to lst file");
+    write!(output, "{:05X}  {:>6}  {} ", loc_counter, machine_code, marker).expect("Can't write to lst");
     // following lines are all instructions or comments
     let mut normal_spacing = false;
     for instr in instr_line.iter(){
         // if we encounter comment, char/hex init or register/indexed, we stop making long indents
-        if instr.starts_with(".") || instr.starts_with("C'") || instr.starts_with("X'") || instr.ends_with(",") || *instr == "EQU" {
+        if instr.starts_with(".") || instr.starts_with("C'") || instr.starts_with("X'") || instr.starts_with("=C'") || instr.ends_with(",") || *instr == "EQU" {
             normal_spacing = true;
         }
         if normal_spacing {
-            write!(output, "{} ", instr).expect("Can't write to lst file");
+            write!(output, "{} ", instr).expect("Can't write to lst");
         }
         else {
-            write!(output, "{:<14}", instr).expect("Can't write to lst file");
+            write!(output, "{:<14}", instr).expect("Can't write to lst");
         }
     }
-    writeln!(output).expect("Can't write to lst file");
+    writeln!(output).expect("Can't write to lst");
 }
 
 
-pub fn write_lst_comment(output: &mut File, comment: &String) {
-    writeln!(output, "                 {}", comment).expect("Can't write to lst file");
+pub fn write_lst_comment(output: &mut String, comment: &String) {
+    writeln!(output, "                 {}", comment).expect("Can't write to lst");
 }
 
 
-pub fn write_obj_header(output: &mut File, name: String, start: usize, len: usize) {
+pub fn write_lst_skipped(output: &mut String, line: &String) {
+    // lines that weren't assembled are marked with - in place of macro marker
+    writeln!(output, "               - {}", line).expect("Can't write to lst");
+}
+
+
+pub fn write_lst_file(output: &mut String, file: &String) {
+    writeln!(output, "                 --- {} ---", file).expect("Can't write to lst");
+}
+
+
+pub fn write_lst_blocks(output: &mut String, section: &str, blocks: &Vec<Block>) {
+    writeln!(output).expect("Can't write to lst");
+    writeln!(output, "Program blocks of {}", section).expect("Can't write to lst");
+    writeln!(output, "Name          Number  Address  Length").expect("Can't write to lst");
+    for (i, blk) in blocks.iter().enumerate() {
+        let name = if blk.name == "" { "(default)" } else { &blk.name };
+        writeln!(output, "{:<14}{:<8}{:05X}    {:05X}", name, i, blk.start, blk.length).expect("Can't write to lst");
+    }
+}
+
+
+// symbols of section sorted by address, written to .sym file
+pub fn write_sym_section(output: &mut String, section: &Section) {
+    let mut symbols: Vec<(&String, &i32)> = section.symtab.iter().collect();
+    symbols.sort_by(|a, b| (a.1, a.0).cmp(&(b.1, b.0)));
+
+    writeln!(output, "Symbols of {}", section.name).expect("Can't write to sym");
+    writeln!(output, "Name          Value").expect("Can't write to sym");
+    for (name, value) in symbols {
+        writeln!(output, "{:<14}{:06X}", name, value & 0xFFFFFF).expect("Can't write to sym");
+    }
+    writeln!(output).expect("Can't write to sym");
+}
+
+
+// symbols listed by EXPORTS with their values, so other programs can find them by name
+pub fn write_exp_section(output: &mut String, section: &Section) {
+    writeln!(output, "Exports of {}", section.name).expect("Can't write to exp");
+    writeln!(output, "Name          Value   Type").expect("Can't write to exp");
+    for name in section.exports.iter() {
+        let kind = if section.absolute.contains(name) { "absolute" } else { "relative" };
+        writeln!(output, "{:<14}{:06X}  {}", name, section.symtab[name] & 0xFFFFFF, kind).expect("Can't write to exp");
+    }
+    writeln!(output).expect("Can't write to exp");
+}
+
+
+pub fn write_obj_header(output: &mut String, name: String, start: usize, len: usize) {
     writeln!(output, "H{:<6}{:06X}{:06X}", name, start, len).expect("Can't write ob header");
 }
 
 
-pub fn write_obj_text(output: &mut File, start: usize, len: usize, code: String) {
+pub fn write_obj_define(output: &mut String, symbols: &Vec<(String, usize)>) {
+    // each definition takes 12 columns, so 6 of them fit in one record
+    for chunk in symbols.chunks(6) {
+        let defs: String = chunk.iter().map(|(name, addr)| format!("{:<6}{:06X}", name, addr)).collect();
+        writeln!(output, "D{}", defs).expect("Can't write obj define");
+    }
+}
+
+
+pub fn write_obj_refer(output: &mut String, symbols: &Vec<String>) {
+    // each reference takes 6 columns, so 12 of them fit in one record
+    for chunk in symbols.chunks(12) {
+        let refs: String = chunk.iter().map(|name| format!("{:<6}", name)).collect();
+        writeln!(output, "R{}", refs).expect("Can't write obj refer");
+    }
+}
+
+
+pub fn write_obj_text(output: &mut String, start: usize, len: usize, code: String) {
     writeln!(output, "T{:06X}{:02X}{}", start, len, code).expect("Can't write obj text");
 }
 
 
-pub fn write_obj_mod(output: &mut File, mod_rec: String) {
+pub fn write_obj_mod(output: &mut String, mod_rec: String) {
     writeln!(output, "M{}", mod_rec).expect("Can't write obj modification record");
 }
 
 
-pub fn write_obj_end(output: &mut File, instr: usize) {
-    writeln!(output, "E{:06X}", instr).expect("Can't write obj end");
-}
\ No newline at end of file
+pub fn write_obj_end(output: &mut String, instr: Option<usize>) {
+    match instr {
+        Some(addr) => writeln!(output, "E{:06X}", addr).expect("Can't write obj end"),
+        // control sections other than first don't have address of first instruction
+        None => writeln!(output, "E").expect("Can't write obj end")
+    }
+}
+
+
+// records of control section that was already read, written the same way assembler writes them
+pub fn write_obj_section(output: &mut String, section: &ObjSection) {
+    write_obj_header(output, section.name.to_owned(), section.start, section.length);
+    write_obj_define(output, &section.defs);
+    write_obj_refer(output, &section.refs);
+    for (addr, bytes) in section.texts.iter() {
+        write_obj_text(output, *addr, bytes.len(), bytes.iter().map(|byte| format!("{:02X}", byte)).collect());
+    }
+    for modif in section.mods.iter() {
+        match &modif.symbol {
+            Some((sign, name)) => write_obj_mod(output, format!("{:06X}{:02X}{}{}", modif.address, modif.length, sign, name)),
+            None => write_obj_mod(output, format!("{:06X}{:02X}", modif.address, modif.length))
+        }
+    }
+    write_obj_end(output, section.entry);
+}
@@ -1,22 +1,186 @@
+use crate::diagnostics::{Diagnostic, error, token_error, at_token, with_hint, line_error, locate};
+use crate::lexer::{Token, TokenKind, tokens_text, line_tokens, token_end};
+use crate::ast::{Operand, operand_tokens, operand_token};
+use crate::simulator::{float_to_bytes, float_from_bytes};
 use std::fs;
-use regex::Regex;
+use std::path::{Path, PathBuf};
 use std::cmp;
 
 
+// line of program with file and number of line it came from, which stay the same after INCLUDE and macro expansion
+pub struct SourceLine {
+    pub text: String,
+    // line as it was written and column in it of every character of text, followed by column after its end
+    // both are empty for lines made by assembler, ie. by macro expansion, those show text instead
+    pub raw: String,
+    pub columns: Vec<usize>,
+    pub file: String,
+    pub line: usize,
+    // line was generated by macro expansion
+    pub expanded: bool,
+    // line is in branch of IF that isn't assembled
+    pub skipped: bool
+}
+
+
+// line made from other line, it keeps columns of written line only when its text stays the same
+pub fn derived_line(text: String, origin: &SourceLine, expanded: bool, skipped: bool) -> SourceLine {
+    let (raw, columns) = if text == origin.text { (origin.raw.to_owned(), origin.columns.clone()) } else { (String::new(), Vec::new()) };
+    SourceLine{text: text, raw: raw, columns: columns, file: origin.file.to_owned(), line: origin.line, expanded: expanded, skipped: skipped}
+}
+
+
 /* 
-    read asm file to cleaned format
+    read asm source to cleaned format
     all empty lines, with arbitrary number of white space, are replaced with ""
-    all spaces is reduced to one space " "
+    all spaces is reduced to one space " ", except inside of apostrophes, ie. C'A  B'
+    INCLUDE "file.asm" is replaced with cleaned lines of that file, filename is used to find included files next to source
 */
-pub fn read_asm_file(filename: &str) -> Vec<String> {
-    let data = fs::read_to_string(filename).expect("Can't read given file. Make sure you specified the right path.");
-    let re_all_white = Regex::new(r"\s+").unwrap();
-    // clean input so all whitespace is reduced to 1 space
-    let cleaned: Vec<String> = data.lines().map(|line| re_all_white.replace_all(&line, " ").trim().to_string()).collect();
+pub fn read_asm_source(source: &str, filename: &str, include_paths: &Vec<String>) -> Result<Vec<SourceLine>, Diagnostic> {
+    let mut program: Vec<SourceLine> = Vec::new();
+    // files that are currently being read, used to detect include cycles
+    let mut open_files: Vec<(PathBuf, String)> = Vec::new();
+
+    match read_source(source, filename, include_paths, &mut open_files, &mut program) {
+        Ok(_) => Ok(program),
+        Err(diag) => Err(diag)
+    }
+}
+
+
+fn read_file(filename: &str) -> Result<String, Diagnostic> {
+    match fs::read_to_string(filename) {
+        Ok(data) => Ok(data),
+        Err(_) => Err(error(format!("Can't read file {}. Make sure you specified the right path.", filename)))
+    }
+}
+
+
+// find included file, first next to file that includes it, then in include paths in given order
+fn find_include(name: &str, including_file: &str, include_paths: &Vec<String>) -> Option<String> {
+    let mut candidates: Vec<PathBuf> = Vec::new();
+    match Path::new(including_file).parent() {
+        Some(dir) => candidates.push(dir.join(name)),
+        None => candidates.push(PathBuf::from(name))
+    }
+    for dir in include_paths.iter() {
+        candidates.push(Path::new(dir).join(name));
+    }
+
+    candidates.iter().find(|path| path.is_file()).map(|path| path.display().to_string())
+}
+
+
+/*
+    reduce white space outside of char and hex constants to single space
+    column in original line is kept for every character, so messages can point into line as it was written
+*/
+fn clean_line(line: &str) -> (String, Vec<usize>) {
+    let chars: Vec<char> = line.chars().collect();
+    let first = chars.iter().position(|c| !c.is_whitespace()).unwrap_or(chars.len());
+    let last = chars.iter().rposition(|c| !c.is_whitespace()).map(|i| i + 1).unwrap_or(first);
+
+    let mut cleaned = String::new();
+    let mut columns: Vec<usize> = Vec::new();
+    let mut quoted = false;
+    let mut comment = false;
+    for (i, ch) in chars.iter().enumerate().take(last).skip(first) {
+        let ch = *ch;
+        if ch.is_whitespace() && !quoted {
+            if !cleaned.ends_with(' ') {
+                cleaned.push(' ');
+                columns.push(i + 1);
+            }
+            continue;
+        }
+        // apostrophes in comment don't start constant
+        if ch == '.' && !quoted {
+            comment = true;
+        }
+        if ch == '\'' && !comment {
+            quoted = !quoted;
+        }
+        cleaned.push(if ch.is_whitespace() { ' ' } else { ch });
+        columns.push(i + 1);
+    }
+    // end of span can be right after last character
+    columns.push(last + 1);
+    (cleaned, columns)
+}
+
+
+// name of file in INCLUDE "file.asm", comment can follow it
+fn parse_include_name(operands: &str) -> Result<String, Diagnostic> {
+    if !operands.starts_with("\"") {
+        return Err(with_hint(token_error(operands, "INCLUDE needs file name in quotes".to_owned()), "INCLUDE \"file.asm\""));
+    }
+    match operands[1..].find('"') {
+        Some(end) => { let rest = operands[end+2..].trim();
+                       if rest != "" && !rest.starts_with(".") {
+                           return Err(token_error(rest, format!("unexpected {} after file name of INCLUDE", rest)));
+                       }
+                       if end == 0 {
+                           return Err(token_error(operands, "INCLUDE file name can't be empty".to_owned()));
+                       }
+                       Ok(operands[1..end+1].to_owned()) },
+        None => Err(token_error(operands, "INCLUDE file name is missing closing quote".to_owned()))
+    }
+}
+
+
+fn read_source(data: &str, filename: &str, include_paths: &Vec<String>, open_files: &mut Vec<(PathBuf, String)>, program: &mut Vec<SourceLine>) -> Result<u8, Diagnostic> {
+    let full_path = fs::canonicalize(filename).unwrap_or(PathBuf::from(filename));
+    open_files.push((full_path, filename.to_owned()));
+
     // don't remove empty lines for accurate line error messaging
-    // cleaned = cleaned.iter().filter(|&line| line != "").map(|line| line.trim().to_string()).collect();
-    
-    cleaned
+    for (index, raw_line) in data.lines().enumerate() {
+        // clean input so all whitespace is reduced to 1 space
+        let (cleaned, columns) = clean_line(raw_line);
+        let src = SourceLine{text: cleaned, raw: raw_line.to_owned(), columns: columns, file: filename.to_owned(), line: index + 1, expanded: false, skipped: false};
+
+        // file name in quotes isn't a token, so only words in front of it are read
+        let words: Vec<Token> = line_tokens(&src.text).0.into_iter().filter(|tok| tok.kind != TokenKind::Comment).take(2).collect();
+        if words.len() > 1 && words[1].text == "INCLUDE" {
+            return Err(locate(at_token(&words[0], "INCLUDE can't have label".to_owned()), &src));
+        }
+        let include = match words.first() {
+                        Some(word) if word.text == "INCLUDE" => word,
+                        _ => { program.push(src);
+                               continue; }
+                      };
+
+        let name = match parse_include_name(src.text[token_end(&src.text, include)..].trim_start()) {
+                        Ok(name) => name,
+                        Err(diag) => return Err(locate(diag, &src))
+                   };
+        let path = match find_include(&name, filename, include_paths) {
+                        Some(path) => path,
+                        None => return Err(with_hint(line_error(&src, &name, format!("can't find included file {}", name)),
+                                                     "directories to search are added with -I <directory>"))
+                   };
+
+        // file that is already being read would include itself again and again
+        let full_path = fs::canonicalize(&path).unwrap_or(PathBuf::from(&path));
+        match open_files.iter().position(|(open, _)| *open == full_path) {
+            Some(first) => { let cycle: Vec<String> = open_files[first..].iter().map(|(_, name)| name.to_owned()).collect();
+                             return Err(line_error(&src, &name, format!("include cycle {} -> {}", cycle.join(" -> "), path))); },
+            None => ()
+        }
+
+        let data = match read_file(&path) {
+                        Ok(data) => data,
+                        Err(diag) => return Err(locate(diag, &src))
+                   };
+        // INCLUDE itself is kept as comment, so listing shows where included lines came from
+        program.push(derived_line(format!(".{}", src.text), &src, false, false));
+        match read_source(&data, &path, include_paths, open_files, program) {
+            Ok(_) => (),
+            Err(diag) => return Err(diag)
+        }
+    }
+
+    open_files.pop();
+    Ok(1)
 }
 
 
@@ -51,16 +215,16 @@ pub fn parse_num(num_str: &str) -> Result<i32, &str> {
 
 
 // parse hex from in init X'<hex val>'
-pub fn parse_hex(val_str: &str) -> Result<ResType, &str> {
+pub fn parse_hex(val_str: &str) -> Result<ResType, Diagnostic> {
     let mut data: Vec<u8> = Vec::new();
 
     // read value in between apostrophes
     let mut val_chars: Vec<char> = match val_str.split("'").nth(1) {
                                         Some(value) if value != "" => value.chars().collect(),
-                                        _ => return Err("Invalid hex format. Use: X'<hex val>'. Example: X'42'")
+                                        _ => return Err(with_hint(token_error(val_str, "Invalid hex format".to_owned()), "Use: X'<hex val>'. Example: X'42'"))
                                    };
     // pad value with leading 0 if its size is not divisible by 2
-    if val_chars.len() % 2 != 0 {
+    if val_chars.len() % 2 == 1 {
         val_chars.insert(0, '0');
     }
     let mut i = 0;
@@ -69,7 +233,7 @@ pub fn parse_hex(val_str: &str) -> Result<ResType, &str> {
         let curr_byte = &val_chars[i..i+2].iter().collect::<String>();
         let byte_val = match u8::from_str_radix(curr_byte, 16) {
                             Ok(value) => value,
-                            Err(_) => return Err("Can't parse hex expression")
+                            Err(_) => return Err(token_error(val_str, format!("Can't parse hex expression, {} is not a hex byte", curr_byte)))
                        };
         data.push(byte_val);
         i += 2;
@@ -79,178 +243,149 @@ pub fn parse_hex(val_str: &str) -> Result<ResType, &str> {
 }
 
 
+/*
+    bytes of char constant C'<char val>', each character is one byte
+    escapes are '' for apostrophe, \n, \r, \t, \0, \\ for backslash and \xNN for any byte
+*/
+pub fn char_bytes(val_str: &str) -> Result<Vec<u8>, Diagnostic> {
+    // lexer gives constant with both apostrophes, ie. C'EOF'
+    let val_chars: Vec<char> = val_str.chars().collect();
+    if val_chars.len() < 4 {
+        return Err(with_hint(token_error(val_str, "Invalid char format".to_owned()), "Use: C'<char val>'. Example: C'SIC'"));
+    }
+    let val_chars = &val_chars[2..val_chars.len()-1];
+
+    let mut bytes: Vec<u8> = Vec::new();
+    let mut i = 0;
+    while i < val_chars.len() {
+        let ch = val_chars[i];
+        i += 1;
+        let byte = match ch {
+                        // second apostrophe of pair is skipped
+                        '\'' => { i += 1;
+                                  b'\'' },
+                        '\\' => { let escape = val_chars.get(i).copied();
+                                   i += 1;
+                                   match escape {
+                                        Some('n') => b'\n',
+                                        Some('r') => b'\r',
+                                        Some('t') => b'\t',
+                                        Some('0') => 0,
+                                        Some('\\') => b'\\',
+                                        Some('x') => { let hex: String = val_chars.iter().skip(i).take(2).collect();
+                                                       i += 2;
+                                                       match u8::from_str_radix(&hex, 16) {
+                                                           Ok(byte) if hex.len() == 2 && hex.chars().all(|c| c.is_ascii_hexdigit()) => byte,
+                                                           _ => return Err(with_hint(token_error(&format!("\\x{}", hex), format!("\\x{} is not a byte, \\x needs two hex digits", hex)), "Example: C'LINE\\x0A'"))
+                                                       } },
+                                        Some(other) => return Err(with_hint(token_error(&format!("\\{}", other), format!("unknown escape \\{} in {}", other, val_str)),
+                                                                            "escapes are '', \\n, \\r, \\t, \\0, \\\\ and \\xNN")),
+                                        None => return Err(with_hint(token_error(val_str, format!("{} ends with \\ that doesn't escape anything", val_str)), "backslash itself is written as \\\\"))
+                                   } },
+                        _ if ch.is_ascii() => ch as u8,
+                        _ => return Err(with_hint(token_error(&ch.to_string(), format!("character {} of {} can't be stored in a byte", ch, val_str)),
+                                                  "only ASCII characters are allowed, other bytes are written with \\xNN"))
+                   };
+        bytes.push(byte);
+    }
+    Ok(bytes)
+}
+
+
 // parse char from init C'<char val>'
-pub fn parse_char(val_str: &str) -> Result<ResType, &str> {
-    // read value in between apostrophes and transform it to chars
-    let val_chars: Vec<u8> = match val_str.split("'").nth(1) {
-                                        Some(value) if value != "" => value.chars().map(|c| c as u8).collect(),
-                                        _ => return Err("Invalid char format. Use: C'<char val>'. Example: C'SIC'")
-                                   };
+pub fn parse_char(val_str: &str) -> Result<ResType, Diagnostic> {
+    match char_bytes(val_str) {
+        Ok(bytes) => Ok(ResType::Char(bytes)),
+        Err(diag) => Err(diag)
+    }
+}
+
 
-    Ok(ResType::Char(val_chars))
+// parse float of FLOAT and float literals, ie. -1.5E3, integers are also accepted, ie. FLOAT 2
+pub fn parse_float(value: &[Token]) -> Result<ResType, Diagnostic> {
+    let value_str = tokens_text(value);
+    let (negative, number) = match value {
+                                [sign, number] if sign.kind == TokenKind::Operator('-') || sign.kind == TokenKind::Operator('+') => (sign.text == "-", number),
+                                [number] => (false, number),
+                                _ => return Err(with_hint(token_error(&value_str, format!("Invalid float {}", value_str)), "Use: FLOAT <decimal number>. Example: FLOAT -1.5E3"))
+                             };
+    let magnitude = match number.kind {
+                        TokenKind::Float => number.text.parse::<f64>().unwrap(),
+                        TokenKind::Number(num) => num as f64,
+                        _ => return Err(with_hint(at_token(number, format!("Invalid float {}", value_str)), "Use: FLOAT <decimal number>. Example: FLOAT -1.5E3"))
+                    };
+    let float = if negative { -magnitude } else { magnitude };
+
+    // digits other than 0 written in front of exponent, ie. 1E-400, which is read as 0.0
+    let mantissa = number.text.split(['E', 'e']).next().unwrap();
+    let underflow = mantissa.chars().any(|c| c.is_ascii_digit() && c != '0') && !magnitude.is_normal();
+
+    // exponent has 11 bits, so value that is too large or too close to 0 doesn't come back the same
+    let bytes = float_to_bytes(float);
+    let stored = float_from_bytes(&bytes);
+    if underflow || !float.is_finite() || (float != 0.0 && (stored == 0.0 || ((stored - float) / float).abs() > 1e-10)) {
+        return Err(with_hint(token_error(&value_str, format!("float {} is out of range", value_str)),
+                             "SIC/XE float can hold magnitudes from 2.3E-308 to 8.9E307"));
+    }
+    Ok(ResType::Float(bytes.to_vec()))
 }
 
 
 pub enum ResType {
-    Num(usize),
+    Num(i32),
     Hex(Vec<u8>),
-    Char(Vec<u8>)
+    Char(Vec<u8>),
+    // 6 bytes of SIC/XE float
+    Float(Vec<u8>),
+    Expr(Vec<Token>)
 }
 
 
 // parse init value when using BYTE and WORD
-pub fn parse_init(num_str: &str) -> Result<ResType, &str> {
-    match num_str.chars().nth(0).unwrap() {
-        'X' => parse_hex(num_str),
-        'C' => parse_char(num_str),
-        // if it is num, we split by space and take only number (1st el), this avoids edge case of comments after res
-        _ => match parse_num(num_str.split(" ").next().unwrap()) {
-                Ok(value) => Ok(ResType::Num(value as usize)),
-                Err(msg) => Err(msg)
-        }
+pub fn parse_init(value: &[Token]) -> Result<ResType, Diagnostic> {
+    if value.len() == 0 {
+        return Err(error("Missing init value".to_owned()));
     }
-    
+    match value[0].kind {
+        // constant alone is data, constant inside expression is its value, ie. C'A'+1
+        TokenKind::Hex if value.len() == 1 => parse_hex(&value[0].text),
+        TokenKind::Char if value.len() == 1 => parse_char(&value[0].text),
+        _ if value.iter().any(|tok| tok.kind == TokenKind::Float) => parse_float(value),
+        _ => { if let Ok(num) = parse_num(&tokens_text(value)) {
+                   Ok(ResType::Num(num))
+               }
+               // anything else is expression with labels, which is evaluated once symbols are known
+               else {
+                   Ok(ResType::Expr(value.to_vec()))
+               } }
+    }
+}
+
+
+// float in BYTE or WORD, which only hold integers
+pub fn float_not_allowed(value: &[Token]) -> Diagnostic {
+    let value_str = tokens_text(value);
+    with_hint(token_error(&value_str, format!("float {} can't be value of BYTE or WORD", value_str)), "Use: FLOAT <decimal number>")
 }
 
 
 // parse size of init value
-pub fn parse_init_size(num_str: &str, type_size: usize) -> Result<usize, String> {
-    match parse_init(num_str) {
+pub fn parse_init_size(value: &[Token], type_size: usize) -> Result<usize, Diagnostic> {
+    match parse_init(value) {
         Ok(result) => match result {
                         // when dealing with just a number size is equal to type size
-                        ResType::Num(_) => Ok(type_size),
+                        ResType::Num(_) | ResType::Expr(_) => Ok(type_size),
                         // when dealing with hex arrays and char arrays, size needs to be at least size of type, but can be greater
                         ResType::Hex(vec) => Ok(cmp::max(type_size, vec.len())),
-                        ResType::Char(vec) => Ok(cmp::max(type_size, vec.len()))
+                        ResType::Char(vec) => Ok(cmp::max(type_size, vec.len())),
+                        ResType::Float(_) => Err(float_not_allowed(value))
                       },
-        Err(msg) => Err(msg.to_owned())
+        Err(diag) => Err(diag)
     }
 }
 
 
-// parse size of reservation
-pub fn parse_res_size(num_str: &str, type_size: usize) -> Result<usize, String> {
-    match parse_num(num_str) {
-        Ok(value) => if value < 1 {
-                        return Err("Reservations need to be greater than 0".to_owned())
-                    }
-                    else {
-                        Ok(type_size * value as usize)
-                    },
-        Err(msg) => Err(msg.to_owned())
-    }
-}
-
-
-// parse arithmetic expression
-pub fn parse_expr(expr: &str) -> Result<i32, String> {
-    let mut stack: Vec<String> = Vec::new();
-
-    let exp_chars: Vec<char> = expr.chars().collect();
-    // parse
-    let mut last_val: String;
-    let mut last_op: String;
-    let mut current = String::new();
-
-    for i in 0..expr.len() {
-        let ch = exp_chars[i];
-        
-        // skip all white spaces in expression
-        if ch.is_whitespace() {
-            continue;
-        }
-
-        // while we are on digit push chars to current number string
-        if ch.is_digit(10) {
-            current.push(ch)
-        }
-        else if ch == '+' || ch == '-' || ch == '*' || ch == '/' {
-            // when we encounter operator, we push current number to stack
-            stack.push(current);
-            current = String::new();
-
-            // first operator is just pushed to stack since we need at least 2 numbers
-            if stack.len() == 1 {
-                stack.push(ch.to_string());
-            }
-            else {
-                // get last value and operator
-                last_val = match stack.pop() {
-                            Some(val) => val,
-                            None => return Err("Invalid expression".to_owned())
-                        };
-                last_op = match stack.pop() {
-                    Some(val) => val,
-                    None => return Err("Invalid expression".to_owned())
-                };
-
-                // if current operator is * or / and previous was + or - we push them back due to precedence
-                if (ch == '*' || ch == '/') && (last_op == "+" || last_op == "-") {
-                    stack.push(last_op);
-                    stack.push(last_val);
-                }
-                else {
-                    // parse both numbers from stack
-                    let last_num = match last_val.parse::<i32>() {
-                                      Ok(val) => val,
-                                      Err(msg) => return Err(format!("Can't parse {}, {}", last_val, msg))
-                                   };
-                    let stack_num = match stack.pop().unwrap().parse::<i32>() {
-                                    Ok(val) => val,
-                                    Err(msg) => return Err(format!("Can't parse, {}", msg))
-                                 };
-                    // perform operation and push back to stack
-                    match last_op.as_ref() {
-                        "+" => stack.push(format!("{}", stack_num + last_num)),
-                        "-" => stack.push(format!("{}", stack_num - last_num)),
-                        "*" => stack.push(format!("{}", stack_num * last_num)),
-                        "/" => stack.push(format!("{}", stack_num / last_num)),
-                        _ => return Err(format!("Wrong operator {}", last_op))
-                    }
-                }
-                // also push operator on stack
-                stack.push(ch.to_string());
-            }
-        }
-        else {
-            return Err(format!("Invalid character {}", ch));
-        }
-    }
-    // at the end push te current number, ie. the rightmost to stack
-    stack.push(current);
-
-    // evaluate
-    let mut last_val: String;
-    while stack.len() > 1 {
-        last_val = stack.pop().unwrap();
-        
-        // get both numbers and parse them
-        let op = stack.pop().unwrap();
-        let stack_num = match stack.pop().unwrap().parse::<i32>() {
-                            Ok(val) => val,
-                            Err(msg) => return Err(format!("Can't parse, {}", msg))
-                        };
-        let last_num = match last_val.parse::<i32>() {
-                            Ok(val) => val,
-                            Err(msg) => return Err(format!("Can't parse {}, {}", last_val, msg))
-                        };
-        
-        // apply operation
-        match op.as_ref() {
-            "+" => stack.push(format!("{}", stack_num + last_num)),
-            "-" => stack.push(format!("{}", stack_num - last_num)),
-            "*" => stack.push(format!("{}", stack_num * last_num)),
-            "/" => stack.push(format!("{}", stack_num / last_num)),
-            _ => return Err(format!("Wrong operator {}", op))
-        }
-    }
-
-    // this also covers just 1 number without operations
-    Ok(stack.pop().unwrap().parse::<i32>().unwrap())
-}
-
-
-pub fn parse_reg(reg_str: &str) -> Result<u8, &str> {
+pub fn parse_reg(reg_str: &str) -> Result<u8, Diagnostic> {
     // AXLBSTF → 0,1,2,3,4,5,6
     match reg_str {
         "A" => Ok(0),
@@ -260,24 +395,29 @@ pub fn parse_reg(reg_str: &str) -> Result<u8, &str> {
         "S" => Ok(4),
         "T" => Ok(5),
         "F" => Ok(6),
-        _   => Err("Not a valid register")
+        _   => Err(with_hint(token_error(reg_str, format!("{} is not a valid register", reg_str)), "registers are A, X, L, B, S, T and F"))
     }
 }
 
 
-pub fn parse_indexed(operands: &Vec<&str>) -> Option<String> {
-    // since there can be space after comma: either var,X or var, X. We join them to var,X
-    let val_join;
-    match operands[0].ends_with(",") {
-        // if it ends with , it means other reg is separated by space so we join
-        true if operands.len() > 1 => val_join = format!("{}{}", operands[0], operands[1]),
-        _ => val_join = operands[0].to_owned(),
+// parse comma separated list of symbols used by EXTDEF and EXTREF
+pub fn parse_symbol_list(operands: &Vec<Operand>) -> Result<Vec<String>, Diagnostic> {
+    if operands.len() == 0 {
+        return Err(error("Expected list of symbols".to_owned()));
     }
-    let val_split: Vec<&str> = val_join.split(",").map(|val| val.trim()).collect();
-    if val_split.len() < 2 || val_split[1] != "X" {
-        return None;
-    }
-    else {
-        return Some(val_split[0].to_owned());
+
+    let mut symbols: Vec<String> = Vec::new();
+    for operand in operands.iter() {
+        // names are written to D and R records which only have 6 characters per name
+        let name = match operand {
+                        Operand::Simple(tokens) if tokens.len() == 1 && (tokens[0].kind == TokenKind::Symbol || tokens[0].kind == TokenKind::Register) => &tokens[0],
+                        _ => { let name = tokens_text(operand_tokens(operand));
+                               return Err(at_token(operand_token(operand), format!("Invalid external symbol '{}', names must be 1 to 6 characters long", name))); }
+                   };
+        if name.text.len() > 6 {
+            return Err(at_token(name, format!("Invalid external symbol '{}', names must be 1 to 6 characters long", name.text)));
+        }
+        symbols.push(name.text.to_owned());
     }
-}
\ No newline at end of file
+    Ok(symbols)
+}
@@ -0,0 +1,70 @@
+use crate::diagnostics::{Diagnostic, Severity, warning_flag};
+
+
+// ANSI escape codes, used only when colors are turned on
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+
+fn paint(text: &str, color: &str, use_color: bool) -> String {
+    if use_color { format!("{}{}{}", color, text, RESET) } else { text.to_owned() }
+}
+
+
+/*
+    render diagnostic in style of rustc, ie.
+
+    error: Symbol LOPP does not appear as a left label
+      --> loop.asm:12:3
+       |
+    12 | J LOPP
+       |   ^^^^
+       = hint: define it as label, or declare it with EXTREF when it's in other section
+       = did you mean LOOP?
+
+    line is shown as it was written, tabs before caret are kept so caret stays under its column
+*/
+pub fn render_diagnostic(diag: &Diagnostic, use_color: bool) -> String {
+    let (severity, color) = match diag.severity {
+                                Severity::Error => ("error", RED),
+                                Severity::Warning => ("warning", YELLOW)
+                            };
+    let mut text = format!("{}{}\n", paint(severity, color, use_color), paint(&format!(": {}{}", diag.message, warning_flag(diag)), BOLD, use_color));
+
+    // gutter is as wide as number of line
+    let gutter = " ".repeat(diag.line.to_string().len());
+    let bar = paint("|", BLUE, use_color);
+    match (diag.line, diag.span) {
+        (0, _) if diag.file == "" => (),
+        (0, _) => text.push_str(&format!("{} {}\n", paint(&format!("{}-->", gutter), BLUE, use_color), diag.file)),
+        (line, span) => { let location = match span {
+                                            Some((col, _)) => format!("{}:{}:{}", diag.file, line, col),
+                                            None => format!("{}:{}", diag.file, line)
+                                         };
+                          text.push_str(&format!("{} {}\n", paint(&format!("{}-->", gutter), BLUE, use_color), location));
+                          text.push_str(&format!("{} {}\n", gutter, bar));
+                          text.push_str(&format!("{} {} {}\n", paint(&line.to_string(), BLUE, use_color), bar, diag.source));
+
+                          // caret under token, or under whole line when problem isn't in single word
+                          let (start, end) = match span {
+                                                Some(span) => span,
+                                                None => (1, diag.source.chars().count() + 1)
+                                             };
+                          let carets = "^".repeat(std::cmp::max(1, end - start));
+                          let indent: String = diag.source.chars().chain(std::iter::repeat(' ')).take(start - 1).map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+                          text.push_str(&format!("{} {} {}{}\n", gutter, bar, indent, paint(&carets, color, use_color))); }
+    }
+
+    match &diag.hint {
+        Some(hint) => text.push_str(&format!("{} {} hint: {}\n", gutter, paint("=", BLUE, use_color), hint)),
+        None => ()
+    }
+    match &diag.suggestion {
+        Some(word) => text.push_str(&format!("{} {} did you mean {}?\n", gutter, paint("=", BLUE, use_color), word)),
+        None => ()
+    }
+    text
+}
@@ -0,0 +1,594 @@
+use crate::commands::{find_mnemonic, determine_format};
+use crate::linker::LinkedProgram;
+use std::collections::HashMap;
+use std::cmp;
+use std::cmp::Ordering;
+use std::fs::File;
+use std::io::{Read, Write};
+
+
+/*
+    SIC/XE machine that runs programs loaded by linker
+
+    registers are stored by their number: A 0, X 1, L 2, B 3, S 4, T 5, PC 8, SW 9, F is kept separately as float
+    program halts when it jumps to itself (HALT J HALT), on SVC or when limit of steps is reached
+*/
+pub struct Machine {
+    pub memory: Vec<u8>,
+    pub regs: [i32; 10],
+    pub f: f64,
+    devices: HashMap<u8, Device>
+}
+
+
+// device of RD, WD and TD, file is opened when it's first used
+struct Device {
+    path: String,
+    input: Option<File>,
+    output: Option<File>
+}
+
+
+pub const MEMORY_SIZE: usize = 1 << 20;
+
+pub const REG_NAMES: [&str; 10] = ["A", "X", "L", "B", "S", "T", "F", "", "PC", "SW"];
+
+const REG_A: usize = 0;
+const REG_X: usize = 1;
+const REG_L: usize = 2;
+const REG_B: usize = 3;
+const REG_F: usize = 6;
+const REG_PC: usize = 8;
+const REG_SW: usize = 9;
+
+// condition code is kept in bits 6 and 7 of SW
+const CC_LT: i32 = 0x00;
+const CC_EQ: i32 = 0x40;
+const CC_GT: i32 = 0x80;
+const CC_MASK: i32 = 0xC0;
+
+
+// registers are 24 bits wide, values are kept unsigned and made signed for arithmetic
+fn to_signed(value: i32) -> i32 {
+    if value & 0x800000 != 0 { value - 0x1000000 } else { value }
+}
+
+
+fn to_word(value: i64) -> i32 {
+    (value & 0xFFFFFF) as i32
+}
+
+
+// 48 bit float: sign bit, 11 bit exponent with bias 1024 and 36 bit fraction in [0.5, 1)
+pub fn float_from_bytes(bytes: &[u8]) -> f64 {
+    let mut bits: u64 = 0;
+    for byte in bytes.iter() {
+        bits = (bits << 8) | *byte as u64;
+    }
+    let fraction = (bits & 0xFFFFFFFFF) as f64 / (1u64 << 36) as f64;
+    let exponent = ((bits >> 36) & 0x7FF) as i32 - 1024;
+    let value = fraction * 2f64.powi(exponent);
+    if bits & (1 << 47) != 0 { -value } else { value }
+}
+
+
+pub fn float_to_bytes(value: f64) -> [u8; 6] {
+    let mut bytes = [0u8; 6];
+    if value == 0.0 || !value.is_finite() {
+        return bytes;
+    }
+
+    // find exponent, so fraction is normalized to [0.5, 1)
+    let mut magnitude = value.abs();
+    let mut exponent: i32 = 0;
+    while magnitude >= 1.0 {
+        magnitude /= 2.0;
+        exponent += 1;
+    }
+    while magnitude < 0.5 {
+        magnitude *= 2.0;
+        exponent -= 1;
+    }
+    let mut fraction = (magnitude * (1u64 << 36) as f64).round() as u64;
+    if fraction == 1 << 36 {
+        fraction >>= 1;
+        exponent += 1;
+    }
+    // numbers too small or too large for 11 bit exponent
+    if exponent + 1024 < 0 {
+        return bytes;
+    }
+    let exponent = cmp::min(exponent + 1024, 0x7FF) as u64;
+
+    let mut bits = (exponent << 36) | fraction;
+    if value < 0.0 {
+        bits |= 1 << 47;
+    }
+    for i in (0..6).rev() {
+        bytes[i] = (bits & 0xFF) as u8;
+        bits >>= 8;
+    }
+    bytes
+}
+
+
+// devices 0, 1 and 2 are stdin, stdout and stderr, others are files named by number, ie. F1.dev
+pub fn new_machine(program: &LinkedProgram, device_paths: &HashMap<u8, String>) -> Result<Machine, String> {
+    if program.start + program.length > MEMORY_SIZE {
+        return Err(format!("Program ends at {:06X}, which is outside of 1 MiB memory", program.start + program.length));
+    }
+    let mut memory = vec![0; MEMORY_SIZE];
+    memory[program.start..program.start+program.length].copy_from_slice(&program.memory);
+
+    let mut devices: HashMap<u8, Device> = HashMap::new();
+    for (number, path) in device_paths.iter() {
+        devices.insert(*number, Device{path: path.to_owned(), input: None, output: None});
+    }
+
+    let mut regs = [0; 10];
+    regs[REG_PC] = program.entry as i32;
+    Ok(Machine{memory: memory, regs: regs, f: 0.0, devices: devices})
+}
+
+
+fn device_path(number: u8) -> String {
+    match number {
+        0 => "stdin".to_owned(),
+        1 => "stdout".to_owned(),
+        2 => "stderr".to_owned(),
+        _ => format!("{:02X}.dev", number)
+    }
+}
+
+
+fn read_device(machine: &mut Machine, number: u8) -> Result<u8, String> {
+    let device = machine.devices.entry(number).or_insert(Device{path: device_path(number), input: None, output: None});
+    let mut byte = [0u8; 1];
+    // end of input is read as 0
+    let read = if device.path == "stdin" {
+                    std::io::stdin().read(&mut byte)
+               }
+               else {
+                    if device.input.is_none() {
+                        match File::open(&device.path) {
+                            Ok(file) => device.input = Some(file),
+                            Err(_) => return Err(format!("Can't open device {:02X} ({}) for reading", number, device.path))
+                        }
+                    }
+                    device.input.as_mut().unwrap().read(&mut byte)
+               };
+    match read {
+        Ok(_) => Ok(byte[0]),
+        Err(_) => Err(format!("Can't read from device {:02X} ({})", number, device.path))
+    }
+}
+
+
+fn write_device(machine: &mut Machine, number: u8, byte: u8) -> Result<u8, String> {
+    let device = machine.devices.entry(number).or_insert(Device{path: device_path(number), input: None, output: None});
+    let written = match device.path.as_str() {
+                    "stdout" => std::io::stdout().write_all(&[byte]),
+                    "stderr" => std::io::stderr().write_all(&[byte]),
+                    _ => { if device.output.is_none() {
+                               match File::create(&device.path) {
+                                   Ok(file) => device.output = Some(file),
+                                   Err(_) => return Err(format!("Can't open device {:02X} ({}) for writing", number, device.path))
+                               }
+                           }
+                           device.output.as_mut().unwrap().write_all(&[byte]) }
+                  };
+    match written {
+        Ok(_) => Ok(1),
+        Err(_) => Err(format!("Can't write to device {:02X} ({})", number, device.path))
+    }
+}
+
+
+fn check_address(address: usize, size: usize) -> Result<usize, String> {
+    if address + size > MEMORY_SIZE {
+        return Err(format!("Address {:06X} is outside of memory", address));
+    }
+    Ok(address)
+}
+
+
+fn read_byte(machine: &Machine, address: usize) -> Result<u8, String> {
+    match check_address(address, 1) {
+        Ok(addr) => Ok(machine.memory[addr]),
+        Err(msg) => Err(msg)
+    }
+}
+
+
+fn read_word(machine: &Machine, address: usize) -> Result<i32, String> {
+    match check_address(address, 3) {
+        Ok(addr) => Ok(((machine.memory[addr] as i32) << 16) | ((machine.memory[addr+1] as i32) << 8) | machine.memory[addr+2] as i32),
+        Err(msg) => Err(msg)
+    }
+}
+
+
+fn write_word(machine: &mut Machine, address: usize, value: i32) -> Result<u8, String> {
+    match check_address(address, 3) {
+        Ok(addr) => { machine.memory[addr] = ((value >> 16) & 0xFF) as u8;
+                      machine.memory[addr+1] = ((value >> 8) & 0xFF) as u8;
+                      machine.memory[addr+2] = (value & 0xFF) as u8; },
+        Err(msg) => return Err(msg)
+    }
+    Ok(1)
+}
+
+
+fn get_reg(machine: &Machine, reg: usize) -> Result<i32, String> {
+    match reg {
+        REG_F => Ok(to_word(machine.f as i64)),
+        0..=5 | 8 | 9 => Ok(machine.regs[reg]),
+        _ => Err(format!("{} is not a valid register number", reg))
+    }
+}
+
+
+fn set_reg(machine: &mut Machine, reg: usize, value: i32) -> Result<u8, String> {
+    match reg {
+        REG_F => machine.f = to_signed(value) as f64,
+        0..=5 | 8 | 9 => machine.regs[reg] = to_word(value as i64),
+        _ => return Err(format!("{} is not a valid register number", reg))
+    }
+    Ok(1)
+}
+
+
+fn set_cc(machine: &mut Machine, ordering: Ordering) {
+    let cc = match ordering {
+                Ordering::Less => CC_LT,
+                Ordering::Equal => CC_EQ,
+                Ordering::Greater => CC_GT
+             };
+    machine.regs[REG_SW] = (machine.regs[REG_SW] & !CC_MASK) | cc;
+}
+
+
+fn cc(machine: &Machine) -> i32 {
+    machine.regs[REG_SW] & CC_MASK
+}
+
+
+// instructions without operands, returns true if machine halted
+fn execute_format_1(machine: &mut Machine, mnem: &str) -> Result<bool, String> {
+    match mnem {
+        "FIX" => machine.regs[REG_A] = to_word(machine.f as i64),
+        "FLOAT" => machine.f = to_signed(machine.regs[REG_A]) as f64,
+        // float is always kept normalized, channel instructions have no effect
+        "NORM" | "HIO" | "SIO" => (),
+        "TIO" => set_cc(machine, Ordering::Less),
+        _ => return Err(format!("{} is not format 1 instruction", mnem))
+    }
+    Ok(false)
+}
+
+
+// instructions on registers, r2 is shift count or service number for some
+fn execute_format_2(machine: &mut Machine, mnem: &str, r1: usize, r2: usize) -> Result<bool, String> {
+    let value1 = if mnem == "SVC" { 0 } else {
+                    match get_reg(machine, r1) {
+                        Ok(value) => value,
+                        Err(msg) => return Err(msg)
+                    }
+                 };
+    let value2 = if mnem == "CLEAR" || mnem == "TIXR" || mnem == "SVC" || mnem.starts_with("SHIFT") { 0 } else {
+                    match get_reg(machine, r2) {
+                        Ok(value) => value,
+                        Err(msg) => return Err(msg)
+                    }
+                 };
+
+    let result = match mnem {
+        "ADDR" => set_reg(machine, r2, value2.wrapping_add(value1)),
+        "SUBR" => set_reg(machine, r2, value2.wrapping_sub(value1)),
+        "MULR" => set_reg(machine, r2, to_word(to_signed(value2) as i64 * to_signed(value1) as i64)),
+        "DIVR" => if value1 == 0 {
+                    return Err("Division by zero".to_owned());
+                  }
+                  else {
+                    set_reg(machine, r2, to_word((to_signed(value2) / to_signed(value1)) as i64))
+                  },
+        "COMPR" => { set_cc(machine, to_signed(value1).cmp(&to_signed(value2)));
+                     Ok(1) },
+        "CLEAR" => set_reg(machine, r1, 0),
+        "RMO" => set_reg(machine, r2, value1),
+        // shift count is stored as is by assembler
+        "SHIFTL" => set_reg(machine, r1, ((value1 << r2) | (value1 >> (24 - r2))) & 0xFFFFFF),
+        "SHIFTR" => set_reg(machine, r1, to_signed(value1) >> r2),
+        "TIXR" => { machine.regs[REG_X] = to_word(machine.regs[REG_X] as i64 + 1);
+                    set_cc(machine, to_signed(machine.regs[REG_X]).cmp(&to_signed(value1)));
+                    Ok(1) },
+        "SVC" => return Ok(true),
+        _ => Err(format!("{} is not format 2 instruction", mnem))
+    };
+    match result {
+        Ok(_) => Ok(false),
+        Err(msg) => Err(msg)
+    }
+}
+
+
+// operand of format 3 and 4 instructions, immediate operand is target address itself
+fn operand_word(machine: &Machine, target: usize, immediate: bool) -> Result<i32, String> {
+    if immediate { Ok(target as i32) } else { read_word(machine, target) }
+}
+
+
+fn operand_byte(machine: &Machine, target: usize, immediate: bool) -> Result<u8, String> {
+    if immediate { Ok((target & 0xFF) as u8) } else { read_byte(machine, target) }
+}
+
+
+fn operand_float(machine: &Machine, target: usize, immediate: bool) -> Result<f64, String> {
+    if immediate {
+        return Ok(target as f64);
+    }
+    match check_address(target, 6) {
+        Ok(addr) => Ok(float_from_bytes(&machine.memory[addr..addr+6])),
+        Err(msg) => Err(msg)
+    }
+}
+
+
+// instructions with memory operand, returns true if machine halted
+fn execute_format_3(machine: &mut Machine, mnem: &str, target: usize, immediate: bool, instr_addr: usize) -> Result<bool, String> {
+    let store_reg = match mnem {
+                        "STA" => Some(0), "STX" => Some(1), "STL" => Some(2), "STB" => Some(3),
+                        "STS" => Some(4), "STT" => Some(5), "STSW" => Some(9),
+                        _ => None
+                    };
+    let load_reg = match mnem {
+                        "LDA" => Some(0), "LDX" => Some(1), "LDL" => Some(2), "LDB" => Some(3),
+                        "LDS" => Some(4), "LDT" => Some(5),
+                        _ => None
+                   };
+
+    if let Some(reg) = store_reg {
+        if immediate {
+            return Err(format!("{} can't have immediate operand", mnem));
+        }
+        let value = machine.regs[reg];
+        return match write_word(machine, target, value) {
+                    Ok(_) => Ok(false),
+                    Err(msg) => Err(msg)
+               };
+    }
+    if let Some(reg) = load_reg {
+        return match operand_word(machine, target, immediate) {
+                    Ok(value) => { machine.regs[reg] = value; Ok(false) },
+                    Err(msg) => Err(msg)
+               };
+    }
+
+    match mnem {
+        "ADD" | "SUB" | "MUL" | "DIV" | "AND" | "OR" | "COMP" | "TIX" => {
+            let value = match operand_word(machine, target, immediate) {
+                            Ok(value) => value,
+                            Err(msg) => return Err(msg)
+                        };
+            let a = to_signed(machine.regs[REG_A]) as i64;
+            let v = to_signed(value) as i64;
+            match mnem {
+                "ADD" => machine.regs[REG_A] = to_word(a + v),
+                "SUB" => machine.regs[REG_A] = to_word(a - v),
+                "MUL" => machine.regs[REG_A] = to_word(a * v),
+                "DIV" => if v == 0 {
+                            return Err("Division by zero".to_owned());
+                         }
+                         else {
+                            machine.regs[REG_A] = to_word(a / v)
+                         },
+                "AND" => machine.regs[REG_A] &= value,
+                "OR" => machine.regs[REG_A] |= value,
+                "COMP" => set_cc(machine, a.cmp(&v)),
+                _ => { machine.regs[REG_X] = to_word(machine.regs[REG_X] as i64 + 1);
+                       set_cc(machine, (to_signed(machine.regs[REG_X]) as i64).cmp(&v)); }
+            }
+        },
+        "ADDF" | "SUBF" | "MULF" | "DIVF" | "COMPF" | "LDF" => {
+            let value = match operand_float(machine, target, immediate) {
+                            Ok(value) => value,
+                            Err(msg) => return Err(msg)
+                        };
+            match mnem {
+                "ADDF" => machine.f += value,
+                "SUBF" => machine.f -= value,
+                "MULF" => machine.f *= value,
+                "DIVF" => if value == 0.0 {
+                            return Err("Division by zero".to_owned());
+                          }
+                          else {
+                            machine.f /= value
+                          },
+                "COMPF" => set_cc(machine, machine.f.partial_cmp(&value).unwrap_or(Ordering::Equal)),
+                _ => machine.f = value
+            }
+        },
+        "STF" => match check_address(target, 6) {
+                    Ok(addr) => machine.memory[addr..addr+6].copy_from_slice(&float_to_bytes(machine.f)),
+                    Err(msg) => return Err(msg)
+                 },
+        "LDCH" => match operand_byte(machine, target, immediate) {
+                    Ok(byte) => machine.regs[REG_A] = (machine.regs[REG_A] & 0xFFFF00) | byte as i32,
+                    Err(msg) => return Err(msg)
+                  },
+        "STCH" => match check_address(target, 1) {
+                    Ok(addr) => machine.memory[addr] = (machine.regs[REG_A] & 0xFF) as u8,
+                    Err(msg) => return Err(msg)
+                  },
+        "J" => { // jump to itself is the usual way to end program
+                 machine.regs[REG_PC] = target as i32;
+                 if target == instr_addr {
+                     return Ok(true);
+                 } },
+        "JEQ" => if cc(machine) == CC_EQ { machine.regs[REG_PC] = target as i32 },
+        "JGT" => if cc(machine) == CC_GT { machine.regs[REG_PC] = target as i32 },
+        "JLT" => if cc(machine) == CC_LT { machine.regs[REG_PC] = target as i32 },
+        "JSUB" => { machine.regs[REG_L] = machine.regs[REG_PC];
+                    machine.regs[REG_PC] = target as i32; },
+        "RSUB" => machine.regs[REG_PC] = machine.regs[REG_L],
+        "RD" | "WD" | "TD" => {
+            let number = match operand_byte(machine, target, immediate) {
+                            Ok(number) => number,
+                            Err(msg) => return Err(msg)
+                         };
+            match mnem {
+                "RD" => match read_device(machine, number) {
+                            Ok(byte) => machine.regs[REG_A] = (machine.regs[REG_A] & 0xFFFF00) | byte as i32,
+                            Err(msg) => return Err(msg)
+                        },
+                "WD" => match write_device(machine, number, (machine.regs[REG_A] & 0xFF) as u8) {
+                            Ok(_) => (),
+                            Err(msg) => return Err(msg)
+                        },
+                // devices are always ready
+                _ => set_cc(machine, Ordering::Less)
+            }
+        },
+        // privileged instructions have no effect
+        "LPS" | "SSK" | "STI" => (),
+        _ => return Err(format!("{} is not format 3 instruction", mnem))
+    }
+    Ok(false)
+}
+
+
+// execute one instruction, returns true if machine halted
+pub fn step(machine: &mut Machine) -> Result<bool, String> {
+    let pc = machine.regs[REG_PC] as usize;
+    let byte1 = match read_byte(machine, pc) {
+                    Ok(byte) => byte,
+                    Err(msg) => return Err(msg)
+                };
+    let mnem = match find_mnemonic(byte1) {
+                    Some(mnem) => mnem,
+                    None => return Err(format!("Invalid opcode {:02X} at {:06X}", byte1, pc))
+               };
+
+    match determine_format(mnem) {
+        Ok(1) => { machine.regs[REG_PC] = (pc + 1) as i32;
+                   return execute_format_1(machine, mnem); },
+        Ok(2) => { let byte2 = match read_byte(machine, pc + 1) {
+                                    Ok(byte) => byte,
+                                    Err(msg) => return Err(msg)
+                               };
+                   machine.regs[REG_PC] = (pc + 2) as i32;
+                   return execute_format_2(machine, mnem, (byte2 >> 4) as usize, (byte2 & 0x0F) as usize); },
+        _ => ()
+    }
+
+    let bytes = match check_address(pc, 3) {
+                    Ok(addr) => [machine.memory[addr], machine.memory[addr+1], machine.memory[addr+2]],
+                    Err(msg) => return Err(msg)
+                };
+    let ni = byte1 & 0x03;
+    let indexed = bytes[1] & 0x80 != 0;
+    let base_rel = bytes[1] & 0x40 != 0;
+    let pc_rel = bytes[1] & 0x20 != 0;
+    let extended = bytes[1] & 0x10 != 0;
+
+    let mut target: i64;
+    if ni == 0 {
+        // SIC format has 15 bit address and only x bit
+        target = (((bytes[1] & 0x7F) as i64) << 8) | bytes[2] as i64;
+        machine.regs[REG_PC] = (pc + 3) as i32;
+    }
+    else if extended {
+        let byte4 = match read_byte(machine, pc + 3) {
+                        Ok(byte) => byte,
+                        Err(msg) => return Err(msg)
+                    };
+        target = (((bytes[1] & 0x0F) as i64) << 16) | ((bytes[2] as i64) << 8) | byte4 as i64;
+        machine.regs[REG_PC] = (pc + 4) as i32;
+    }
+    else {
+        let disp = (((bytes[1] & 0x0F) as i64) << 8) | bytes[2] as i64;
+        machine.regs[REG_PC] = (pc + 3) as i32;
+        target = if pc_rel && base_rel {
+                    return Err(format!("Instruction at {:06X} is both PC and base relative", pc));
+                 }
+                 else if pc_rel {
+                    // displacement is 12 bit signed number
+                    let signed = if disp & 0x800 != 0 { disp - 0x1000 } else { disp };
+                    (pc + 3) as i64 + signed
+                 }
+                 else if base_rel {
+                    machine.regs[REG_B] as i64 + disp
+                 }
+                 else {
+                    disp
+                 };
+    }
+    if indexed {
+        target += machine.regs[REG_X] as i64;
+    }
+    target &= 0xFFFFFF;
+
+    // indirect addressing takes address from memory
+    if ni == 2 {
+        target = match read_word(machine, target as usize) {
+                    Ok(addr) => addr as i64,
+                    Err(msg) => return Err(msg)
+                 };
+    }
+    execute_format_3(machine, mnem, target as usize, ni == 1, pc)
+}
+
+
+// run until program halts or limit of steps is reached, returns number of executed instructions
+pub fn run(machine: &mut Machine, max_steps: Option<usize>) -> Result<usize, String> {
+    let mut steps = 0;
+    loop {
+        match max_steps {
+            // error of program is reported, not failure to flush output it wrote before it
+            Some(limit) if steps >= limit => { let _ = flush_devices(machine);
+                                               return Err(format!("Program didn't halt after {} instructions", limit)); },
+            _ => ()
+        }
+        let pc = machine.regs[REG_PC];
+        steps += 1;
+        match step(machine) {
+            Ok(true) => break,
+            Ok(false) => (),
+            Err(msg) => { let _ = flush_devices(machine);
+                          return Err(format!("{} (instruction at {:06X})", msg, pc)); }
+        }
+    }
+    match flush_devices(machine) {
+        Ok(_) => Ok(steps),
+        Err(msg) => Err(msg)
+    }
+}
+
+
+fn flush_devices(machine: &mut Machine) -> Result<u8, String> {
+    if std::io::stdout().flush().is_err() {
+        return Err("Can't write to device 01 (stdout)".to_owned());
+    }
+    for (number, device) in machine.devices.iter_mut() {
+        if let Some(file) = device.output.as_mut() {
+            if file.flush().is_err() {
+                return Err(format!("Can't write to device {:02X} ({})", number, device.path));
+            }
+        }
+    }
+    Ok(1)
+}
+
+
+// values of all registers, one line for each
+pub fn registers(machine: &Machine) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for (reg, name) in REG_NAMES.iter().enumerate() {
+        match reg {
+            REG_F => lines.push(format!("{:<3}{}", name, machine.f)),
+            7 => (),
+            _ => lines.push(format!("{:<3}{:06X} ({})", name, machine.regs[reg], to_signed(machine.regs[reg])))
+        }
+    }
+    lines
+}
@@ -1,123 +1,482 @@
 use crate::commands::*;
-use crate::parser::parse_num;
+use crate::parser::{parse_symbol_list, SourceLine};
+use crate::lexer::{Token, TokenKind, tokens_text};
+use crate::ast::{Line, Operand, operation, is_instruction, directive_value, directive_values, directive_symbol, literal_name};
+use crate::expressions::{ValueKind, Scope, parse_expression, expr_symbols, uses_location, eval_expr, eval_constant};
 use crate::equresolution::*;
-use std::collections::HashMap;
+use crate::diagnostics::{Diagnostic, error, at_token, with_hint, line_error, locate};
+use std::collections::{HashMap, HashSet};
 
-pub fn get_symbol_table(program: &Vec<String>) -> Result<(usize, HashMap<String, i32>), String> { 
-    let mut symtab: HashMap<String, i32> = HashMap::new();
 
-    let mut equtab: HashMap<String, EquExpression> = HashMap::new();
+// control section, started by START or CSECT, each has its own symbol table and location counter
+#[derive(Debug)]
+pub struct Section {
+    pub name: String,
+    pub start: usize,
+    pub length: usize,
+    pub symtab: HashMap<String, i32>,
+    // symbols whose value doesn't change when program is relocated, ie. EQU 42
+    pub absolute: HashSet<String>,
+    pub extdef: Vec<String>,
+    pub extref: Vec<String>,
+    // symbols listed by EXPORTS, labels are also written to D records
+    pub exports: Vec<String>,
+    pub literals: Vec<Literal>,
+    pub blocks: Vec<Block>,
+    // address written to end record, only first section has it
+    pub entry: Option<usize>
+}
+
+
+// program block, selected by USE, each has its own location counter inside section
+// blocks are placed one after another in order of their first appearance
+#[derive(Debug)]
+pub struct Block {
+    pub name: String,
+    pub start: usize,
+    pub length: usize
+}
+
+
+// literal operand, ie. =C'EOF', =X'05' or =42
+#[derive(Debug)]
+pub struct Literal {
+    pub name: String,
+    pub code: String,
+    // literals are placed at next LTORG, CSECT or END, each of those starts new pool
+    pub pool: usize,
+    pub block: usize,
+    pub address: usize
+}
+
+
+fn new_section(name: &str, start: usize, entry: Option<usize>) -> Section {
+    // every section starts in default block, which has no name
+    let default_block = Block{name: String::new(), start: start, length: 0};
+    Section{name: name.to_owned(), start: start, length: 0, symtab: HashMap::new(), absolute: HashSet::new(), extdef: Vec::new(), extref: Vec::new(), exports: Vec::new(), literals: Vec::new(), blocks: vec![default_block], entry: entry}
+}
+
+
+// symbols of section that expressions can use, loc is value of *
+pub fn section_scope(section: &Section, loc: usize) -> Scope<'_> {
+    Scope{symtab: &section.symtab, absolute: &section.absolute, extref: &section.extref, loc: loc}
+}
 
-    let mut loc_counter: usize;
-    // find start index, skip leading empty lines or comments
-    let mut st_index = 0;
-    while program[st_index].starts_with(".") || program[st_index] == "" { st_index += 1;}
 
-    let start: Vec<&str> = program[st_index].split(" ").collect();
-    if start[0] == "START" {
-        return Err("Program needs to start with <name of program> START <address>".to_owned());
+// add literal from instruction operand to current pool, same literal is stored only once per pool
+fn add_literal(section: &mut Section, operands: &Vec<Operand>, pool: usize) -> Result<u8, Diagnostic> {
+    let literal = match operands.first() {
+                        Some(Operand::Literal(literal)) => literal,
+                        _ => return Ok(0)
+                  };
+
+    let name = literal_name(literal);
+    if section.literals.iter().any(|lit| lit.pool == pool && lit.name == name) {
+        return Ok(1);
     }
-    else if start[1] == "START"{
-        loc_counter = start[2].parse::<usize>().unwrap();
+    match handle_literal(literal) {
+        Ok(code) => section.literals.push(Literal{name: name, code: code, pool: pool, block: 0, address: 0}),
+        Err(diag) => return Err(diag)
     }
-    else {
-        return Err("Program needs to start with <name of program> START <address>".to_owned());
+    Ok(1)
+}
+
+
+// place literals of pool at current location of block, returns location after pool
+fn place_literals(section: &mut Section, pool: usize, block: usize, mut loc_counter: usize) -> usize {
+    for lit in section.literals.iter_mut().filter(|lit| lit.pool == pool) {
+        lit.block = block;
+        lit.address = loc_counter;
+        loc_counter += lit.code.len() / 2;
     }
+    loc_counter
+}
 
-    let mut line_count = 1;
 
-    for line in program[st_index+1..].iter() {
-        line_count += 1;
-        if line == "" || line.starts_with(".") {
-            // empty or comment line
+// switch to block with given name, returns its index, block is created on first use
+fn use_block(section: &mut Section, name: &str) -> usize {
+    match section.blocks.iter().position(|blk| blk.name == name) {
+        Some(index) => index,
+        None => { section.blocks.push(Block{name: name.to_owned(), start: 0, length: 0});
+                  section.blocks.len() - 1 }
+    }
+}
+
+
+// finish section when next CSECT or END is reached, EQUs are resolved per section
+// exported are names of EXTDEF and EXPORTS with their directive and line, so undefined ones are reported there
+fn close_section(section: &mut Section, equtab: &Vec<EquExpression>, exported: &Vec<(&str, String, &SourceLine)>, sym_block: &HashMap<String, usize>, block: usize, loc_counter: usize,
+                 diagnostics: &mut Vec<Diagnostic>) {
+    section.blocks[block].length = loc_counter;
+
+    // now that all lengths are known blocks can be placed one after another
+    let mut addr = section.start;
+    for blk in section.blocks.iter_mut() {
+        blk.start = addr;
+        addr += blk.length;
+    }
+    section.length = addr - section.start;
+
+    // labels and literals were relative to their block, move them to final layout
+    for (name, value) in section.symtab.iter_mut() {
+        // absolute symbols have no block
+        match sym_block.get(name) {
+            Some(blk) => *value += section.blocks[*blk].start as i32,
+            None => ()
+        }
+    }
+    for lit in section.literals.iter_mut() {
+        lit.address += section.blocks[lit.block].start;
+    }
+
+    // EQUs that had to wait for final addresses
+    diagnostics.extend(resolve_equs(section, equtab));
+
+    // exported symbols must be defined inside section
+    for (dir, name, src) in exported.iter() {
+        if !section.symtab.contains_key(name) {
+            diagnostics.push(line_error(src, name, format!("{} symbol {} is not defined in section {}", dir, name, section.name)));
+        }
+    }
+}
+
+
+/*
+    symbol in value of directive that moves location counter, ie. LEN in RESB LEN, which is defined only after it
+    location of every line must be known in first pass, so such symbol is reported at its use with line of its definition
+*/
+fn defined_later(op: &str, values: &Vec<&[Token]>, section: &Section, equtab: &Vec<EquExpression>, program: &Vec<SourceLine>, lines: &Vec<Option<Line>>,
+                 index: usize) -> Result<u8, Diagnostic> {
+    if !matches!(op, "RESB" | "RESW" | "RESF" | "FILL" | "ALIGN" | "ORG") {
+        return Ok(1);
+    }
+    for tok in values.iter().flat_map(|value| value.iter()) {
+        if !matches!(tok.kind, TokenKind::Symbol | TokenKind::Register) || section.symtab.contains_key(&tok.text) || section.extref.contains(&tok.text) {
             continue;
         }
+        // EQU before this line that uses symbols defined after it
+        match equtab.iter().find(|equ| equ.name == tok.text) {
+            Some(equ) => return Err(with_hint(at_token(tok, format!("{} must be defined before it is used in {}", tok.text, op)),
+                                              &format!("EQU of {} at {}:{} uses symbols defined after this line, location counter of every line must be known in first pass",
+                                                       tok.text, equ.src.file, equ.src.line))),
+            None => ()
+        }
+        // label of later line in the same section
+        let later = lines[index+1..].iter().zip(program[index+1..].iter())
+                                    .take_while(|(line, _)| !matches!(line, Some(line) if operation(line) == "CSECT"))
+                                    .find(|(line, _)| matches!(line, Some(line) if line.label.as_ref().is_some_and(|label| label.text == tok.text)));
+        match later {
+            Some((_, src)) => return Err(with_hint(at_token(tok, format!("{} must be defined before it is used in {}", tok.text, op)),
+                                                   &format!("{} is defined at {}:{}, location counter of every line must be known in first pass", tok.text, src.file, src.line))),
+            None => ()
+        }
+    }
+    Ok(1)
+}
+
+
+fn start_error() -> Diagnostic {
+    with_hint(error("Program needs to start with <name of program> START <address>".to_owned()), "PROG START 0")
+}
 
-        let split: Vec<&str> = line.split(" ").collect();
 
-        let instr_size: Result<usize, String>;
-        if !is_instr(split[0]) && !is_directive(split[0]) {
+/*
+    first pass, collects symbols and sizes of control sections
+    lines with errors are reported to diagnostics and skipped, so rest of program is still checked
+    Err is returned only when program can't be assembled at all
+*/
+pub fn get_symbol_table(program: &Vec<SourceLine>, lines: &Vec<Option<Line>>, diagnostics: &mut Vec<Diagnostic>) -> Result<Vec<Section>, Diagnostic> {
+    let mut sections: Vec<Section> = Vec::new();
+
+    // EQUs of current section that are resolved when it's closed
+    let mut equtab: Vec<EquExpression> = Vec::new();
+    // names given to EXTDEF and EXPORTS in current section, checked when it's closed
+    let mut exported: Vec<(&str, String, &SourceLine)> = Vec::new();
+
+    let start_addr: usize;
+    // find start index, skip leading empty lines or comments
+    let mut st_index = 0;
+    while st_index < program.len() && (program[st_index].text.starts_with(".") || program[st_index].text == "") { st_index += 1;}
+    if st_index == program.len() {
+        return Err(start_error());
+    }
+
+    let start_src = &program[st_index];
+    let start = match &lines[st_index] {
+                    Some(line) if operation(line) == "START" && line.label.is_some() => line,
+                    _ => return Err(locate(start_error(), start_src))
+                };
+    match directive_value(start) {
+        // symbols aren't defined yet, so address can only be made of numbers
+        Ok(value) if value.iter().any(|tok| tok.kind == TokenKind::Symbol || tok.kind == TokenKind::Register) => {
+            let symbol = value.iter().find(|tok| tok.kind == TokenKind::Symbol || tok.kind == TokenKind::Register).unwrap();
+            return Err(locate(at_token(symbol, format!("Start address {} is not a number", symbol.text)), start_src));
+        },
+        Ok(value) => match eval_constant(value) {
+                        Ok(addr) if addr >= 0 => start_addr = addr as usize,
+                        Ok(_) => return Err(locate(at_token(&value[0], "Start address can't be negative".to_owned()), start_src)),
+                        Err(diag) => return Err(locate(diag, start_src))
+                     },
+        Err(_) => return Err(locate(start_error(), start_src))
+    }
+
+    // first section is started by START, by default execution begins at its start
+    let mut section = new_section(&start.label.as_ref().unwrap().text, start_addr, Some(start_addr));
+
+    // location counter is relative to start of current block, block of every label is needed to get final address
+    let mut loc_counter: usize = 0;
+    let mut block = 0;
+    let mut sym_block: HashMap<String, usize> = HashMap::new();
+
+    // address given to END, it can only be resolved once first section is closed
+    let mut end_value: Option<(&[Token], &SourceLine)> = None;
+
+    // literals are collected to current pool until it's placed
+    let mut pool = 0;
+
+    for (index, (src, line)) in program.iter().zip(lines.iter()).enumerate().skip(st_index + 1) {
+        let line = match line {
+                        Some(line) => line,
+                        // line in IF branch that isn't assembled, line with error or line after END
+                        None => continue
+                   };
+        let op = operation(line);
+        if op == "" && line.label.is_none() {
+            // empty or comment line
+            continue;
+        }
+
+        let instr_size: Result<usize, Diagnostic>;
+        if let Some(label) = &line.label {
             // 1st word is label
+            let name = label.text.as_str();
+
+            if op == "CSECT" {
+                // label is name of new control section
+                if name.len() > 6 {
+                    return Err(locate(at_token(label, format!("section name must be at most 6 characters wide, {} is {}", name, name.len())), src));
+                }
+                if section.name == name || sections.iter().any(|sect| sect.name == name) {
+                    return Err(locate(at_token(label, format!("duplicate section name: {}", name)), src));
+                }
+
+                // remaining literals belong to section that is being closed
+                loc_counter = place_literals(&mut section, pool, block, loc_counter);
+                close_section(&mut section, &equtab, &exported, &sym_block, block, loc_counter, diagnostics);
+                sections.push(section);
+                equtab = Vec::new();
+                exported = Vec::new();
+
+                // each section has its own location counter, blocks and literal pools, starting at 0
+                loc_counter = 0;
+                block = 0;
+                pool = 0;
+                sym_block = HashMap::new();
+                section = new_section(name, 0, None);
+                continue;
+            }
+
+            if matches!(op, "SET" | "IF" | "ELSE" | "ENDIF") {
+                // SET symbols were already filled in by conditional assembly, it also reports labels of IF, ELSE and ENDIF
+                continue;
+            }
 
-            if symtab.contains_key(split[0]) {
-                return Err(format!("Line {}, duplicate label: {}", line_count, split[0]));
+            if section.symtab.contains_key(name) || equtab.iter().any(|equ| equ.name == name) {
+                diagnostics.push(locate(at_token(label, format!("duplicate label: {}", name)), src));
+                continue;
+            }
+            if section.extref.iter().any(|ext| ext == name) {
+                diagnostics.push(locate(at_token(label, format!("label {} is already declared in EXTREF", name)), src));
+                continue;
             }
 
-            if is_instr(split[1]) {
+            if is_instruction(line) {
                 // label for instr
-                instr_size = determine_command_size(split[1]);
+                instr_size = determine_command_size(op);
+
+                match add_literal(&mut section, &line.operands, pool) {
+                    Ok(_) => (),
+                    Err(diag) => { diagnostics.push(locate(diag, src));
+                                   continue; }
+                }
             }
-            else if is_directive(split[1]) {
-                // label for directive
-                if split[1] == "EQU" {
-                    // equ contains current addr
-                    if split[2] == "*" {
-                        insert_equ_number(&mut equtab, split[0], loc_counter as i32);
+            else if op == "EQU" {
+                let value = match directive_value(line) {
+                                Ok(value) => value,
+                                Err(diag) => { diagnostics.push(locate(diag, src));
+                                               continue; }
+                            };
+                if value.len() == 1 && value[0].kind == TokenKind::Operator('*') {
+                    // equ contains current addr, which is the same as label, so it's also relative to block
+                    instr_size = Ok(0);
+                }
+                else {
+                    let expr = match parse_expression(value) {
+                                    Ok(expr) => expr,
+                                    Err(diag) => { diagnostics.push(locate(diag, src));
+                                                   continue; }
+                               };
+                    let symbols = expr_symbols(&expr);
+                    let defined = symbols.iter().all(|tok| section.symtab.contains_key(&tok.text) || section.extref.contains(&tok.text));
+                    // blocks of relative symbols and *, their difference is only known now when they are in the same block
+                    let mut blocks: Vec<usize> = symbols.iter().filter_map(|tok| sym_block.get(&tok.text).copied()).collect();
+                    if uses_location(&expr) {
+                        blocks.push(block);
                     }
-                    // equ contains value
-                    else {
-                        
+                    blocks.sort();
+                    blocks.dedup();
 
-                        match insert_equ_str(&mut equtab, split[0], &split[2..].to_vec()) {
+                    if defined && blocks.len() <= 1 {
+                        match eval_expr(value, &section_scope(&section, loc_counter)) {
+                            Ok(equ) if equ.refs.len() > 0 => diagnostics.push(locate(at_token(&value[0], "EQU can't use external reference".to_owned()), src)),
+                            Ok(equ) => { section.symtab.insert(name.to_owned(), equ.value);
+                                         if equ.kind == ValueKind::Absolute {
+                                             section.absolute.insert(name.to_owned());
+                                         }
+                                         else {
+                                             // relative value is in the same block as symbols it's made of
+                                             sym_block.insert(name.to_owned(), blocks.first().copied().unwrap_or(block));
+                                         } },
+                            Err(diag) => diagnostics.push(locate(diag, src))
+                        }
+                    }
+                    else {
+                        // equ uses symbols defined later or labels of several blocks, it's resolved once blocks are placed
+                        match insert_equ_str(&mut equtab, name, value, block, loc_counter, src) {
                             Ok(_) => (),
-                            Err(msg) => return Err(format!("Line {}, failed inserting EQU {}", line_count, msg))
+                            Err(diag) => diagnostics.push(locate(diag, src))
                         }
                     }
                     continue;
                 }
-                instr_size = determine_res_size(split[1], &split[2..].to_vec());
             }
-            else if split[1].starts_with(".") {
+            else if op == "" {
                 // label + comment is still valid... kind of
                 instr_size = Ok(0);
             }
             else {
-                return Err(format!("Line {}, not a valid format.", line_count));
+                // label for directive
+                instr_size = match directive_values(line) {
+                                Ok(values) => defined_later(op, &values, &section, &equtab, program, lines, index)
+                                                .and_then(|_| determine_res_size(op, &values, &section_scope(&section, loc_counter))),
+                                Err(diag) => Err(diag)
+                             };
             }
 
-            // 1st word is label
-            symtab.insert(split[0].to_owned(), loc_counter as i32);
+            // label is defined even when its operands are wrong, so lines using it don't report more errors
+            section.symtab.insert(name.to_owned(), loc_counter as i32);
+            sym_block.insert(name.to_owned(), block);
         }
-        else if is_instr(split[0]) {
+        else if is_instruction(line) {
             // 1st word is an instruction
-            instr_size = determine_command_size(split[0]);
+            instr_size = determine_command_size(op);
+
+            match add_literal(&mut section, &line.operands, pool) {
+                Ok(_) => (),
+                Err(diag) => { diagnostics.push(locate(diag, src));
+                               continue; }
+            }
         }
-        else if is_directive(split[0]){
+        else {
             // 1st word is a directive
-            match split[0]{
-                "EQU" => return Err(format!("Line {}, can't use EQU without label", line_count)),
-                "END" => if split.len() == 1 || symtab.contains_key(&*split[1]) {
-                            break;
-                         }
-                         else {
-                            return Err(format!("Line {}, END directive invalid here. (END can't be used as label)", line_count));   
-                         },
-                "ORG" => instr_size = match parse_num(split[1]) {
-                                        Ok(value) => if value > 0 {
-                                                        Ok(value as usize)
-                                                     } 
-                                                     else { 
-                                                        return Err(format!("Line {}, ORG needs positive number", line_count))
-                                                     },
-                                        Err(msg) => Err(msg.to_owned())
-                                      },
+            match op {
+                "EQU" => { diagnostics.push(line_error(src, op, "can't use EQU without label".to_owned()));
+                           continue; },
+                "CSECT" => return Err(line_error(src, op, "CSECT needs name of section as label".to_owned())),
+                "EXTDEF" => { match parse_symbol_list(&line.operands) {
+                                Ok(symbols) => for sym in symbols {
+                                                    exported.push((op, sym.to_owned(), src));
+                                                    section.extdef.push(sym);
+                                               },
+                                Err(diag) => diagnostics.push(locate(diag, src))
+                              }
+                              continue; },
+                "EXPORTS" => { match parse_symbol_list(&line.operands) {
+                                Ok(symbols) => for sym in symbols {
+                                                    if !section.exports.contains(&sym) {
+                                                        exported.push((op, sym.to_owned(), src));
+                                                        section.exports.push(sym);
+                                                    }
+                                               },
+                                Err(diag) => diagnostics.push(locate(diag, src))
+                               }
+                               continue; },
+                "EXTREF" => { match parse_symbol_list(&line.operands) {
+                                Ok(symbols) => for sym in symbols {
+                                                    if section.symtab.contains_key(&sym) {
+                                                        diagnostics.push(line_error(src, &sym, format!("{} is already defined as label", sym)));
+                                                        continue;
+                                                    }
+                                                    section.extref.push(sym);
+                                               },
+                                Err(diag) => diagnostics.push(locate(diag, src))
+                              }
+                              continue; },
+                "END" => { match directive_value(line) {
+                                Ok(value) => end_value = Some((value, src)),
+                                Err(_) if line.operands.len() == 0 => (),
+                                Err(diag) => diagnostics.push(locate(diag, src))
+                           }
+                           break; },
+                "ORG" => { // location counter is relative to section, while absolute address isn't
+                           match directive_value(line).and_then(|value| defined_later(op, &vec![value], &section, &equtab, program, lines, index)
+                                                                            .and_then(|_| determine_org(value, &section_scope(&section, loc_counter), section.start))) {
+                                Ok(new_loc) => loc_counter = new_loc,
+                                Err(diag) => diagnostics.push(locate(diag, src))
+                           }
+                           continue; },
+                "ALIGN" => { match directive_value(line).and_then(|value| defined_later(op, &vec![value], &section, &equtab, program, lines, index)
+                                                                              .and_then(|_| determine_align(value, &section_scope(&section, loc_counter), loc_counter))) {
+                                Ok(padding) => loc_counter += padding,
+                                Err(diag) => diagnostics.push(locate(diag, src))
+                             }
+                             continue; },
                 "BASE" | "NOBASE" => continue,
-                _     => instr_size = determine_res_size(split[0], &split[1..].to_vec())
+                // SET without label is reported by conditional assembly
+                "IF" | "ELSE" | "ENDIF" | "SET" => continue,
+                "LTORG" => { loc_counter = place_literals(&mut section, pool, block, loc_counter);
+                             pool += 1;
+                             continue; },
+                "USE" => { // save location of block we are leaving, USE without name returns to default block
+                           section.blocks[block].length = loc_counter;
+                           let name = match directive_symbol(line) {
+                                            Ok(Some(name)) => name.text.as_str(),
+                                            Ok(None) => "",
+                                            Err(diag) => { diagnostics.push(locate(diag, src));
+                                                           continue; }
+                                      };
+                           block = use_block(&mut section, name);
+                           loc_counter = section.blocks[block].length;
+                           continue; },
+                _     => instr_size = match directive_values(line) {
+                                        Ok(values) => defined_later(op, &values, &section, &equtab, program, lines, index)
+                                                        .and_then(|_| determine_res_size(op, &values, &section_scope(&section, loc_counter))),
+                                        Err(diag) => Err(diag)
+                                      }
             }
         }
-        else {
-            // invalid
-            return Err(format!("Line {}, Not a valid instruction", line_count));
-        }
 
         match instr_size {
             Ok(size) => loc_counter += size,
-            Err(msg) => return Err(format!("{}, line {}", msg, line_count))
+            Err(diag) => diagnostics.push(locate(diag, src))
         }
     }
 
-    match resolve_equs(&mut symtab, &mut equtab) {
-        Ok(_) => Ok((loc_counter, symtab)),
-        Err(msg) => Err(format!("Failed resolving EQUs, {}", msg))
+    // literals that weren't placed by LTORG are placed at the end of program
+    loc_counter = place_literals(&mut section, pool, block, loc_counter);
+    close_section(&mut section, &equtab, &exported, &sym_block, block, loc_counter, diagnostics);
+    sections.push(section);
+
+    // execution starts at address given to END, symbols in it must be from first section
+    match end_value {
+        Some((value, end_src)) => match eval_expr(value, &section_scope(&sections[0], 0)) {
+                                        Ok(entry) if entry.refs.len() > 0 || entry.value < 0 =>
+                                            diagnostics.push(locate(at_token(&value[0], format!("END address {} is not in first section", tokens_text(value))), end_src)),
+                                        Ok(entry) => sections[0].entry = Some(entry.value as usize),
+                                        Err(diag) => diagnostics.push(locate(diag, end_src))
+                                  },
+        None => ()
     }
-}
\ No newline at end of file
+
+    Ok(sections)
+}
@@ -0,0 +1,141 @@
+use crate::commands::float_precision;
+use crate::parser::SourceLine;
+use crate::lexer::{Token, TokenKind, tokens_text};
+use crate::ast::{Line, Operand, operation, is_instruction, operand_tokens};
+use crate::symbols::{Section, section_scope};
+use crate::expressions::eval_expr;
+use crate::diagnostics::{Diagnostic, WarningKind, warning, with_hint, locate};
+use std::collections::HashSet;
+
+
+// names used in operands, char and hex constants are left out since their content isn't a name
+fn operand_names(operands: &Vec<Operand>) -> Vec<String> {
+    operands.iter()
+            .flat_map(|operand| operand_tokens(operand).iter())
+            .filter(|tok| tok.kind == TokenKind::Symbol || tok.kind == TokenKind::Register)
+            .map(|tok| tok.text.to_owned())
+            .collect()
+}
+
+
+// value of LDB and BASE operand, None when it can't be known
+fn operand_value(operand: &[Token], section: &Section) -> Option<i32> {
+    match eval_expr(operand, &section_scope(section, 0)) {
+        Ok(value) if value.refs.len() == 0 => Some(value.value),
+        _ => None
+    }
+}
+
+
+/*
+    warnings that need whole program: unused labels, code falling through into data and BASE that doesn't match LDB
+    program must have passed first pass without errors, so every line is valid
+*/
+pub fn check_warnings(program: &Vec<SourceLine>, lines: &Vec<Option<Line>>, sections: &Vec<Section>) -> Vec<Diagnostic> {
+    let mut warnings: Vec<Diagnostic> = Vec::new();
+
+    // labels with their section and line, names that are used in each section
+    let mut labels: Vec<(String, usize, &SourceLine)> = Vec::new();
+    let mut used: HashSet<(usize, String)> = HashSet::new();
+
+    let mut sect = 0;
+    // last instruction before data, None after data or when location changed
+    let mut prev_instr: Option<String> = None;
+    // value assembler assumes for base register and value last loaded by LDB #
+    let mut base: Option<i32> = None;
+    let mut loaded_base: Option<i32> = None;
+
+    let mut started = false;
+    for (src, line) in program.iter().zip(lines.iter()) {
+        let line = match line {
+                        Some(line) if line.label.is_some() || line.operation.is_some() => line,
+                        _ => continue
+                   };
+        let label = line.label.as_ref().map(|tok| tok.text.as_str()).unwrap_or("");
+        let mnem = operation(line);
+        let operands = &line.operands;
+        if !started {
+            // START line, its label is name of program
+            started = true;
+            continue;
+        }
+        let mnem = mnem.strip_prefix("+").unwrap_or(mnem);
+
+        if mnem == "CSECT" {
+            sect += 1;
+            prev_instr = None;
+            base = None;
+            loaded_base = None;
+            continue;
+        }
+
+        // labels from macro expansion are left out, they can't be removed from single call, and so are symbols from command line
+        if label != "" && mnem != "SET" && !src.expanded && src.line != 0 {
+            labels.push((label.to_owned(), sect, src));
+        }
+        // label of END is always in first section
+        let used_sect = if mnem == "END" { 0 } else { sect };
+        for name in operand_names(operands) {
+            used.insert((used_sect, name));
+        }
+
+        match mnem {
+            "BYTE" | "WORD" | "RESB" | "RESW" | "FLOAT" | "RESF" | "FILL" | "ASCIZ" | "ASCIC" if !is_instruction(line) => {
+                match prev_instr {
+                    Some(instr) if instr != "J" && instr != "RSUB" =>
+                        warnings.push(with_hint(locate(warning(WarningKind::FallThrough, mnem,
+                                                               format!("execution falls through from {} into data", instr)), src),
+                                                "end code with J or RSUB, or move data after it")),
+                    _ => ()
+                }
+                prev_instr = None; },
+            "USE" | "ORG" | "LTORG" | "END" => prev_instr = None,
+            "BASE" => { base = operands.first().and_then(|operand| operand_value(operand_tokens(operand), &sections[sect]));
+                        match (base, loaded_base) {
+                            (Some(value), Some(loaded)) if value != loaded =>
+                                warnings.push(locate(warning(WarningKind::BaseMismatch, &tokens_text(operand_tokens(&operands[0])),
+                                                             format!("BASE is {}, but LDB loaded {} into base register", value, loaded)), src)),
+                            _ => ()
+                        } },
+            "NOBASE" => base = None,
+            _ => ()
+        }
+
+        // floats that are written with more digits than they keep, in FLOAT and float literals
+        for operand in operands.iter() {
+            let lost = match operand {
+                            Operand::Literal(value) => float_precision(value),
+                            Operand::Simple(value) if mnem == "FLOAT" => float_precision(value),
+                            _ => None
+                       };
+            match lost {
+                Some(diag) => warnings.push(locate(diag, src)),
+                None => ()
+            }
+        }
+
+        if is_instruction(line) {
+            prev_instr = Some(mnem.to_owned());
+        }
+        if mnem == "LDB" {
+            // only immediate value is known, anything else is loaded from memory
+            loaded_base = match operands.first() {
+                                Some(Operand::Immediate(value)) => operand_value(value, &sections[sect]),
+                                _ => None
+                          };
+            match (base, loaded_base) {
+                (Some(value), Some(loaded)) if value != loaded =>
+                    warnings.push(locate(warning(WarningKind::BaseMismatch, &tokens_text(operand_tokens(&operands[0])),
+                                                 format!("LDB loads {} into base register, but BASE is {}", loaded, value)), src)),
+                _ => ()
+            }
+        }
+    }
+
+    for (label, sect, src) in labels {
+        if !used.contains(&(sect, label.to_owned())) {
+            warnings.push(locate(warning(WarningKind::UnusedLabel, &label, format!("label {} is never used", label)), src));
+        }
+    }
+    warnings
+}
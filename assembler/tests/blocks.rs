@@ -0,0 +1,44 @@
+mod common;
+
+use common::{assemble_ok, records};
+
+
+// data block is written between code lines, but placed after all code of default block
+const BLOCKS: &str = "\
+BLK     START   0
+FIRST   LDA     NUM
+        USE     CDATA
+NUM     WORD    7
+        USE
+        STA     RES
+HALT    J       HALT
+        USE     CBLKS
+RES     RESW    1
+        USE     CDATA
+TWO     WORD    2
+        END     FIRST";
+
+
+#[test]
+fn blocks_follow_each_other_in_order_of_first_use() {
+    let output = assemble_ok(BLOCKS);
+    let blocks: Vec<(String, usize, usize)> = output.sections[0].blocks.iter().map(|block| (block.name.to_owned(), block.start, block.length)).collect();
+    assert_eq!(blocks, vec![(String::new(), 0x00, 9), ("CDATA".to_owned(), 0x09, 6), ("CBLKS".to_owned(), 0x0F, 3)]);
+    assert_eq!(output.sections[0].length, 0x12);
+}
+
+
+#[test]
+fn labels_and_text_records_use_block_addresses() {
+    let output = assemble_ok(BLOCKS);
+    let symtab = &output.sections[0].symtab;
+    assert_eq!((symtab["NUM"], symtab["RES"], symtab["TWO"]), (0x09, 0x0F, 0x0C));
+    // new text record starts whenever block changes
+    assert_eq!(records(&output),
+               vec!["HBLK   000000000012",
+                    "T00000003032006",
+                    "T00000903000007",
+                    "T000003060F20093F2FFD",
+                    "T00000C03000002",
+                    "E000000"]);
+}
@@ -0,0 +1,59 @@
+// helpers shared by integration tests, each test file uses only some of them
+#![allow(dead_code)]
+// &Vec parameters are used on purpose throughout the assembler
+#![allow(clippy::ptr_arg)]
+
+use assembler::{assemble, objwriter, AssemblyOptions, AssemblyOutput, Diagnostic};
+use assembler::objreader::ObjSection;
+
+
+pub fn options(name: &str) -> AssemblyOptions {
+    AssemblyOptions{source_name: name.to_owned(), ..AssemblyOptions::default()}
+}
+
+
+// source must assemble without errors
+pub fn assemble_ok(source: &str) -> AssemblyOutput {
+    match assemble(source, &options("test.asm")) {
+        Ok(output) => output,
+        Err(diagnostics) => panic!("source doesn't assemble: {:?}\n{}", messages(&diagnostics), source)
+    }
+}
+
+
+// source must have errors, they are returned
+pub fn assemble_err(source: &str) -> Vec<Diagnostic> {
+    match assemble(source, &options("test.asm")) {
+        Ok(_) => panic!("source assembles, but it has errors:\n{}", source),
+        Err(diagnostics) => diagnostics
+    }
+}
+
+
+pub fn messages(diagnostics: &Vec<Diagnostic>) -> Vec<String> {
+    diagnostics.iter().map(|diag| diag.message.to_owned()).collect()
+}
+
+
+// object as it's written to .obj file
+pub fn object_text(object: &Vec<ObjSection>) -> String {
+    let mut text = String::new();
+    for section in object.iter() {
+        objwriter::write_obj_section(&mut text, section);
+    }
+    text
+}
+
+
+// records of object, one per line
+pub fn records(output: &AssemblyOutput) -> Vec<String> {
+    object_text(&output.object).lines().map(|line| line.to_owned()).collect()
+}
+
+
+// bytes of all text records of section, in order of their addresses
+pub fn text_bytes(section: &ObjSection) -> Vec<u8> {
+    let mut texts = section.texts.clone();
+    texts.sort_by_key(|(addr, _)| *addr);
+    texts.into_iter().flat_map(|(_, bytes)| bytes).collect()
+}
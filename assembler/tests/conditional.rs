@@ -0,0 +1,82 @@
+mod common;
+
+use common::{assemble_ok, assemble_err, messages, text_bytes};
+
+
+#[test]
+fn only_taken_branch_is_assembled() {
+    let output = assemble_ok("\
+PROG    START   0
+DEBUG   EQU     1
+        IF      DEBUG
+FIRST   LDA     #1
+        ELSE
+FIRST   LDA     #2
+        ENDIF
+        IF      DEBUG-1
+        LDA     #3
+        ELSE
+        LDA     #4
+        ENDIF
+HALT    J       HALT
+        END     FIRST");
+    assert_eq!(text_bytes(&output.object[0]), vec![0x01, 0x00, 0x01, 0x01, 0x00, 0x04, 0x3F, 0x2F, 0xFD]);
+}
+
+
+#[test]
+fn set_symbol_has_value_of_last_set_before_line() {
+    let output = assemble_ok("\
+PROG    START   0
+COUNT   SET     1
+FIRST   LDA     #COUNT
+COUNT   SET     COUNT+1
+        LDA     #COUNT
+NEG     SET     -2
+        LDA     #NEG
+HALT    J       HALT
+        END     FIRST");
+    assert_eq!(text_bytes(&output.object[0]), vec![0x01, 0x00, 0x01, 0x01, 0x00, 0x02, 0x01, 0x0F, 0xFE, 0x3F, 0x2F, 0xFD]);
+}
+
+
+#[test]
+fn comparison_is_reported_once() {
+    let diagnostics = assemble_err("\
+PROG    START   0
+DEBUG   EQU     1
+FIRST   LDA     #0
+        IF      DEBUG>0
+        LDA     #1
+        ENDIF
+HALT    J       HALT
+        END     FIRST");
+    assert_eq!(messages(&diagnostics), vec!["comparison > is not supported"]);
+    assert_eq!(diagnostics[0].line, 4);
+}
+
+
+#[test]
+fn if_uses_only_values_defined_before() {
+    let diagnostics = assemble_err("\
+PROG    START   0
+        IF      LATER
+FIRST   RSUB
+        ENDIF
+LATER   EQU     1
+        END     FIRST");
+    assert_eq!(messages(&diagnostics)[0], "LATER is not known here");
+    assert_eq!(diagnostics[0].line, 2);
+}
+
+
+#[test]
+fn if_without_endif_is_reported() {
+    let diagnostics = assemble_err("\
+PROG    START   0
+        IF      1
+FIRST   RSUB
+        END     FIRST");
+    assert_eq!(messages(&diagnostics), vec!["IF without ENDIF"]);
+    assert_eq!(diagnostics[0].line, 2);
+}
@@ -0,0 +1,39 @@
+mod common;
+
+use common::{assemble_ok, assemble_err, messages, text_bytes};
+
+
+#[test]
+fn floats_are_stored_as_sign_exponent_and_fraction() {
+    let output = assemble_ok("\
+FLT     START   0
+FIRST   J       FIRST
+        FLOAT   1
+        FLOAT   -1.5
+        FLOAT   0.5
+        FLOAT   0
+        FLOAT   0.0E-400
+        END     FIRST");
+    let bytes = text_bytes(&output.object[0]);
+    let floats: Vec<&[u8]> = bytes[3..].chunks(6).collect();
+    assert_eq!(floats, vec![&[0x40, 0x18, 0x00, 0x00, 0x00, 0x00][..],
+                            &[0xC0, 0x1C, 0x00, 0x00, 0x00, 0x00][..],
+                            &[0x40, 0x08, 0x00, 0x00, 0x00, 0x00][..],
+                            &[0x00, 0x00, 0x00, 0x00, 0x00, 0x00][..],
+                            &[0x00, 0x00, 0x00, 0x00, 0x00, 0x00][..]]);
+}
+
+
+#[test]
+fn floats_out_of_range_are_reported() {
+    let diagnostics = assemble_err("\
+FLT     START   0
+FIRST   J       FIRST
+        FLOAT   1E-400
+        FLOAT   -1E-309
+        FLOAT   1E308
+        END     FIRST");
+    assert_eq!(messages(&diagnostics), vec!["float 1E-400 is out of range", "float -1E-309 is out of range", "float 1E308 is out of range"]);
+    let lines: Vec<usize> = diagnostics.iter().map(|diag| diag.line).collect();
+    assert_eq!(lines, vec![3, 4, 5]);
+}
@@ -0,0 +1,78 @@
+mod common;
+
+use assembler::assemble;
+use common::{options, assemble_err, messages, text_bytes};
+use std::fs;
+use std::path::PathBuf;
+
+
+// empty directory for files of one test
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("include_test_{}_{}", std::process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+
+#[test]
+fn included_lines_are_assembled_in_place() {
+    let dir = test_dir("in_place");
+    fs::write(dir.join("data.asm"), "NUM     WORD    7\n").unwrap();
+
+    let opts = options(&dir.join("main.asm").display().to_string());
+    let output = assemble("\
+P       START   0
+FIRST   LDA     NUM
+HALT    J       HALT
+        INCLUDE \"data.asm\"   . data
+        END     FIRST", &opts).unwrap();
+    assert_eq!(text_bytes(&output.object[0]), vec![0x03, 0x20, 0x03, 0x3F, 0x2F, 0xFD, 0x00, 0x00, 0x07]);
+    assert_eq!(output.sections[0].symtab["NUM"], 6);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+
+#[test]
+fn include_paths_are_searched_and_errors_name_included_file() {
+    let dir = test_dir("paths");
+    fs::create_dir_all(dir.join("lib")).unwrap();
+    fs::write(dir.join("lib").join("bad.asm"), "        LDA     #1\n        FOO     2\n").unwrap();
+
+    let mut opts = options(&dir.join("main.asm").display().to_string());
+    opts.include_paths = vec![dir.join("lib").display().to_string()];
+    let diagnostics = match assemble("\
+P       START   0
+FIRST   RSUB
+        INCLUDE \"bad.asm\"
+        END     FIRST", &opts) {
+                        Ok(_) => panic!("error in included file isn't reported"),
+                        Err(diagnostics) => diagnostics
+                      };
+    assert!(diagnostics[0].file.ends_with("bad.asm"), "{}", diagnostics[0].file);
+    assert_eq!(diagnostics[0].line, 2);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+
+#[test]
+fn missing_include_is_reported() {
+    let diagnostics = assemble_err("\
+P       START   0
+FIRST   RSUB
+        INCLUDE \"no_such_file.asm\"
+        END     FIRST");
+    assert_eq!(messages(&diagnostics), vec!["can't find included file no_such_file.asm"]);
+    assert_eq!(diagnostics[0].line, 3);
+}
+
+
+#[test]
+fn include_cant_have_label() {
+    let diagnostics = assemble_err("\
+P       START   0
+FIRST   RSUB
+DATA    INCLUDE \"data.asm\"
+        END     FIRST");
+    assert_eq!(messages(&diagnostics), vec!["INCLUDE can't have label"]);
+}
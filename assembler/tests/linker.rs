@@ -0,0 +1,68 @@
+// &Vec parameters are used on purpose throughout the assembler
+#![allow(clippy::ptr_arg)]
+
+mod common;
+
+use assembler::linker;
+use common::assemble_ok;
+
+
+// main module calls subroutine of other module and uses its data
+const MAIN: &str = "\
+MAIN    START   0
+        EXTREF  RDREC,LEN
+FIRST   +JSUB   RDREC
+        +LDA    LEN
+HALT    J       HALT
+DIST    WORD    LEN-RDREC
+        END     FIRST";
+
+const LIB: &str = "\
+LIB     START   0
+        EXTDEF  RDREC,LEN
+RDREC   CLEAR   X
+        RSUB
+LEN     WORD    5
+        END";
+
+
+fn bytes(memory: &Vec<u8>, start: usize, count: usize) -> Vec<u8> {
+    memory[start..start + count].to_vec()
+}
+
+
+#[test]
+fn modules_are_loaded_one_after_another() {
+    let modules = vec![("main.obj".to_owned(), assemble_ok(MAIN).object), ("lib.obj".to_owned(), assemble_ok(LIB).object)];
+    let program = linker::link_modules(&modules, Some(0x1000)).unwrap();
+
+    assert_eq!(program.sections, vec![("MAIN".to_owned(), 0x1000, 0x0E), ("LIB".to_owned(), 0x100E, 0x08)]);
+    let mut estab = program.estab.clone();
+    estab.sort();
+    assert_eq!(estab, vec![("LEN".to_owned(), 0x1013), ("LIB".to_owned(), 0x100E), ("MAIN".to_owned(), 0x1000), ("RDREC".to_owned(), 0x100E)]);
+    assert_eq!(program.entry, 0x1000);
+}
+
+
+#[test]
+fn external_references_get_addresses_of_their_definitions() {
+    let modules = vec![("main.obj".to_owned(), assemble_ok(MAIN).object), ("lib.obj".to_owned(), assemble_ok(LIB).object)];
+    let program = linker::link_modules(&modules, Some(0x1000)).unwrap();
+    let memory = &program.memory;
+
+    // memory starts at load address
+    assert_eq!(bytes(memory, 0x0, 4), vec![0x4B, 0x10, 0x10, 0x0E]);
+    assert_eq!(bytes(memory, 0x4, 4), vec![0x03, 0x10, 0x10, 0x13]);
+    // difference of two external symbols doesn't depend on load address
+    assert_eq!(bytes(memory, 0xB, 3), vec![0x00, 0x00, 0x05]);
+}
+
+
+#[test]
+fn undefined_external_symbol_is_reported() {
+    let modules = vec![("main.obj".to_owned(), assemble_ok(MAIN).object)];
+    match linker::link_modules(&modules, Some(0x1000)) {
+        Ok(_) => panic!("program links without LIB"),
+        Err(msg) => assert!(msg.starts_with("main.obj, undefined external symbol"), "{}", msg)
+    }
+}
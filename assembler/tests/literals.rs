@@ -0,0 +1,40 @@
+mod common;
+
+use common::{assemble_ok, text_bytes};
+
+
+#[test]
+fn literals_are_placed_at_ltorg_and_end() {
+    let output = assemble_ok("\
+LIT     START   0
+FIRST   LDA     =X'05'
+        LDA     =C'AB'
+        J       NEXT
+        LTORG
+NEXT    LDA     =X'05'
+        LDA     =3
+HALT    J       HALT
+        END     FIRST");
+    let literals: Vec<(String, usize, usize)> = output.sections[0].literals.iter().map(|lit| (lit.name.to_owned(), lit.pool, lit.address)).collect();
+    // literal used again after LTORG gets a copy in next pool
+    assert_eq!(literals, vec![("=X'05'".to_owned(), 0, 0x09), ("=C'AB'".to_owned(), 0, 0x0A),
+                              ("=X'05'".to_owned(), 1, 0x15), ("=3".to_owned(), 1, 0x16)]);
+    assert_eq!(text_bytes(&output.object[0]),
+               vec![0x03, 0x20, 0x06, 0x03, 0x20, 0x04, 0x3F, 0x20, 0x03, 0x05, 0x41, 0x42,
+                    0x03, 0x20, 0x06, 0x03, 0x20, 0x04, 0x3F, 0x2F, 0xFD, 0x05, 0x00, 0x00, 0x03]);
+}
+
+
+#[test]
+fn same_literal_in_pool_is_stored_once() {
+    let output = assemble_ok("\
+LIT     START   0
+FIRST   LDA     =5
+        ADD     =5
+HALT    J       HALT
+        END     FIRST");
+    assert_eq!(output.sections[0].literals.len(), 1);
+    assert_eq!(output.sections[0].length, 12);
+    // both instructions address literal at 9
+    assert_eq!(text_bytes(&output.object[0]), vec![0x03, 0x20, 0x06, 0x1B, 0x20, 0x03, 0x3F, 0x2F, 0xFD, 0x00, 0x00, 0x05]);
+}
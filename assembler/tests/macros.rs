@@ -0,0 +1,84 @@
+mod common;
+
+use common::{assemble_ok, assemble_err, messages, text_bytes};
+
+
+const ADD2: &str = "\
+P       START   0
+ADD2    MACRO   &A,&B=1     . adds
+$L      LDA     &A
+        ADD     #&B
+        MEND
+";
+
+
+#[test]
+fn macro_expands_with_keyword_and_default_arguments() {
+    let output = assemble_ok(&format!("{}{}", ADD2, "\
+FIRST   ADD2    BUF,B=3
+        ADD2    BUF
+HALT    J       HALT
+BUF     WORD    1
+        END     FIRST"));
+    assert_eq!(text_bytes(&output.object[0]),
+               vec![0x03, 0x20, 0x0C, 0x19, 0x00, 0x03,
+                    0x03, 0x20, 0x06, 0x19, 0x00, 0x01,
+                    0x3F, 0x2F, 0xFD, 0x00, 0x00, 0x01]);
+    // label of call is label of first expanded line
+    assert_eq!(output.sections[0].symtab["FIRST"], 0);
+}
+
+
+#[test]
+fn dollar_labels_are_unique_for_each_expansion() {
+    let output = assemble_ok(&format!("{}{}", ADD2, "\
+FIRST   ADD2    BUF
+        ADD2    BUF
+HALT    J       HALT
+BUF     WORD    1
+        END     FIRST"));
+    let mut labels: Vec<(&String, &i32)> = output.sections[0].symtab.iter().filter(|(name, _)| name.starts_with('$')).collect();
+    labels.sort();
+    assert_eq!(labels.len(), 2);
+    assert_eq!((*labels[0].1, *labels[1].1), (0, 6));
+    assert_ne!(labels[0].0, labels[1].0);
+}
+
+
+#[test]
+fn wrong_arguments_are_reported_at_call() {
+    let diagnostics = assemble_err(&format!("{}{}", ADD2, "\
+FIRST   ADD2    BUF,C=3
+        ADD2    BUF,2,3
+HALT    J       HALT
+BUF     WORD    1
+        END     FIRST"));
+    assert_eq!(messages(&diagnostics), vec!["macro ADD2 has no parameter C", "too many arguments for macro ADD2, it takes 2"]);
+    assert_eq!((diagnostics[0].line, diagnostics[1].line), (6, 7));
+}
+
+
+#[test]
+fn macro_without_mend_is_reported() {
+    let diagnostics = assemble_err("\
+P       START   0
+LOAD    MACRO   &A
+        LDA     &A
+FIRST   RSUB
+        END     FIRST");
+    assert_eq!(messages(&diagnostics), vec!["macro LOAD is missing MEND"]);
+    assert_eq!(diagnostics[0].line, 2);
+}
+
+
+#[test]
+fn macro_calling_itself_is_reported() {
+    let diagnostics = assemble_err("\
+P       START   0
+LOOP    MACRO
+        LOOP
+        MEND
+FIRST   LOOP
+        END     FIRST");
+    assert!(messages(&diagnostics).contains(&"macro LOOP is nested too deep, does it call itself?".to_owned()), "{:?}", messages(&diagnostics));
+}
@@ -0,0 +1,57 @@
+mod common;
+
+use common::{assemble_ok, assemble_err, messages, records};
+
+
+#[test]
+fn dup_repeats_values_and_their_relocation() {
+    let output = assemble_ok("\
+D       START   0
+FIRST   J       FIRST
+        BYTE    3 DUP X'AB'
+        WORD    2 DUP (FIRST)
+        END     FIRST");
+    assert_eq!(records(&output),
+               vec!["HD     00000000000C",
+                    "T0000000C3F2FFDABABAB000000000000",
+                    "M00000606",
+                    "M00000906",
+                    "E000000"]);
+}
+
+
+#[test]
+fn fill_and_align_move_location() {
+    let output = assemble_ok("\
+D       START   0
+FIRST   J       FIRST
+        FILL    4, 0xFF
+        FILL    2
+        ALIGN   4
+NUM     WORD    1
+        BYTE    X'01'
+        ALIGN   8
+LAST    BYTE    X'02'
+        END     FIRST");
+    let symtab = &output.sections[0].symtab;
+    // ALIGN 4 skips 0A and 0B, ALIGN 8 at multiple of 8 adds nothing
+    assert_eq!((symtab["NUM"], symtab["LAST"]), (0x0C, 0x10));
+    assert_eq!(records(&output),
+               vec!["HD     000000000011",
+                    "T000000093F2FFDFFFFFFFF0000",
+                    "T00000C050000010102",
+                    "E000000"]);
+}
+
+
+#[test]
+fn counts_must_be_absolute_and_positive() {
+    let diagnostics = assemble_err("\
+D       START   0
+FIRST   J       FIRST
+        FILL    0
+        ALIGN   FIRST
+        WORD    FIRST DUP 1
+        END     FIRST");
+    assert_eq!(messages(&diagnostics), vec!["Fill count must be greater than 0", "Alignment FIRST must be absolute", "Repeat count FIRST must be absolute"]);
+}
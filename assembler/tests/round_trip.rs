@@ -0,0 +1,91 @@
+// &Vec parameters are used on purpose throughout the assembler
+#![allow(clippy::ptr_arg)]
+
+use assembler::{assemble, linker, simulator, disassembler, objwriter, AssemblyOptions};
+use assembler::objreader::{self, ObjSection};
+use std::collections::HashMap;
+
+
+// stores sum, difference, product, quotient and remainder of 45 and 7
+const ARITH: &str = include_str!("../../asm_files/arith.asm");
+
+// subroutine is called and its data used with extended format, so linked program has absolute 20 bit addresses
+const EXTENDED: &str = "\
+EXT     START   0
+FIRST   +JSUB   DOUBLE
+HALT    J       HALT
+DOUBLE  +LDA    NUM
+        ADD     NUM
+        +STA    RES
+        RSUB
+NUM     WORD    21
+RES     RESW    1
+        END     FIRST";
+
+
+fn options(name: &str) -> AssemblyOptions {
+    AssemblyOptions{source_name: name.to_owned(), ..AssemblyOptions::default()}
+}
+
+
+fn object_text(object: &Vec<ObjSection>) -> String {
+    let mut text = String::new();
+    for section in object.iter() {
+        objwriter::write_obj_section(&mut text, section);
+    }
+    text
+}
+
+
+fn word_at(memory: &Vec<u8>, address: usize) -> i32 {
+    ((memory[address] as i32) << 16) | ((memory[address + 1] as i32) << 8) | memory[address + 2] as i32
+}
+
+
+#[test]
+fn assembled_program_links_and_runs() {
+    let output = assemble(ARITH, &options("arith.asm")).unwrap();
+    assert!(output.diagnostics.is_empty());
+
+    // loaded away from its START, so every address it uses must be relocated correctly
+    let program = linker::link_modules(&vec![("arith.obj".to_owned(), output.object.clone())], Some(0x1000)).unwrap();
+    let mut machine = simulator::new_machine(&program, &HashMap::new()).unwrap();
+    simulator::run(&mut machine, Some(1000)).unwrap();
+
+    let symtab = &output.sections[0].symtab;
+    let results: Vec<i32> = ["SUM", "DIFF", "PROD", "QUOT", "MOD"].iter().map(|name| word_at(&machine.memory, 0x1000 + symtab[*name] as usize)).collect();
+    assert_eq!(results, vec![52, 38, 315, 6, 3]);
+}
+
+
+#[test]
+fn disassembled_program_assembles_to_same_object() {
+    let output = assemble(ARITH, &options("arith.asm")).unwrap();
+
+    let source = disassembler::disassemble_sections(&output.object, "arith.obj").join("\n");
+    let again = match assemble(&source, &options("arith_dis.asm")) {
+                    Ok(again) => again,
+                    Err(diagnostics) => panic!("disassembled source doesn't assemble: {:?}\n{}", diagnostics, source)
+                };
+    assert_eq!(object_text(&again.object), object_text(&output.object));
+}
+
+
+#[test]
+fn disassembled_linked_program_keeps_extended_format() {
+    let output = assemble(EXTENDED, &options("ext.asm")).unwrap();
+    let program = linker::link_modules(&vec![("ext.obj".to_owned(), output.object)], Some(0x1000)).unwrap();
+    let linked = linker::linked_obj(&program);
+
+    let source = disassembler::disassemble_sections(&objreader::read_obj(&linked, "ext.obj").unwrap(), "ext.obj").join("\n");
+    for operation in ["+JSUB", "+LDA", "+STA"] {
+        assert!(source.lines().any(|line| line.split_whitespace().any(|word| word == operation)), "{} missing in:\n{}", operation, source);
+    }
+    assert!(!source.contains("BYTE"), "instruction kept as bytes:\n{}", source);
+
+    let again = match assemble(&source, &options("ext_dis.asm")) {
+                    Ok(again) => again,
+                    Err(diagnostics) => panic!("disassembled source doesn't assemble: {:?}\n{}", diagnostics, source)
+                };
+    assert_eq!(object_text(&again.object), linked);
+}
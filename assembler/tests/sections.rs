@@ -0,0 +1,74 @@
+mod common;
+
+use common::{assemble_ok, assemble_err, messages, records};
+
+
+// main section calls subroutine of second section and uses its data through EXTREF
+const SECTIONS: &str = "\
+MAIN    START   0
+        EXTREF  RDREC,LEN
+FIRST   +JSUB   RDREC
+        +LDA    LEN
+HALT    J       HALT
+        WORD    RDREC-LEN
+RDREC   CSECT
+        EXTDEF  RDREC2,LEN
+RDREC2  CLEAR   X
+        RSUB
+LEN     WORD    5
+        END     FIRST";
+
+
+#[test]
+fn every_section_has_its_own_records() {
+    let output = assemble_ok(SECTIONS);
+    assert_eq!(output.object.len(), 2);
+    assert_eq!(records(&output),
+               vec!["HMAIN  00000000000E",
+                    "RRDREC LEN   ",
+                    "T0000000E4B100000031000003F2FFD000000",
+                    "M00000105+RDREC",
+                    "M00000505+LEN",
+                    "M00000B06+RDREC",
+                    "M00000B06-LEN",
+                    "E000000",
+                    "HRDREC 000000000008",
+                    "DRDREC2000000LEN   000005",
+                    "T00000008B4104F0000000005",
+                    "E"]);
+}
+
+
+#[test]
+fn each_section_starts_at_zero() {
+    let output = assemble_ok(SECTIONS);
+    let rdrec = &output.sections[1];
+    assert_eq!(rdrec.name, "RDREC");
+    assert_eq!(rdrec.symtab["RDREC2"], 0);
+    assert_eq!(rdrec.symtab["LEN"], 5);
+}
+
+
+#[test]
+fn extdef_must_be_defined_in_section() {
+    let diagnostics = assemble_err("\
+A       START   0
+        EXTDEF  NOPE
+FIRST   RSUB
+        END     FIRST");
+    assert_eq!(messages(&diagnostics), vec!["EXTDEF symbol NOPE is not defined in section A"]);
+    assert_eq!(diagnostics[0].line, 2);
+}
+
+
+#[test]
+fn extref_cant_be_defined_as_label() {
+    let diagnostics = assemble_err("\
+A       START   0
+        EXTREF  LOOP
+FIRST   RSUB
+LOOP    RSUB
+        END     FIRST");
+    assert_eq!(messages(&diagnostics), vec!["label LOOP is already declared in EXTREF"]);
+    assert_eq!(diagnostics[0].line, 4);
+}
@@ -0,0 +1,114 @@
+// &Vec parameters are used on purpose throughout the assembler
+#![allow(clippy::ptr_arg)]
+
+mod common;
+
+use assembler::{linker, simulator};
+use assembler::simulator::Machine;
+use common::assemble_ok;
+use std::collections::HashMap;
+use std::fs;
+
+
+fn load(source: &str, devices: &HashMap<u8, String>) -> Machine {
+    let program = linker::link_modules(&vec![("test.obj".to_owned(), assemble_ok(source).object)], None).unwrap();
+    simulator::new_machine(&program, devices).unwrap()
+}
+
+
+fn word_at(memory: &Vec<u8>, address: usize) -> i32 {
+    ((memory[address] as i32) << 16) | ((memory[address + 1] as i32) << 8) | memory[address + 2] as i32
+}
+
+
+#[test]
+fn loop_sums_numbers() {
+    // sum of 1 to 10, X counts up to T
+    let mut machine = load("\
+SUM     START   0x100
+FIRST   LDX     #0
+        LDT     #10
+        LDA     #0
+LOOP    TIXR    T
+        ADDR    X,A
+        JLT     LOOP
+        STA     RES
+HALT    J       HALT
+RES     RESW    1
+        END     FIRST", &HashMap::new());
+    let steps = simulator::run(&mut machine, Some(1000)).unwrap();
+    assert_eq!(steps, 3 + 3 * 10 + 2);
+    assert_eq!(word_at(&machine.memory, 0x116), 55);
+    assert_eq!(machine.regs[0], 55);
+    assert_eq!(machine.regs[1], 10);
+    assert!(simulator::registers(&machine).contains(&"A  000037 (55)".to_owned()));
+}
+
+
+#[test]
+fn subroutine_returns_and_negative_results_wrap() {
+    let mut machine = load("\
+NEG     START   0
+FIRST   JSUB    CALC
+        STA     RES
+HALT    J       HALT
+CALC    LDA     #3
+        SUB     #5
+        RSUB
+RES     RESW    1
+        END     FIRST", &HashMap::new());
+    simulator::run(&mut machine, Some(100)).unwrap();
+    assert_eq!(word_at(&machine.memory, 0x12), 0xFFFFFE);
+}
+
+
+#[test]
+fn float_arithmetic() {
+    let mut machine = load("\
+FLT     START   0
+FIRST   LDF     HALF
+        MULF    THREE
+        STF     RES
+HALT    J       HALT
+HALF    FLOAT   0.5
+THREE   FLOAT   3
+RES     RESF    1
+        END     FIRST", &HashMap::new());
+    simulator::run(&mut machine, Some(100)).unwrap();
+    assert_eq!(machine.f, 1.5);
+    assert_eq!(simulator::float_from_bytes(&machine.memory[0x18..0x1E]), 1.5);
+}
+
+
+#[test]
+fn written_bytes_go_to_device_file() {
+    let path = std::env::temp_dir().join(format!("simulator_test_{}.dev", std::process::id()));
+    let mut devices: HashMap<u8, String> = HashMap::new();
+    devices.insert(0x05, path.display().to_string());
+
+    let mut machine = load("\
+OUT     START   0
+FIRST   LDX     #0
+LOOP    LDCH    TEXT,X
+        WD      DEV
+        TIX     #2
+        JLT     LOOP
+HALT    J       HALT
+TEXT    BYTE    C'OK'
+DEV     BYTE    X'05'
+        END     FIRST", &devices);
+    simulator::run(&mut machine, Some(100)).unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "OK");
+    fs::remove_file(&path).unwrap();
+}
+
+
+#[test]
+fn program_that_doesnt_halt_is_stopped() {
+    let mut machine = load("\
+LOOP    START   0
+FIRST   J       NEXT
+NEXT    J       FIRST
+        END     FIRST", &HashMap::new());
+    assert_eq!(simulator::run(&mut machine, Some(50)), Err("Program didn't halt after 50 instructions".to_owned()));
+}
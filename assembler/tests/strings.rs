@@ -0,0 +1,41 @@
+mod common;
+
+use common::{assemble_ok, assemble_err, messages, text_bytes};
+
+
+#[test]
+fn asciz_ends_with_zero_and_ascic_starts_with_length() {
+    let output = assemble_ok("\
+S       START   0
+FIRST   J       FIRST
+LINE    ASCIZ   C'HI\\n'
+QUOTE   ASCIC   C'IT''S'
+        END     FIRST");
+    assert_eq!(text_bytes(&output.object[0])[3..], [0x48, 0x49, 0x0A, 0x00, 0x04, 0x49, 0x54, 0x27, 0x53]);
+    let symtab = &output.sections[0].symtab;
+    assert_eq!((symtab["LINE"], symtab["QUOTE"]), (3, 7));
+    assert_eq!(output.sections[0].length, 12);
+}
+
+
+#[test]
+fn ascic_length_must_fit_in_byte() {
+    let diagnostics = assemble_err(&format!("\
+S       START   0
+FIRST   J       FIRST
+        ASCIC   C'{}'
+        END     FIRST", "A".repeat(256)));
+    assert_eq!(messages(&diagnostics), vec!["ASCIC string has 256 characters, but its length byte can hold at most 255"]);
+    assert_eq!(diagnostics[0].line, 3);
+}
+
+
+#[test]
+fn strings_need_char_constant() {
+    let diagnostics = assemble_err("\
+S       START   0
+FIRST   J       FIRST
+        ASCIZ   X'41'
+        END     FIRST");
+    assert_eq!(messages(&diagnostics), vec!["ASCIZ needs char constant"]);
+}